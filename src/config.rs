@@ -0,0 +1,684 @@
+// Layered configuration
+//
+// Settings are merged from, lowest precedence first: built-in defaults, the
+// user file (`$XDG_CONFIG_HOME/gemini-mcp/config.toml`), the project file
+// (`gemini-mcp.toml` or `.gemini-mcp.toml` in the working directory or a
+// parent), environment variables and command-line flags. Every field is
+// optional so a layer only overrides what it sets.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::error::{GeminiError, Result};
+use crate::gemini::client::{CONTINUATIONS_ENV, MAX_CONTINUATIONS};
+use crate::gemini::types::HarmCategory;
+use crate::gemini::{fallback, models, ratelimit, safety};
+use crate::tools::personas::TOOL_DEFAULTS;
+use crate::tools::prompts;
+use crate::tools::settings::TOOL_NAMES;
+
+/// Project file names, looked up from the working directory upwards
+pub const PROJECT_FILES: &[&str] = &["gemini-mcp.toml", ".gemini-mcp.toml"];
+/// Comma-separated MCP tool names to expose
+pub const TOOLS_ENV: &str = "GEMINI_TOOLS";
+/// Log level or filter directive, e.g. `debug` or `gemini_mcp=trace`
+pub const LOG_LEVEL_ENV: &str = "GEMINI_LOG_LEVEL";
+/// Seconds to wait for in-flight tool calls on shutdown
+pub const SHUTDOWN_TIMEOUT_ENV: &str = "GEMINI_SHUTDOWN_TIMEOUT";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub client: ClientConfig,
+    pub models: ModelsConfig,
+    pub limits: LimitsConfig,
+    pub safety: SafetyConfig,
+    pub tools: ToolsConfig,
+    pub templates: TemplatesConfig,
+    pub logging: LoggingConfig,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Seconds shutdown waits for in-flight tool calls
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shutdown_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool_idle_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Continuation requests after a MAX_TOKENS stop
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_continuations: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelsConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pro: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flash: Option<String>,
+    /// Extra aliases, alias -> model id
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, String>,
+    /// Chains such as `pro -> flash -> gemini-2.5-flash-lite`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallbacks: Option<Vec<String>>,
+    /// Error classes that trigger a fallback
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback_on: Option<Vec<String>>,
+    /// Model list cache file; empty disables the cache
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Requests per minute per model; 0 means unlimited
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rpm: Option<u32>,
+    /// Input tokens per minute per model; 0 means unlimited
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tpm: Option<u32>,
+    /// Limits for single models, by id or alias
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub models: BTreeMap<String, ModelLimits>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelLimits {
+    pub rpm: u32,
+    pub tpm: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SafetyConfig {
+    /// Threshold for every category, e.g. `block_only_high`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<String>,
+    /// Thresholds for single categories, e.g. `hate_speech = "block_none"`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub categories: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToolsConfig {
+    /// MCP tool names to expose; gemini-status is always available
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<Vec<String>>,
+    /// Defaults per tool id (query, search, analyze, ...)
+    #[serde(flatten)]
+    pub defaults: BTreeMap<String, ToolConfig>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToolConfig {
+    /// Model id or alias used when the call names none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persona: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TemplatesConfig {
+    /// Directory with `<id>.hbs` files overriding the embedded prompts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<String>,
+}
+
+/// Built-in model and generation defaults per tool id
+const TOOL_BUILTINS: &[(&str, &str, Option<f64>, Option<u32>)] = &[
+    ("query", models::PRO_ALIAS, None, None),
+    ("search", models::PRO_ALIAS, Some(0.3), Some(2048)),
+    ("analyze", models::PRO_ALIAS, None, None),
+    ("analyze-code", models::PRO_ALIAS, None, None),
+    // max_tokens depends on the requested length unless configured
+    ("summarize", models::FLASH_ALIAS, Some(0.4), None),
+    ("brainstorm", models::PRO_ALIAS, Some(0.9), Some(2048)),
+    ("agent", models::PRO_ALIAS, Some(0.2), Some(4096)),
+];
+
+fn set<T>(target: &mut Option<T>, value: Option<T>) {
+    if value.is_some() {
+        *target = value;
+    }
+}
+
+impl Config {
+    pub fn builtin() -> Self {
+        Self {
+            server: ServerConfig {
+                shutdown_timeout_secs: Some(30),
+            },
+            client: ClientConfig {
+                base_url: Some(crate::gemini::client::BASE_URL.to_string()),
+                timeout_secs: Some(60),
+                pool_idle_timeout_secs: Some(90),
+                pool_max_idle_per_host: Some(10),
+                max_continuations: Some(0),
+            },
+            models: ModelsConfig {
+                pro: Some(models::DEFAULT_PRO_MODEL.to_string()),
+                flash: Some(models::DEFAULT_FLASH_MODEL.to_string()),
+                aliases: BTreeMap::new(),
+                fallbacks: Some(Vec::new()),
+                fallback_on: Some(vec!["rate_limited".to_string(), "overloaded".to_string()]),
+                cache: None,
+            },
+            limits: LimitsConfig {
+                rpm: Some(0),
+                tpm: Some(0),
+                models: BTreeMap::new(),
+            },
+            // The API's own thresholds apply unless configured
+            safety: SafetyConfig::default(),
+            tools: ToolsConfig {
+                enabled: Some(TOOL_NAMES.iter().map(|name| name.to_string()).collect()),
+                defaults: TOOL_BUILTINS
+                    .iter()
+                    .map(|(tool, model, temperature, max_tokens)| {
+                        let config = ToolConfig {
+                            model: Some(model.to_string()),
+                            temperature: *temperature,
+                            max_tokens: *max_tokens,
+                            ..Default::default()
+                        };
+                        (tool.to_string(), config)
+                    })
+                    .collect(),
+            },
+            templates: TemplatesConfig::default(),
+            logging: LoggingConfig {
+                level: Some("info".to_string()),
+            },
+        }
+    }
+
+    /// Built-in defaults overridden by the environment, without config files
+    pub fn from_env() -> Result<Self> {
+        let mut config = Self::builtin();
+        config.merge(Self::from_lookup(|key| std::env::var(key).ok())?);
+        Ok(config)
+    }
+
+    /// The environment layer: only variables that are set become values
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let list = |key: &str, separator: char| {
+            lookup(key).map(|spec| {
+                spec.split(separator)
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+        };
+        let number = |key: &str| -> Result<Option<u32>> {
+            lookup(key)
+                .map(|value| {
+                    value.trim().parse::<u32>().map_err(|_| {
+                        GeminiError::ConfigError(format!("Invalid {} '{}': expected a number", key, value))
+                    })
+                })
+                .transpose()
+        };
+
+        let mut config = Self::default();
+
+        config.server.shutdown_timeout_secs = number(SHUTDOWN_TIMEOUT_ENV)?.map(u64::from);
+        config.client.max_continuations = number(CONTINUATIONS_ENV)?;
+
+        config.models.pro = lookup("GEMINI_PRO_MODEL");
+        config.models.flash = lookup("GEMINI_FLASH_MODEL");
+        for pair in list(models::ALIASES_ENV, ',').unwrap_or_default() {
+            let (alias, id) = pair
+                .split_once('=')
+                .filter(|(alias, id)| !alias.trim().is_empty() && !id.trim().is_empty())
+                .ok_or_else(|| {
+                    GeminiError::ConfigError(format!(
+                        "Invalid {} entry '{}': expected alias=model",
+                        models::ALIASES_ENV,
+                        pair
+                    ))
+                })?;
+            config.models.aliases.insert(alias.trim().to_string(), id.trim().to_string());
+        }
+        config.models.fallbacks = list(fallback::CHAINS_ENV, ';');
+        config.models.fallback_on = list(fallback::ON_ENV, ',');
+        fallback::FallbackPolicy::parse(
+            config.models.fallbacks.as_deref().unwrap_or_default(),
+            config.models.fallback_on.as_deref(),
+            (fallback::CHAINS_ENV, fallback::ON_ENV),
+        )?;
+        config.models.cache = lookup(models::CACHE_ENV);
+
+        config.limits.rpm = number(ratelimit::RPM_ENV)?;
+        config.limits.tpm = number(ratelimit::TPM_ENV)?;
+        if let Some(spec) = lookup(ratelimit::LIMITS_ENV) {
+            for (model, limit) in ratelimit::parse_model_limits(&spec)? {
+                let limits = ModelLimits {
+                    rpm: limit.rpm.unwrap_or(0),
+                    tpm: limit.tpm.unwrap_or(0),
+                };
+                config.limits.models.insert(model, limits);
+            }
+        }
+
+        // Thresholds are checked here so errors name the variable
+        if let Some(value) = lookup(safety::THRESHOLD_ENV) {
+            safety::parse_threshold(safety::THRESHOLD_ENV, &value)?;
+            config.safety.threshold = Some(value);
+        }
+        for category in HarmCategory::ALL {
+            let key = safety::category_env(category);
+            if let Some(value) = lookup(&key) {
+                safety::parse_threshold(&key, &value)?;
+                config.safety.categories.insert(safety::category_name(category), value);
+            }
+        }
+
+        config.tools.enabled = list(TOOLS_ENV, ',');
+        for (tool, _) in TOOL_DEFAULTS {
+            let suffix = tool.to_uppercase().replace('-', "_");
+            let tool_config = ToolConfig {
+                persona: lookup(&format!("GEMINI_PERSONA_{}", suffix)),
+                system: lookup(&format!("GEMINI_SYSTEM_{}", suffix)),
+                ..Default::default()
+            };
+            if tool_config != ToolConfig::default() {
+                config.tools.defaults.insert(tool.to_string(), tool_config);
+            }
+        }
+
+        config.templates.dir = lookup(prompts::DIR_ENV);
+        config.logging.level = lookup(LOG_LEVEL_ENV);
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            GeminiError::ConfigError(format!("Cannot read config file {}: {}", path.display(), e))
+        })?;
+        toml::from_str(&text).map_err(|e| {
+            GeminiError::ConfigError(format!("Invalid config file {}: {}", path.display(), e))
+        })
+    }
+
+    /// Applies the values set in `layer` on top of this config
+    pub fn merge(&mut self, layer: Config) {
+        set(&mut self.server.shutdown_timeout_secs, layer.server.shutdown_timeout_secs);
+
+        let client = layer.client;
+        set(&mut self.client.base_url, client.base_url);
+        set(&mut self.client.timeout_secs, client.timeout_secs);
+        set(&mut self.client.pool_idle_timeout_secs, client.pool_idle_timeout_secs);
+        set(&mut self.client.pool_max_idle_per_host, client.pool_max_idle_per_host);
+        set(&mut self.client.max_continuations, client.max_continuations);
+
+        let models = layer.models;
+        set(&mut self.models.pro, models.pro);
+        set(&mut self.models.flash, models.flash);
+        self.models.aliases.extend(models.aliases);
+        set(&mut self.models.fallbacks, models.fallbacks);
+        set(&mut self.models.fallback_on, models.fallback_on);
+        set(&mut self.models.cache, models.cache);
+
+        set(&mut self.limits.rpm, layer.limits.rpm);
+        set(&mut self.limits.tpm, layer.limits.tpm);
+        self.limits.models.extend(layer.limits.models);
+
+        set(&mut self.safety.threshold, layer.safety.threshold);
+        self.safety.categories.extend(layer.safety.categories);
+
+        set(&mut self.tools.enabled, layer.tools.enabled);
+        for (tool, defaults) in layer.tools.defaults {
+            let target = self.tools.defaults.entry(tool).or_default();
+            set(&mut target.model, defaults.model);
+            set(&mut target.persona, defaults.persona);
+            set(&mut target.system, defaults.system);
+            set(&mut target.temperature, defaults.temperature);
+            set(&mut target.max_tokens, defaults.max_tokens);
+        }
+
+        set(&mut self.templates.dir, layer.templates.dir);
+        set(&mut self.logging.level, layer.logging.level);
+    }
+
+    /// Checks values that have no other consumer to catch them early
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |what: String| Err(GeminiError::ConfigError(what));
+
+        if self.client.timeout_secs == Some(0) {
+            return invalid("Invalid client.timeout_secs 0: expected at least 1".to_string());
+        }
+        if let Some(n) = self.client.max_continuations.filter(|n| *n > MAX_CONTINUATIONS) {
+            return invalid(format!(
+                "Invalid client.max_continuations {}: expected 0-{}",
+                n, MAX_CONTINUATIONS
+            ));
+        }
+        safety::settings_from_config(&self.safety)?;
+        fallback::FallbackPolicy::new(
+            self.models.fallbacks.as_deref().unwrap_or_default(),
+            self.models.fallback_on.as_deref(),
+        )?;
+        if self.limits.models.keys().any(|model| model.trim().is_empty()) {
+            return invalid("Invalid limits.models entry: the model name is empty".to_string());
+        }
+
+        for name in self.tools.enabled.iter().flatten() {
+            if !TOOL_NAMES.contains(&name.as_str()) && name != "gemini-status" {
+                return invalid(format!(
+                    "Invalid tools.enabled entry '{}'. Available: {}",
+                    name,
+                    TOOL_NAMES.join(", ")
+                ));
+            }
+        }
+        for (tool, defaults) in &self.tools.defaults {
+            if !TOOL_DEFAULTS.iter().any(|(id, _)| id == tool) {
+                let ids: Vec<&str> = TOOL_DEFAULTS.iter().map(|(id, _)| *id).collect();
+                return invalid(format!("Unknown tool '[tools.{}]'. Available: {}", tool, ids.join(", ")));
+            }
+            if let Some(t) = defaults.temperature.filter(|t| !(0.0..=2.0).contains(t)) {
+                return invalid(format!("Invalid tools.{}.temperature {}: expected 0-2", tool, t));
+            }
+            if defaults.max_tokens == Some(0) {
+                return invalid(format!("Invalid tools.{}.max_tokens 0: expected at least 1", tool));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+/// `$XDG_CONFIG_HOME/gemini-mcp/config.toml`, else `~/.config/gemini-mcp/config.toml`
+pub fn user_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("gemini-mcp").join("config.toml"))
+}
+
+/// The nearest project file in `dir` or one of its parents
+pub fn project_path(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .flat_map(|dir| PROJECT_FILES.iter().map(move |name| dir.join(name)))
+        .find(|path| path.is_file())
+}
+
+/// The merged configuration and the files it was read from
+#[derive(Debug, Clone)]
+pub struct Loaded {
+    pub config: Config,
+    pub files: Vec<PathBuf>,
+}
+
+/// Merges all layers. `project` replaces project file discovery; `cli`
+/// holds the values given on the command line.
+pub fn load(project: Option<&Path>, cli: Config) -> Result<Loaded> {
+    let project = match project {
+        Some(path) => Some(path.to_path_buf()),
+        None => std::env::current_dir().ok().and_then(|dir| project_path(&dir)),
+    };
+    load_layers(user_path().as_deref(), project.as_deref(), cli)
+}
+
+/// Merges the given files with the environment and `cli`. A missing user
+/// file is skipped; a missing project file is an error.
+pub fn load_layers(user: Option<&Path>, project: Option<&Path>, cli: Config) -> Result<Loaded> {
+    let mut config = Config::builtin();
+    let mut files = Vec::new();
+
+    if let Some(path) = user.filter(|p| p.is_file()) {
+        config.merge(Config::from_file(path)?);
+        files.push(path.to_path_buf());
+    }
+    if let Some(path) = project {
+        config.merge(Config::from_file(path)?);
+        files.push(path.to_path_buf());
+    }
+
+    config.merge(Config::from_lookup(|key| std::env::var(key).ok())?);
+    config.merge(cli);
+    config.validate()?;
+
+    Ok(Loaded { config, files })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{env, temp_dir};
+
+    fn parse(text: &str) -> Result<Config> {
+        toml::from_str(text).map_err(|e| GeminiError::ConfigError(e.to_string()))
+    }
+
+    #[test]
+    fn test_layers_override_in_order() {
+        let user = parse(
+            r#"
+            [server]
+            shutdown_timeout_secs = 10
+
+            [client]
+            timeout_secs = 120
+
+            [models.aliases]
+            lite = "gemini-2.5-flash-lite"
+
+            [tools.brainstorm]
+            temperature = 1.2
+            max_tokens = 4096
+            "#,
+        )
+        .unwrap();
+        let project = parse(
+            r#"
+            [tools]
+            enabled = ["gemini-search-v2", "gemini-brainstorm-v2"]
+
+            [tools.brainstorm]
+            temperature = 0.7
+            "#,
+        )
+        .unwrap();
+        let environment = Config::from_lookup(env(&[
+            ("GEMINI_PERSONA_BRAINSTORM", "analyst"),
+            (TOOLS_ENV, "gemini-brainstorm-v2"),
+            (SHUTDOWN_TIMEOUT_ENV, "20"),
+        ]))
+        .unwrap();
+        let cli = Config {
+            server: ServerConfig {
+                shutdown_timeout_secs: Some(5),
+            },
+            logging: LoggingConfig {
+                level: Some("debug".to_string()),
+            },
+            ..Default::default()
+        };
+
+        let mut config = Config::builtin();
+        for layer in [user, project, environment, cli] {
+            config.merge(layer);
+        }
+        config.validate().unwrap();
+
+        assert_eq!(config.server.shutdown_timeout_secs, Some(5));
+        assert_eq!(config.client.timeout_secs, Some(120));
+        assert_eq!(config.client.pool_max_idle_per_host, Some(10));
+        assert_eq!(config.models.aliases["lite"], "gemini-2.5-flash-lite");
+        assert_eq!(config.tools.enabled, Some(vec!["gemini-brainstorm-v2".to_string()]));
+        assert_eq!(config.logging.level.as_deref(), Some("debug"));
+
+        let brainstorm = &config.tools.defaults["brainstorm"];
+        assert_eq!(brainstorm.temperature, Some(0.7));
+        assert_eq!(brainstorm.max_tokens, Some(4096));
+        assert_eq!(brainstorm.persona.as_deref(), Some("analyst"));
+        assert_eq!(brainstorm.model.as_deref(), Some("pro"));
+    }
+
+    #[test]
+    fn test_env_layer_only_sets_present_values() {
+        assert_eq!(Config::from_lookup(env(&[])).unwrap(), Config::default());
+
+        let config = Config::from_lookup(env(&[
+            (ratelimit::LIMITS_ENV, "flash=10/0"),
+            (fallback::CHAINS_ENV, "pro->flash; flash->lite"),
+        ]))
+        .unwrap();
+        assert_eq!(config.limits.models["flash"], ModelLimits { rpm: 10, tpm: 0 });
+        assert_eq!(config.models.fallbacks.unwrap().len(), 2);
+
+        assert!(Config::from_lookup(env(&[(ratelimit::RPM_ENV, "many")])).is_err());
+        assert!(Config::from_lookup(env(&[(ratelimit::TPM_ENV, "lots")])).is_err());
+        assert!(Config::from_lookup(env(&[(ratelimit::LIMITS_ENV, "pro=5")])).is_err());
+        assert!(Config::from_lookup(env(&[(models::ALIASES_ENV, "lite")])).is_err());
+
+        // Errors name the variable that held the bad value
+        let error = |pairs: &[(&str, &str)]| Config::from_lookup(env(pairs)).unwrap_err().to_string();
+        assert!(error(&[(CONTINUATIONS_ENV, "many")]).contains(CONTINUATIONS_ENV));
+        assert!(error(&[("GEMINI_SAFETY_HATE_SPEECH", "sometimes")]).contains("GEMINI_SAFETY_HATE_SPEECH"));
+        assert!(error(&[(fallback::CHAINS_ENV, "pro")]).contains(fallback::CHAINS_ENV));
+    }
+
+    #[test]
+    fn test_env_layer_configures_the_client() {
+        let mut config = Config::builtin();
+        config.merge(
+            Config::from_lookup(env(&[
+                (ratelimit::RPM_ENV, "10"),
+                (ratelimit::LIMITS_ENV, "gemini-2.5-flash-lite = 15/0; gemini-3-pro-preview=5/250000"),
+                (fallback::CHAINS_ENV, "pro -> flash; flash->gemini-2.5-flash-lite"),
+                (fallback::ON_ENV, "server_error"),
+                (models::ALIASES_ENV, "lite = gemini-2.5-flash-lite"),
+                (models::CACHE_ENV, ""),
+                (safety::THRESHOLD_ENV, "block_only_high"),
+                ("GEMINI_SAFETY_HATE_SPEECH", "off"),
+                (SHUTDOWN_TIMEOUT_ENV, "5"),
+            ]))
+            .unwrap(),
+        );
+
+        assert_eq!(config.server.shutdown_timeout_secs, Some(5));
+        assert_eq!((config.limits.rpm, config.limits.tpm), (Some(10), Some(0)));
+        assert_eq!(config.limits.models["gemini-2.5-flash-lite"], ModelLimits { rpm: 15, tpm: 0 });
+        assert_eq!(
+            config.limits.models["gemini-3-pro-preview"],
+            ModelLimits { rpm: 5, tpm: 250_000 }
+        );
+        assert_eq!(config.models.fallback_on, Some(vec!["server_error".to_string()]));
+        assert_eq!(config.models.aliases["lite"], "gemini-2.5-flash-lite");
+        assert_eq!(config.models.cache.as_deref(), Some(""));
+        assert_eq!(config.safety.threshold.as_deref(), Some("block_only_high"));
+        assert_eq!(config.safety.categories["hate_speech"], "off");
+        assert_eq!(safety::settings_from_config(&config.safety).unwrap().len(), HarmCategory::ALL.len());
+
+        let policy = fallback::FallbackPolicy::new(
+            config.models.fallbacks.as_deref().unwrap(),
+            config.models.fallback_on.as_deref(),
+        )
+        .unwrap();
+        let resolve = |name: &str| Some(name.to_string());
+        assert_eq!(policy.fallbacks_for("pro", resolve), ["flash"]);
+        assert!(policy.applies_to(&GeminiError::ApiError {
+            status: 500,
+            message: String::new(),
+        }));
+    }
+
+    #[test]
+    fn test_invalid_files_are_rejected() {
+        assert!(parse("[client]\ntimeout = 5").is_err());
+        assert!(parse("[tools.brainstorm]\ntemp = 5").is_err());
+
+        let mut config = Config::builtin();
+        config.merge(parse("[tools.brainstorm]\ntemperature = 5.0").unwrap());
+        assert!(config.validate().unwrap_err().to_string().contains("temperature"));
+
+        let unknown = parse("[tools.poetry]\ntemperature = 1.0").unwrap();
+        assert!(unknown.validate().unwrap_err().to_string().contains("poetry"));
+
+        let disabled = parse("[tools]\nenabled = [\"gemini-teapot\"]").unwrap();
+        assert!(disabled.validate().is_err());
+
+        // Errors name the config key, not the environment variable
+        let safety = parse("[safety.categories]\nhate_speech = \"sometimes\"").unwrap();
+        assert!(safety.validate().unwrap_err().to_string().contains("safety.categories.hate_speech"));
+        let continuations = parse("[client]\nmax_continuations = 99").unwrap();
+        assert!(continuations.validate().unwrap_err().to_string().contains("client.max_continuations 99"));
+        let fallbacks = parse("[models]\nfallbacks = [\"pro\"]").unwrap();
+        assert!(fallbacks.validate().unwrap_err().to_string().contains("models.fallbacks chain 'pro'"));
+        let fallback_on = parse("[models]\nfallback_on = [\"sometimes\"]").unwrap();
+        assert!(fallback_on.validate().unwrap_err().to_string().contains("models.fallback_on"));
+        let limits = parse("[limits.models]\n\" \" = { rpm = 1, tpm = 0 }").unwrap();
+        assert!(limits.validate().unwrap_err().to_string().contains("limits.models"));
+
+        // An environment value out of range fails validation after merging
+        let mut config = Config::builtin();
+        config.merge(Config::from_lookup(env(&[(CONTINUATIONS_ENV, "99")])).unwrap());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_builtin_round_trips_through_toml() {
+        let config = Config::builtin();
+        let text = config.to_toml();
+        assert!(text.contains("[tools.brainstorm]"));
+        assert_eq!(parse(&text).unwrap(), config);
+    }
+
+    #[test]
+    fn test_project_file_is_found_in_parents() {
+        let root = temp_dir("config");
+        let nested = root.join("a").join("b");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(root.join(".gemini-mcp.toml"), "[client]\ntimeout_secs = 5\n").unwrap();
+
+        let path = project_path(&nested).unwrap();
+        assert_eq!(path, root.join(".gemini-mcp.toml"));
+        assert_eq!(Config::from_file(&path).unwrap().client.timeout_secs, Some(5));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,5 +1,8 @@
 use thiserror::Error;
 
+use crate::gemini::safety::{self, BlockTarget};
+use crate::gemini::types::SafetyRating;
+
 #[derive(Error, Debug)]
 pub enum GeminiError {
     #[error("HTTP client error: {0}")]
@@ -14,6 +17,29 @@ pub enum GeminiError {
     #[error("Empty response from API")]
     EmptyResponse,
 
+    #[error(
+        "Response hit max_output_tokens before producing any text ({continuations} continuation(s)); \
+         raise max_tokens or lower thinking_budget"
+    )]
+    Truncated { continuations: u32 },
+
+    #[error("{target} blocked by safety filters ({reason}){}", safety::describe(.ratings))]
+    Blocked {
+        target: BlockTarget,
+        reason: String,
+        ratings: Vec<SafetyRating>,
+    },
+
+    #[error(
+        "Input is {tokens} tokens but {model} accepts at most {limit}; split it into at least {} \
+         chunks and process them separately (e.g. summarize each chunk, then summarize the summaries)",
+        chunks_needed(.tokens, .limit)
+    )]
+    InputTooLarge { model: String, tokens: u32, limit: u32 },
+
+    #[error("{0}")]
+    InvalidModel(String),
+
     #[error("Authentication error: {0}")]
     AuthError(String),
 
@@ -21,4 +47,8 @@ pub enum GeminiError {
     ConfigError(String),
 }
 
+fn chunks_needed(tokens: &u32, limit: &u32) -> u32 {
+    tokens.div_ceil((*limit).max(1))
+}
+
 pub type Result<T> = std::result::Result<T, GeminiError>;
@@ -0,0 +1,707 @@
+// Golden-case evaluation of tool output
+//
+// A suite is a set of TOML case files. Each case names a V2 tool, gives its
+// input in the same shape as the MCP arguments and lists properties the
+// response must have, such as "mentions SQL injection" or
+// "result.quality_score < 5". Cases run in order against any
+// `GenerationBackend`, including a client replaying a cassette. The report
+// gives the pass rate and, compared with a baseline run, which cases and
+// checks changed between prompt template versions.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::gemini::backend::GenerationBackend;
+use crate::tools::sandbox::Sandbox;
+use crate::tools::settings::ToolSettings;
+use crate::tools::{agent, analyze, brainstorm, query, summarize};
+
+/// Tools a case may name, as in `gemini-mcp run`
+pub const TOOLS: &[&str] = &["search", "analyze", "summarize", "brainstorm", "agent"];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Suite {
+    #[serde(default, rename = "case")]
+    pub cases: Vec<Case>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Case {
+    pub name: String,
+    pub tool: String,
+    /// Tool arguments, as sent to the MCP tool
+    pub input: Value,
+    #[serde(default, rename = "expect")]
+    pub expectations: Vec<Expectation>,
+}
+
+/// A property of a tool response. `mentions` and `not_mentions` search the
+/// strings in `result`, ignoring case. The comparisons apply to the value
+/// at `path`, a dotted path from the response root such as
+/// `result.ideas.0.text`; all comparisons given must hold.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expectation {
+    pub mentions: Option<String>,
+    pub not_mentions: Option<String>,
+    pub path: Option<String>,
+    pub equals: Option<Value>,
+    pub lt: Option<f64>,
+    pub le: Option<f64>,
+    pub gt: Option<f64>,
+    pub ge: Option<f64>,
+    /// Minimum number of items or characters
+    pub min_len: Option<usize>,
+    pub max_len: Option<usize>,
+    /// Regular expression a string must match
+    pub matches: Option<String>,
+}
+
+impl Suite {
+    /// Loads case files, or every `*.toml` file of directories, in order
+    pub fn load(paths: &[impl AsRef<Path>]) -> anyhow::Result<Self> {
+        let mut suite = Suite::default();
+        for path in paths {
+            let path = path.as_ref();
+            let files = if path.is_dir() {
+                let mut files: Vec<_> = std::fs::read_dir(path)
+                    .map_err(|e| anyhow::anyhow!("Cannot read {}: {}", path.display(), e))?
+                    .filter_map(|entry| entry.ok().map(|e| e.path()))
+                    .filter(|file| file.extension().is_some_and(|ext| ext == "toml"))
+                    .collect();
+                files.sort();
+                files
+            } else {
+                vec![path.to_path_buf()]
+            };
+            for file in files {
+                let text = std::fs::read_to_string(&file)
+                    .map_err(|e| anyhow::anyhow!("Cannot read {}: {}", file.display(), e))?;
+                let parsed = Self::parse(&text).map_err(|e| anyhow::anyhow!("{}: {}", file.display(), e))?;
+                suite.cases.extend(parsed.cases);
+            }
+        }
+        suite.validate()?;
+        Ok(suite)
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let suite: Suite = toml::from_str(text)?;
+        suite.validate()?;
+        Ok(suite)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut names = BTreeSet::new();
+        for case in &self.cases {
+            if !names.insert(case.name.as_str()) {
+                anyhow::bail!("Duplicate case name '{}'", case.name);
+            }
+            if !TOOLS.contains(&case.tool.as_str()) {
+                anyhow::bail!("Case '{}': unknown tool '{}'; expected one of {}", case.name, case.tool, TOOLS.join(", "));
+            }
+            check_input(&case.tool, &case.input)
+                .map_err(|e| anyhow::anyhow!("Case '{}': invalid {} input: {}", case.name, case.tool, e))?;
+            if case.expectations.is_empty() {
+                anyhow::bail!("Case '{}' has no expectations", case.name);
+            }
+            for expectation in &case.expectations {
+                expectation
+                    .validate()
+                    .map_err(|e| anyhow::anyhow!("Case '{}': {}", case.name, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `input` deserializes into the tool's input type
+fn check_input(tool: &str, input: &Value) -> serde_json::Result<()> {
+    let input = input.clone();
+    match tool {
+        "search" => serde_json::from_value::<query::SearchInput>(input).map(drop),
+        "analyze" => serde_json::from_value::<analyze::AnalyzeInput>(input).map(drop),
+        "summarize" => serde_json::from_value::<summarize::SummarizeInput>(input).map(drop),
+        "brainstorm" => serde_json::from_value::<brainstorm::BrainstormInput>(input).map(drop),
+        _ => serde_json::from_value::<agent::AgentInput>(input).map(drop),
+    }
+}
+
+impl Expectation {
+    fn has_comparison(&self) -> bool {
+        self.equals.is_some()
+            || self.lt.is_some()
+            || self.le.is_some()
+            || self.gt.is_some()
+            || self.ge.is_some()
+            || self.min_len.is_some()
+            || self.max_len.is_some()
+            || self.matches.is_some()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let searches = self.mentions.is_some() as u8 + self.not_mentions.is_some() as u8;
+        match (searches, &self.path, self.has_comparison()) {
+            (1, None, false) => {}
+            (0, Some(_), true) => {}
+            (0, Some(path), false) => return Err(format!("expectation on '{}' has no comparison", path)),
+            (0, None, true) => return Err("comparisons need a path".to_string()),
+            (0, None, false) => return Err("empty expectation".to_string()),
+            _ => return Err("use one of mentions, not_mentions or path per expectation".to_string()),
+        }
+        if let Some(pattern) = &self.matches {
+            Regex::new(pattern).map_err(|e| format!("invalid pattern '{}': {}", pattern, e))?;
+        }
+        Ok(())
+    }
+
+    /// Readable form, e.g. `result.quality_score < 5`
+    pub fn describe(&self) -> String {
+        if let Some(text) = &self.mentions {
+            return format!("mentions \"{}\"", text);
+        }
+        if let Some(text) = &self.not_mentions {
+            return format!("does not mention \"{}\"", text);
+        }
+        let mut parts = Vec::new();
+        if let Some(value) = &self.equals {
+            parts.push(format!("= {}", value));
+        }
+        for (op, bound) in [("<", self.lt), ("<=", self.le), (">", self.gt), (">=", self.ge)] {
+            if let Some(bound) = bound {
+                parts.push(format!("{} {}", op, bound));
+            }
+        }
+        if let Some(n) = self.min_len {
+            parts.push(format!("has at least {} items", n));
+        }
+        if let Some(n) = self.max_len {
+            parts.push(format!("has at most {} items", n));
+        }
+        if let Some(pattern) = &self.matches {
+            parts.push(format!("matches /{}/", pattern));
+        }
+        format!("{} {}", self.path.as_deref().unwrap_or_default(), parts.join(" and "))
+    }
+
+    /// Checks a serialized `ToolResponse`; the error says what was found
+    pub fn check(&self, response: &Value) -> Result<(), String> {
+        if let Some(text) = &self.mentions {
+            return match strings(&response["result"]).to_lowercase().contains(&text.to_lowercase()) {
+                true => Ok(()),
+                false => Err("not mentioned".to_string()),
+            };
+        }
+        if let Some(text) = &self.not_mentions {
+            return match strings(&response["result"]).to_lowercase().contains(&text.to_lowercase()) {
+                true => Err("mentioned".to_string()),
+                false => Ok(()),
+            };
+        }
+
+        let path = self.path.as_deref().unwrap_or_default();
+        let pointer: String = path.split('.').map(|segment| format!("/{}", segment)).collect();
+        let value = response
+            .pointer(&pointer)
+            .ok_or_else(|| format!("{} is missing", path))?;
+        let got = || format!("got {}", value);
+
+        if self.equals.as_ref().is_some_and(|expected| !equal(value, expected)) {
+            return Err(got());
+        }
+        for (bound, holds) in [
+            (self.lt, (|v, b| v < b) as fn(f64, f64) -> bool),
+            (self.le, |v, b| v <= b),
+            (self.gt, |v, b| v > b),
+            (self.ge, |v, b| v >= b),
+        ] {
+            if let Some(bound) = bound {
+                let number = value.as_f64().ok_or_else(|| format!("{} is not a number", value))?;
+                if !holds(number, bound) {
+                    return Err(got());
+                }
+            }
+        }
+        if self.min_len.is_some() || self.max_len.is_some() {
+            let len = match value {
+                Value::Array(items) => items.len(),
+                Value::String(text) => text.chars().count(),
+                Value::Object(map) => map.len(),
+                _ => return Err(format!("{} has no length", value)),
+            };
+            if self.min_len.is_some_and(|min| len < min) || self.max_len.is_some_and(|max| len > max) {
+                return Err(format!("got {} items", len));
+            }
+        }
+        if let Some(pattern) = &self.matches {
+            let text = value.as_str().ok_or_else(|| format!("{} is not a string", value))?;
+            if !Regex::new(pattern).map_err(|e| e.to_string())?.is_match(text) {
+                return Err(got());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Numbers compare by value, so `9` equals `9.0`
+fn equal(value: &Value, expected: &Value) -> bool {
+    match (value.as_f64(), expected.as_f64()) {
+        (Some(a), Some(b)) => a == b,
+        _ => value == expected,
+    }
+}
+
+/// Every string in `value`, one per line
+fn strings(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(items) => items.iter().map(strings).collect::<Vec<_>>().join("\n"),
+        Value::Object(map) => map.values().map(strings).collect::<Vec<_>>().join("\n"),
+        _ => String::new(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub label: String,
+    pub passed: usize,
+    pub total: usize,
+    pub pass_rate: f64,
+    pub cases: Vec<CaseReport>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comparison: Option<Comparison>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaseReport {
+    pub name: String,
+    pub tool: String,
+    pub passed: bool,
+    /// Template and version that rendered the prompt, e.g. `analyze.code v1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Why the tool call failed; its checks are then not run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub checks: Vec<CheckReport>,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CheckReport {
+    pub expectation: String,
+    pub passed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Differences from a baseline run, typically of other prompt templates
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Comparison {
+    pub baseline: String,
+    pub baseline_pass_rate: f64,
+    /// Change in pass rate, in percentage points
+    pub pass_rate_delta: f64,
+    /// Cases that passed in the baseline and fail now
+    pub regressions: Vec<String>,
+    /// Cases that failed in the baseline and pass now
+    pub fixes: Vec<String>,
+    /// Cases whose template version or check outcomes differ
+    pub changes: Vec<CaseChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaseChange {
+    pub name: String,
+    pub baseline_template: Option<String>,
+    pub template: Option<String>,
+    /// `None` when the case is not in the baseline
+    pub baseline_passed: Option<bool>,
+    pub passed: bool,
+    /// Expectations whose outcome flipped, prefixed with `+` (now passing)
+    /// or `-` (now failing)
+    pub flipped: Vec<String>,
+}
+
+impl Report {
+    fn new(label: &str, cases: Vec<CaseReport>) -> Self {
+        let passed = cases.iter().filter(|case| case.passed).count();
+        Self {
+            label: label.to_string(),
+            passed,
+            total: cases.len(),
+            pass_rate: if cases.is_empty() { 0.0 } else { passed as f64 / cases.len() as f64 },
+            cases,
+            comparison: None,
+        }
+    }
+
+    /// Fills in `comparison` against `baseline`
+    pub fn compare(&mut self, baseline: &Report) {
+        let previous: BTreeMap<&str, &CaseReport> = baseline.cases.iter().map(|c| (c.name.as_str(), c)).collect();
+        let mut comparison = Comparison {
+            baseline: baseline.label.clone(),
+            baseline_pass_rate: baseline.pass_rate,
+            pass_rate_delta: (self.pass_rate - baseline.pass_rate) * 100.0,
+            regressions: Vec::new(),
+            fixes: Vec::new(),
+            changes: Vec::new(),
+        };
+
+        for case in &self.cases {
+            let before = previous.get(case.name.as_str());
+            match before.map(|b| b.passed) {
+                Some(true) if !case.passed => comparison.regressions.push(case.name.clone()),
+                Some(false) if case.passed => comparison.fixes.push(case.name.clone()),
+                _ => {}
+            }
+
+            let outcomes: BTreeMap<&str, bool> = before
+                .map(|b| b.checks.iter().map(|c| (c.expectation.as_str(), c.passed)).collect())
+                .unwrap_or_default();
+            let flipped: Vec<String> = case
+                .checks
+                .iter()
+                .filter(|check| outcomes.get(check.expectation.as_str()).is_some_and(|&was| was != check.passed))
+                .map(|check| format!("{}{}", if check.passed { "+" } else { "-" }, check.expectation))
+                .collect();
+
+            let baseline_template = before.and_then(|b| b.template.clone());
+            let changed = before.is_none_or(|b| b.passed != case.passed)
+                || baseline_template != case.template
+                || !flipped.is_empty();
+            if changed {
+                comparison.changes.push(CaseChange {
+                    name: case.name.clone(),
+                    baseline_template,
+                    template: case.template.clone(),
+                    baseline_passed: before.map(|b| b.passed),
+                    passed: case.passed,
+                    flipped,
+                });
+            }
+        }
+        self.comparison = Some(comparison);
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        let _ = writeln!(md, "# Eval report: {}\n", self.label);
+        let _ = writeln!(
+            md,
+            "**{}/{} cases passed ({:.1}%)**\n",
+            self.passed,
+            self.total,
+            self.pass_rate * 100.0
+        );
+
+        md.push_str("| Case | Tool | Template | Result |\n|------|------|----------|--------|\n");
+        for case in &self.cases {
+            let _ = writeln!(
+                md,
+                "| {} | {} | {} | {} |",
+                cell(&case.name),
+                case.tool,
+                cell(case.template.as_deref().unwrap_or("-")),
+                if case.passed { "pass" } else { "**FAIL**" }
+            );
+        }
+
+        let failures: Vec<&CaseReport> = self.cases.iter().filter(|case| !case.passed).collect();
+        if !failures.is_empty() {
+            md.push_str("\n## Failures\n");
+            for case in failures {
+                let _ = writeln!(md, "\n### {}\n", case.name);
+                if let Some(error) = &case.error {
+                    let _ = writeln!(md, "- Error: {}", error);
+                }
+                for check in case.checks.iter().filter(|check| !check.passed) {
+                    let _ = writeln!(
+                        md,
+                        "- `{}`: {}",
+                        check.expectation,
+                        check.detail.as_deref().unwrap_or("failed")
+                    );
+                }
+            }
+        }
+
+        if let Some(comparison) = &self.comparison {
+            let _ = writeln!(md, "\n## Compared with {}\n", comparison.baseline);
+            let _ = writeln!(
+                md,
+                "Pass rate: {:.1}% -> {:.1}% ({:+.1} points)\n",
+                comparison.baseline_pass_rate * 100.0,
+                self.pass_rate * 100.0,
+                comparison.pass_rate_delta
+            );
+            let list = |names: &[String]| match names.is_empty() {
+                true => "none".to_string(),
+                false => names.join(", "),
+            };
+            let _ = writeln!(md, "- Regressions: {}", list(&comparison.regressions));
+            let _ = writeln!(md, "- Fixes: {}", list(&comparison.fixes));
+
+            if !comparison.changes.is_empty() {
+                md.push_str("\n| Case | Template | Baseline | Now | Flipped checks |\n");
+                md.push_str("|------|----------|----------|-----|----------------|\n");
+                for change in &comparison.changes {
+                    let template = match (&change.baseline_template, &change.template) {
+                        (before, after) if before == after => after.clone().unwrap_or_else(|| "-".to_string()),
+                        (before, after) => format!(
+                            "{} -> {}",
+                            before.as_deref().unwrap_or("-"),
+                            after.as_deref().unwrap_or("-")
+                        ),
+                    };
+                    let outcome = |passed: Option<bool>| match passed {
+                        Some(true) => "pass",
+                        Some(false) => "fail",
+                        None => "new",
+                    };
+                    let _ = writeln!(
+                        md,
+                        "| {} | {} | {} | {} | {} |",
+                        cell(&change.name),
+                        cell(&template),
+                        outcome(change.baseline_passed),
+                        outcome(Some(change.passed)),
+                        cell(&change.flipped.join("<br>"))
+                    );
+                }
+            }
+        }
+        md
+    }
+}
+
+/// Escapes a Markdown table cell
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// Runs suites against a backend
+pub struct Evaluator {
+    backend: Arc<dyn GenerationBackend>,
+    settings: ToolSettings,
+    sandbox: Option<Sandbox>,
+    model: Option<String>,
+}
+
+impl Evaluator {
+    pub fn new(backend: Arc<dyn GenerationBackend>, settings: ToolSettings) -> Self {
+        Self {
+            backend,
+            settings,
+            sandbox: None,
+            model: None,
+        }
+    }
+
+    /// Allowed roots for `agent` cases; without one they fail
+    pub fn with_sandbox(mut self, sandbox: Option<Sandbox>) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Model for cases whose input does not set one
+    pub fn with_model(mut self, model: Option<String>) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// Runs every case in order
+    pub async fn run(&self, suite: &Suite, label: &str) -> Report {
+        let mut cases = Vec::with_capacity(suite.cases.len());
+        for case in &suite.cases {
+            cases.push(self.run_case(case).await);
+        }
+        Report::new(label, cases)
+    }
+
+    async fn run_case(&self, case: &Case) -> CaseReport {
+        let started = Instant::now();
+        let mut report = CaseReport {
+            name: case.name.clone(),
+            tool: case.tool.clone(),
+            passed: false,
+            template: None,
+            error: None,
+            checks: Vec::new(),
+            duration_ms: 0,
+        };
+
+        match self.execute(&case.tool, case.input.clone()).await {
+            Ok(response) => {
+                let template = &response["metadata"]["template"];
+                report.template = template["id"]
+                    .as_str()
+                    .map(|id| format!("{} v{}", id, template["version"].as_str().unwrap_or("?")));
+                report.checks = case
+                    .expectations
+                    .iter()
+                    .map(|expectation| {
+                        let outcome = expectation.check(&response);
+                        CheckReport {
+                            expectation: expectation.describe(),
+                            passed: outcome.is_ok(),
+                            detail: outcome.err(),
+                        }
+                    })
+                    .collect();
+                report.passed = report.checks.iter().all(|check| check.passed);
+            }
+            Err(e) => report.error = Some(e.to_string()),
+        }
+        report.duration_ms = started.elapsed().as_millis() as u64;
+        report
+    }
+
+    /// Calls a V2 tool and serializes its `ToolResponse`
+    async fn execute(&self, tool: &str, mut input: Value) -> anyhow::Result<Value> {
+        if let (Some(model), Some(args)) = (&self.model, input.as_object_mut()) {
+            args.entry("model").or_insert_with(|| Value::String(model.clone()));
+        }
+        let backend = Arc::clone(&self.backend);
+        let settings = &self.settings;
+
+        Ok(match tool {
+            "search" => serde_json::to_value(query::execute_v2(serde_json::from_value(input)?, backend, settings).await?)?,
+            "analyze" => serde_json::to_value(analyze::execute_v2(serde_json::from_value(input)?, backend, settings).await?)?,
+            "summarize" => {
+                serde_json::to_value(summarize::execute_v2(serde_json::from_value(input)?, backend, settings).await?)?
+            }
+            "brainstorm" => {
+                serde_json::to_value(brainstorm::execute_v2(serde_json::from_value(input)?, backend, settings).await?)?
+            }
+            "agent" => {
+                let sandbox = self
+                    .sandbox
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("agent cases need allowed roots"))?;
+                serde_json::to_value(agent::execute_v2(serde_json::from_value(input)?, backend, settings, sandbox).await?)?
+            }
+            other => anyhow::bail!("Unknown tool '{}'", other),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemini::mock::MockBackend;
+    use serde_json::json;
+
+    const SUITE: &str = r#"
+[[case]]
+name = "sql-injection"
+tool = "analyze"
+input = { content = "query = 'SELECT * FROM users WHERE id=' + user_id", analyzer_type = { type = "code", params = { language = "python" } } }
+
+[[case.expect]]
+mentions = "SQL injection"
+
+[[case.expect]]
+path = "result.quality_score"
+lt = 5
+
+[[case]]
+name = "four-ideas"
+tool = "brainstorm"
+input = { prompt = "Names for a cat", num_ideas = 4 }
+
+[[case.expect]]
+path = "result.ideas"
+min_len = 4
+"#;
+
+    fn evaluator(replies: &[&str]) -> Evaluator {
+        let backend = replies.iter().fold(MockBackend::new(), |mock, reply| mock.with_reply(*reply));
+        Evaluator::new(Arc::new(backend), ToolSettings::default())
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_cases() {
+        assert_eq!(Suite::parse(SUITE).unwrap().cases.len(), 2);
+
+        let unknown_tool = "[[case]]\nname = \"x\"\ntool = \"paint\"\ninput = {}\n[[case.expect]]\nmentions = \"a\"";
+        assert!(Suite::parse(unknown_tool).unwrap_err().to_string().contains("unknown tool"));
+        let bad_input = "[[case]]\nname = \"x\"\ntool = \"brainstorm\"\ninput = { num_ideas = 3 }\n[[case.expect]]\nmentions = \"a\"";
+        assert!(Suite::parse(bad_input).unwrap_err().to_string().contains("invalid brainstorm input"));
+        let no_comparison = "[[case]]\nname = \"x\"\ntool = \"brainstorm\"\ninput = { prompt = \"p\" }\n[[case.expect]]\npath = \"result\"";
+        assert!(Suite::parse(no_comparison).unwrap_err().to_string().contains("no comparison"));
+        let typo = "[[case]]\nname = \"x\"\ntool = \"analyze\"\ninput = {}\n[[case.expect]]\nmention = \"a\"";
+        assert!(Suite::parse(typo).is_err());
+
+        let shipped = Suite::load(&[Path::new(env!("CARGO_MANIFEST_DIR")).join("evals")]).unwrap();
+        assert!(shipped.cases.iter().any(|case| case.name == "code-sql-injection"));
+    }
+
+    #[test]
+    fn test_expectations() {
+        let response = json!({"result": {"score": 4, "ideas": ["Use a WAF"], "verdict": "Prone to SQL Injection"}});
+        let check = |toml: &str| toml::from_str::<Expectation>(toml).unwrap().check(&response);
+
+        assert!(check(r#"mentions = "sql injection""#).is_ok());
+        assert!(check(r#"not_mentions = "waf""#).is_err());
+        assert!(check("path = \"result.score\"\nlt = 5\nge = 4").is_ok());
+        assert_eq!(check("path = \"result.score\"\ngt = 5").unwrap_err(), "got 4");
+        assert!(check("path = \"result.score\"\nequals = 4.0").is_ok());
+        assert!(check("path = \"result.ideas\"\nmin_len = 2").is_err());
+        assert!(check("path = \"result.ideas.0\"\nmatches = \"^Use\"").is_ok());
+        assert_eq!(check("path = \"result.missing\"\nlt = 1").unwrap_err(), "result.missing is missing");
+
+        let expectation: Expectation = toml::from_str("path = \"result.score\"\nlt = 5\nge = 1").unwrap();
+        assert_eq!(expectation.describe(), "result.score < 5 and >= 1");
+    }
+
+    #[tokio::test]
+    async fn test_run_and_compare() {
+        let suite = Suite::parse(SUITE).unwrap();
+
+        let baseline = evaluator(&["Quality score: 7/10", "1. Tom\n2. Felix\n3. Luna\n4. Milo"])
+            .run(&suite, "v1")
+            .await;
+        assert_eq!((baseline.passed, baseline.total), (1, 2));
+        assert_eq!(baseline.cases[0].checks[1].detail.as_deref(), Some("got 7.0"));
+        assert_eq!(baseline.cases[0].template.as_deref(), Some("analyze.code v1"));
+
+        let mut current = evaluator(&["Quality score: 2/10\n- Issue: SQL injection through string concatenation", "1. Tom"])
+            .run(&suite, "v2")
+            .await;
+        current.compare(&baseline);
+        let comparison = current.comparison.as_ref().unwrap();
+        assert_eq!(comparison.regressions, ["four-ideas"]);
+        assert_eq!(comparison.fixes, ["sql-injection"]);
+        assert_eq!(comparison.pass_rate_delta, 0.0);
+        assert_eq!(
+            comparison.changes[0].flipped,
+            ["+mentions \"SQL injection\"", "+result.quality_score < 5"]
+        );
+
+        let markdown = current.to_markdown();
+        assert!(markdown.contains("**1/2 cases passed (50.0%)**"));
+        assert!(markdown.contains("- `result.ideas has at least 4 items`: got 1 items"));
+        assert!(markdown.contains("- Regressions: four-ideas"));
+
+        let json = serde_json::to_string(&current).unwrap();
+        assert_eq!(serde_json::from_str::<Report>(&json).unwrap(), current);
+    }
+
+    #[tokio::test]
+    async fn test_tool_errors_fail_the_case() {
+        let suite = Suite::parse(SUITE).unwrap();
+        let report = evaluator(&[]).run(&suite, "empty").await;
+        assert_eq!(report.passed, 0);
+        assert!(report.cases[0].error.as_deref().unwrap().contains("no scripted reply"));
+        assert!(report.to_markdown().contains("- Error: "));
+    }
+}
@@ -0,0 +1,134 @@
+// Abstraction over the service that generates responses
+//
+// Tools, the MCP server and the health monitor depend on this trait rather
+// than on `GeminiClient`, so they can run against `MockBackend` without a
+// network. Methods return boxed futures so the trait stays object safe.
+
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::gemini::client::GeminiClient;
+use crate::gemini::functions::{FunctionLoopOptions, FunctionRegistry};
+use crate::gemini::keys::KeyUsage;
+use crate::gemini::models::ModelInfo;
+use crate::gemini::types::*;
+
+pub type BackendFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+pub trait GenerationBackend: Send + Sync {
+    /// Resolves a model id or alias, falling back to `default_alias`
+    fn resolve_model<'a>(&'a self, requested: Option<&'a str>, default_alias: &'a str) -> BackendFuture<'a, ModelInfo>;
+
+    /// Generates a response to a single prompt, continuing after MAX_TOKENS
+    /// stops at most `max_continuations` times (`None` uses the default)
+    fn generate_content_with_continuation<'a>(
+        &'a self,
+        prompt: &'a str,
+        system: Option<&'a str>,
+        model: &'a str,
+        config: Option<GenerationConfig>,
+        safety: &'a [SafetySetting],
+        max_continuations: Option<u32>,
+    ) -> BackendFuture<'a, GenerationResponse>;
+
+    /// Runs a tool-use loop over `functions` until the model answers
+    #[allow(clippy::too_many_arguments)]
+    fn generate_with_functions<'a>(
+        &'a self,
+        contents: Vec<Content>,
+        system: Option<&'a str>,
+        model: &'a str,
+        config: Option<GenerationConfig>,
+        safety: &'a [SafetySetting],
+        functions: &'a FunctionRegistry,
+        options: &'a FunctionLoopOptions,
+    ) -> BackendFuture<'a, FunctionLoopResponse>;
+
+    /// Cheap check that the backend is reachable
+    fn test_connection(&self) -> BackendFuture<'_, ()>;
+
+    fn generate_content<'a>(
+        &'a self,
+        prompt: &'a str,
+        system: Option<&'a str>,
+        model: &'a str,
+        config: Option<GenerationConfig>,
+        safety: &'a [SafetySetting],
+    ) -> BackendFuture<'a, GenerationResponse> {
+        self.generate_content_with_continuation(prompt, system, model, config, safety, None)
+    }
+
+    /// Picks up settings from a reloaded configuration
+    fn apply_config(&self, _config: &Config) -> Result<()> {
+        Ok(())
+    }
+
+    /// Fetches the model list again if the known one has expired
+    fn refresh_stale_models(&self) -> BackendFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Per-key request and token counts, for backends that use API keys
+    fn key_usage(&self) -> Vec<KeyUsage> {
+        Vec::new()
+    }
+}
+
+impl GenerationBackend for GeminiClient {
+    fn resolve_model<'a>(&'a self, requested: Option<&'a str>, default_alias: &'a str) -> BackendFuture<'a, ModelInfo> {
+        Box::pin(GeminiClient::resolve_model(self, requested, default_alias))
+    }
+
+    fn generate_content_with_continuation<'a>(
+        &'a self,
+        prompt: &'a str,
+        system: Option<&'a str>,
+        model: &'a str,
+        config: Option<GenerationConfig>,
+        safety: &'a [SafetySetting],
+        max_continuations: Option<u32>,
+    ) -> BackendFuture<'a, GenerationResponse> {
+        Box::pin(GeminiClient::generate_content_with_continuation(
+            self,
+            prompt,
+            system,
+            model,
+            config,
+            safety,
+            max_continuations,
+        ))
+    }
+
+    fn generate_with_functions<'a>(
+        &'a self,
+        contents: Vec<Content>,
+        system: Option<&'a str>,
+        model: &'a str,
+        config: Option<GenerationConfig>,
+        safety: &'a [SafetySetting],
+        functions: &'a FunctionRegistry,
+        options: &'a FunctionLoopOptions,
+    ) -> BackendFuture<'a, FunctionLoopResponse> {
+        Box::pin(GeminiClient::generate_with_functions(
+            self, contents, system, model, config, safety, functions, options,
+        ))
+    }
+
+    fn test_connection(&self) -> BackendFuture<'_, ()> {
+        Box::pin(GeminiClient::test_connection(self))
+    }
+
+    fn apply_config(&self, config: &Config) -> Result<()> {
+        GeminiClient::apply_config(self, config)
+    }
+
+    fn refresh_stale_models(&self) -> BackendFuture<'_, ()> {
+        Box::pin(GeminiClient::refresh_stale_models(self))
+    }
+
+    fn key_usage(&self) -> Vec<KeyUsage> {
+        GeminiClient::key_usage(self)
+    }
+}
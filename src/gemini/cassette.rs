@@ -0,0 +1,292 @@
+// Record/replay cassettes for generateContent traffic
+//
+// In record mode the client appends every successful request/response pair
+// to a JSON cassette file. In replay mode it answers from the cassette
+// instead of the network. Entries are keyed by a hash of the model and the
+// normalized request, so a replay only matches when the tool sends the
+// same prompt, config and history it sent while recording. Secrets are
+// redacted before anything is hashed or written.
+
+use fnv::FnvHasher;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+use crate::error::{GeminiError, Result};
+use crate::gemini::tap::Exchange;
+
+pub const CASSETTE_VERSION: u32 = 1;
+const REDACTED: &str = "[REDACTED]";
+
+/// Key-shaped strings that never belong in a cassette
+static SECRET_PATTERNS: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    [
+        r"AIza[0-9A-Za-z_\-]{35}",
+        r"ya29\.[0-9A-Za-z_\-.]+",
+        r"(?i)bearer\s+[0-9A-Za-z_\-.=]+",
+    ]
+    .iter()
+    .map(|pattern| Regex::new(pattern).expect("valid secret pattern"))
+    .collect()
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    Record,
+    Replay,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CassetteEntry {
+    /// `request_hash` of the model and request
+    pub hash: String,
+    pub model: String,
+    pub request: Value,
+    pub response: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CassetteFile {
+    version: u32,
+    entries: Vec<CassetteEntry>,
+}
+
+#[derive(Debug, Default)]
+struct CassetteState {
+    entries: Vec<CassetteEntry>,
+    /// Replays served per hash, so repeated requests get successive entries
+    served: HashMap<String, usize>,
+}
+
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    state: Mutex<CassetteState>,
+}
+
+impl Cassette {
+    /// Starts an empty cassette at `path`, replacing any existing file
+    pub fn record(path: impl Into<PathBuf>) -> Result<Self> {
+        let cassette = Self {
+            path: path.into(),
+            mode: CassetteMode::Record,
+            state: Mutex::new(CassetteState::default()),
+        };
+        cassette.save(&[])?;
+        Ok(cassette)
+    }
+
+    /// Loads the cassette at `path` for replay
+    pub fn replay(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let file: CassetteFile = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| serde_json::from_str(&text).map_err(|e| e.to_string()))
+            .map_err(|e| GeminiError::ConfigError(format!("Cannot load cassette {}: {}", path.display(), e)))?;
+        if file.version != CASSETTE_VERSION {
+            return Err(GeminiError::ConfigError(format!(
+                "Cassette {} has version {}, expected {}",
+                path.display(),
+                file.version,
+                CASSETTE_VERSION
+            )));
+        }
+
+        Ok(Self {
+            path,
+            mode: CassetteMode::Replay,
+            state: Mutex::new(CassetteState {
+                entries: file.entries,
+                served: HashMap::new(),
+            }),
+        })
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn entries(&self) -> Vec<CassetteEntry> {
+        self.lock().entries.clone()
+    }
+
+    /// Recorded response for a request, matched after redacting `secrets`.
+    /// Identical requests get successive recordings, then the last again.
+    pub fn lookup(&self, model: &str, request: &Value, secrets: &[&str]) -> Result<Value> {
+        let hash = request_hash(model, &normalize(request, secrets));
+        let mut state = self.lock();
+        let matches: Vec<&CassetteEntry> = state.entries.iter().filter(|e| e.hash == hash).collect();
+        let Some(last) = matches.last() else {
+            return Err(GeminiError::ConfigError(format!(
+                "No recording in cassette {} for this {} request (hash {}); re-record it",
+                self.path.display(),
+                model,
+                hash
+            )));
+        };
+
+        let served = state.served.get(&hash).copied().unwrap_or(0);
+        let response = matches.get(served).unwrap_or(last).response.clone();
+        state.served.insert(hash, served + 1);
+        Ok(response)
+    }
+
+    /// Appends a successful exchange and rewrites the file. `secrets` are
+    /// redacted along with anything key-shaped.
+    pub fn record_exchange(&self, exchange: &Exchange, secrets: &[&str]) -> Result<()> {
+        let Some(response) = &exchange.response else {
+            return Ok(());
+        };
+        let request = normalize(&exchange.request, secrets);
+        let mut state = self.lock();
+        state.entries.push(CassetteEntry {
+            hash: request_hash(&exchange.model, &request),
+            model: exchange.model.clone(),
+            request,
+            response: redact(response, secrets),
+        });
+        self.save(&state.entries)
+    }
+
+    fn save(&self, entries: &[CassetteEntry]) -> Result<()> {
+        let file = CassetteFile {
+            version: CASSETTE_VERSION,
+            entries: entries.to_vec(),
+        };
+        std::fs::write(&self.path, serde_json::to_string_pretty(&file)? + "\n").map_err(|e| {
+            GeminiError::ConfigError(format!("Cannot write cassette {}: {}", self.path.display(), e))
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CassetteState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Stable hex hash of a model and a normalized request. Object keys are
+/// sorted by `serde_json`, so field order does not matter.
+pub fn request_hash(model: &str, request: &Value) -> String {
+    let mut hasher = FnvHasher::default();
+    hasher.write(model.as_bytes());
+    hasher.write_u8(0);
+    hasher.write(request.to_string().as_bytes());
+    format!("{:016x}", hasher.finish())
+}
+
+/// Redacts secrets and drops thought signatures, which are opaque and
+/// differ between otherwise identical turns
+pub fn normalize(request: &Value, secrets: &[&str]) -> Value {
+    let mut request = redact(request, secrets);
+    strip_signatures(&mut request);
+    request
+}
+
+/// Copy of `value` with `secrets` and key-shaped strings replaced
+pub fn redact(value: &Value, secrets: &[&str]) -> Value {
+    match value {
+        Value::String(text) => {
+            let mut text = text.clone();
+            for secret in secrets.iter().filter(|s| !s.is_empty()) {
+                text = text.replace(secret, REDACTED);
+            }
+            for pattern in SECRET_PATTERNS.iter() {
+                text = pattern.replace_all(&text, REDACTED).into_owned();
+            }
+            Value::String(text)
+        }
+        Value::Array(items) => items.iter().map(|item| redact(item, secrets)).collect(),
+        Value::Object(map) => map.iter().map(|(k, v)| (k.clone(), redact(v, secrets))).collect(),
+        other => other.clone(),
+    }
+}
+
+fn strip_signatures(value: &mut Value) {
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(strip_signatures),
+        Value::Object(map) => {
+            map.remove("thought_signature");
+            map.remove("thoughtSignature");
+            map.values_mut().for_each(strip_signatures);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_path;
+    use serde_json::json;
+
+    fn exchange(text: &str, answer: &str) -> Exchange {
+        Exchange {
+            model: "gemini-3-flash-preview".to_string(),
+            request: json!({"contents": [{"role": "user", "parts": [{"text": text}]}]}),
+            response: Some(json!({"candidates": [{"content": {"parts": [{"text": answer}]}}]})),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_hash_ignores_key_order_and_signatures() {
+        let a = json!({"contents": [], "generation_config": {"temperature": 0.2}});
+        let b = json!({"generation_config": {"temperature": 0.2}, "contents": []});
+        assert_eq!(request_hash("m", &normalize(&a, &[])), request_hash("m", &normalize(&b, &[])));
+        assert_ne!(request_hash("m", &a), request_hash("other", &a));
+
+        let signed = json!({"parts": [{"text": "x", "thought_signature": "abc"}]});
+        let unsigned = json!({"parts": [{"text": "x"}]});
+        assert_eq!(normalize(&signed, &[]), normalize(&unsigned, &[]));
+    }
+
+    #[test]
+    fn test_redacts_secrets() {
+        let key = format!("AIza{}", "x".repeat(35));
+        let value = json!({"text": format!("key {} and sk-live-123, Bearer abc.def", key)});
+        assert_eq!(
+            redact(&value, &["sk-live-123"]),
+            json!({"text": "key [REDACTED] and [REDACTED], [REDACTED]"})
+        );
+    }
+
+    #[test]
+    fn test_record_then_replay() {
+        let path = temp_path("cassette.json");
+        let recorder = Cassette::record(&path).unwrap();
+        recorder.record_exchange(&exchange("Hi, my key is s3cret", "first"), &["s3cret"]).unwrap();
+        recorder.record_exchange(&exchange("Hi, my key is s3cret", "second"), &["s3cret"]).unwrap();
+        recorder
+            .record_exchange(
+                &Exchange {
+                    response: None,
+                    error: Some("boom".to_string()),
+                    ..exchange("failed", "")
+                },
+                &[],
+            )
+            .unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("s3cret"));
+
+        let player = Cassette::replay(&path).unwrap();
+        assert_eq!(player.entries().len(), 2);
+        // The live request still holds the secret; it is redacted before hashing
+        let request = exchange("Hi, my key is s3cret", "").request;
+        let text = |v: Value| v.pointer("/candidates/0/content/parts/0/text").cloned().unwrap();
+        assert_eq!(text(player.lookup("gemini-3-flash-preview", &request, &["s3cret"]).unwrap()), "first");
+        assert_eq!(text(player.lookup("gemini-3-flash-preview", &request, &["s3cret"]).unwrap()), "second");
+        assert_eq!(text(player.lookup("gemini-3-flash-preview", &request, &["s3cret"]).unwrap()), "second");
+
+        let err = player.lookup("gemini-3-flash-preview", &json!({"contents": []}), &[]).unwrap_err();
+        assert!(err.to_string().contains("re-record"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,199 +1,1895 @@
 use reqwest::{Client, StatusCode};
-use std::time::Duration;
-use tracing::{debug, info};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, info, warn};
 
+use crate::config::Config;
 use crate::error::{GeminiError, Result};
-use crate::gemini::{models::GeminiModel, types::*};
+use crate::gemini::fallback::FallbackPolicy;
+use crate::gemini::functions::{
+    FunctionCallRecord, FunctionLoopOptions, FunctionRegistry, LoopStopReason,
+};
+use crate::gemini::keys::{KeyLease, KeyPool, KeyUsage};
+use crate::gemini::ratelimit::{self, RateLimit, RateLimitConfig, RateLimiter};
+use crate::gemini::models::{self, ListModelsResponse, ModelInfo, ModelRegistry, RegistrySource};
+use crate::gemini::cassette::{Cassette, CassetteMode};
+use crate::gemini::tap::{Exchange, ExchangeTap};
+use crate::gemini::{safety, types::*};
 
-const BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+pub const BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+// Sent as a header rather than a query parameter so the key never ends up in
+// request URLs, which reqwest includes in its error messages
+const API_KEY_HEADER: &str = "x-goog-api-key";
+/// Environment variable for `client.max_continuations`
+pub const CONTINUATIONS_ENV: &str = "GEMINI_MAX_CONTINUATIONS";
+/// Upper bound on continuation requests for a single call
+pub const MAX_CONTINUATIONS: u32 = 5;
+/// Minimum delay between attempts to fetch the model list
+const MODELS_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+const CONTINUE_PROMPT: &str =
+    "Continue exactly where your previous response stopped. Do not repeat or summarize earlier text.";
 
-pub struct GeminiClient {
+/// Settings that can be swapped while the client is in use
+#[derive(Debug, Clone)]
+pub struct ClientSettings {
     http_client: Client,
-    api_key: String,
-    pro_model: String,
-    flash_model: String,
+    base_url: String,
+    /// Server-wide safety thresholds; calls may override single categories
+    safety_settings: Vec<SafetySetting>,
+    /// Continuation requests issued when a response stops at MAX_TOKENS
+    max_continuations: u32,
+    /// Models to retry on when a model is out of quota or overloaded
+    fallbacks: FallbackPolicy,
+    /// Requests and input tokens per minute, per model
+    rate_limits: RateLimitConfig,
 }
 
-impl GeminiClient {
-    pub fn new(api_key: String) -> Result<Self> {
+pub struct GeminiClient {
+    /// Replaced as a whole on reload; calls keep the snapshot they started with
+    settings: RwLock<Arc<ClientSettings>>,
+    /// API keys used in turn; a key that hits its quota cools down
+    keys: KeyPool,
+    /// Known models and aliases, filled from `models.list`
+    models: RwLock<ModelRegistry>,
+    /// Serializes model list refreshes; holds the time of the last attempt
+    models_refresh: tokio::sync::Mutex<Option<Instant>>,
+    models_cache: Option<PathBuf>,
+    rate_limiter: RateLimiter,
+    /// Sees raw generateContent traffic, e.g. for the inspector
+    tap: Option<ExchangeTap>,
+    /// Records generateContent traffic, or answers from a recording
+    cassette: Option<Arc<Cassette>>,
+}
+
+impl ClientSettings {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let client = &config.client;
         let http_client = Client::builder()
-            .timeout(Duration::from_secs(60))
-            .pool_idle_timeout(Duration::from_secs(90))
-            .pool_max_idle_per_host(10)
+            .timeout(Duration::from_secs(client.timeout_secs.unwrap_or(60)))
+            .pool_idle_timeout(Duration::from_secs(client.pool_idle_timeout_secs.unwrap_or(90)))
+            .pool_max_idle_per_host(client.pool_max_idle_per_host.unwrap_or(10))
             .build()
             .map_err(GeminiError::HttpClient)?;
 
-        let pro_model = std::env::var("GEMINI_PRO_MODEL")
-            .unwrap_or_else(|_| GeminiModel::Pro.as_str().to_string());
-        let flash_model = std::env::var("GEMINI_FLASH_MODEL")
-            .unwrap_or_else(|_| GeminiModel::Flash.as_str().to_string());
+        let safety_settings = safety::settings_from_config(&config.safety)?;
+        // Range-checked by `Config::validate`; calls clamp it to MAX_CONTINUATIONS
+        let max_continuations = client.max_continuations.unwrap_or(0);
 
-        info!("Gemini client initialized");
-        debug!("Pro model: {}", pro_model);
-        debug!("Flash model: {}", flash_model);
+        let fallbacks = FallbackPolicy::new(
+            config.models.fallbacks.as_deref().unwrap_or_default(),
+            config.models.fallback_on.as_deref(),
+        )?;
+        let limit = |value: Option<u32>| value.filter(|n| *n > 0);
+        let rate_limits = RateLimitConfig::new(
+            RateLimit {
+                rpm: limit(config.limits.rpm),
+                tpm: limit(config.limits.tpm),
+            },
+            config
+                .limits
+                .models
+                .iter()
+                .map(|(model, l)| {
+                    let rate_limit = RateLimit {
+                        rpm: limit(Some(l.rpm)),
+                        tpm: limit(Some(l.tpm)),
+                    };
+                    (model.clone(), rate_limit)
+                })
+                .collect(),
+        );
+
+        debug!("Safety settings: {:?}", safety_settings);
+        debug!("Fallbacks: {:?}", fallbacks);
+        debug!("Rate limits: {:?}", rate_limits);
 
+        let base_url = client.base_url.as_deref().unwrap_or(BASE_URL);
         Ok(Self {
             http_client,
-            api_key,
-            pro_model,
-            flash_model,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            safety_settings,
+            max_continuations,
+            fallbacks,
+            rate_limits,
+        })
+    }
+}
+
+impl GeminiClient {
+    pub fn new(api_key: String) -> Result<Self> {
+        Self::with_keys(KeyPool::single(api_key)?)
+    }
+
+    /// Client that spreads requests over a pool of API keys, configured
+    /// from the environment
+    pub fn with_keys(keys: KeyPool) -> Result<Self> {
+        Self::from_config(keys, &Config::from_env()?)
+    }
+
+    /// Client built from merged configuration layers
+    pub fn from_config(keys: KeyPool, config: &Config) -> Result<Self> {
+        let settings = ClientSettings::from_config(config)?;
+
+        let mut registry = ModelRegistry::builtin(models::DEFAULT_PRO_MODEL, models::DEFAULT_FLASH_MODEL);
+        apply_aliases(&mut registry, config);
+        // The cached model list belongs to the default endpoint
+        let models_cache = match &config.models.cache {
+            _ if settings.base_url != BASE_URL => None,
+            Some(path) => Some(PathBuf::from(path)).filter(|p| !p.as_os_str().is_empty()),
+            None => models::default_cache_path(),
+        };
+        if let Some(path) = &models_cache {
+            registry.load_cache(path);
+        }
+
+        info!("Gemini client initialized with {} API key(s)", keys.len());
+        debug!("Pro model: {}", registry.alias_target(models::PRO_ALIAS).unwrap_or_default());
+        debug!("Flash model: {}", registry.alias_target(models::FLASH_ALIAS).unwrap_or_default());
+
+        Ok(Self {
+            settings: RwLock::new(Arc::new(settings)),
+            keys,
+            models: RwLock::new(registry),
+            models_refresh: tokio::sync::Mutex::new(None),
+            models_cache,
+            rate_limiter: RateLimiter::new(),
+            tap: None,
+            cassette: None,
         })
     }
 
+    /// Swaps in settings and model aliases from a reloaded configuration.
+    /// Nothing changes when the configuration is invalid. API keys, rate
+    /// limiter state and the base URL, which the model list belongs to,
+    /// are kept.
+    pub fn apply_config(&self, config: &Config) -> Result<()> {
+        let mut settings = ClientSettings::from_config(config)?;
+        settings.base_url = self.settings().base_url.clone();
+
+        let mut registry = self.models.write().unwrap_or_else(|e| e.into_inner());
+        apply_aliases(&mut registry, config);
+        *self.settings.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(settings);
+        Ok(())
+    }
+
+    /// The current settings; a reload does not affect a snapshot in use
+    fn settings(&self) -> Arc<ClientSettings> {
+        Arc::clone(&self.settings.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn settings_mut(&mut self) -> &mut ClientSettings {
+        Arc::make_mut(self.settings.get_mut().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Overrides the API base URL, e.g. to point at a proxy or a mock server.
+    /// The cached model list belongs to the default endpoint, so it is dropped.
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.settings_mut().base_url = base_url.into().trim_end_matches('/').to_string();
+        self.models_cache = None;
+        self.models.get_mut().unwrap_or_else(|e| e.into_inner()).forget_models();
+        self
+    }
+
+    /// Replaces the fallback chains from the configuration
+    pub fn with_fallbacks(mut self, fallbacks: FallbackPolicy) -> Self {
+        self.settings_mut().fallbacks = fallbacks;
+        self
+    }
+
+    /// Replaces the rate limits from the configuration
+    pub fn with_rate_limits(mut self, rate_limits: RateLimitConfig) -> Self {
+        self.settings_mut().rate_limits = rate_limits;
+        self
+    }
+
+    /// Uses `path` for the model list cache instead of the default location
+    pub fn with_models_cache(mut self, path: Option<PathBuf>) -> Self {
+        let registry = self.models.get_mut().unwrap_or_else(|e| e.into_inner());
+        registry.forget_models();
+        if let Some(path) = &path {
+            registry.load_cache(path);
+        }
+        self.models_cache = path;
+        self
+    }
+
+    /// Calls `tap` with every generateContent request and its raw response
+    pub fn with_tap(mut self, tap: impl Fn(&Exchange) + Send + Sync + 'static) -> Self {
+        self.tap = Some(Arc::new(tap));
+        self
+    }
+
+    /// Records successful generateContent exchanges to `cassette`, or in
+    /// replay mode answers from it without touching the network
+    pub fn with_cassette(mut self, cassette: Cassette) -> Self {
+        self.cassette = Some(Arc::new(cassette));
+        self
+    }
+
+    fn registry(&self) -> RwLockReadGuard<'_, ModelRegistry> {
+        self.models.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Resolves a tool's `model` argument, an id or alias, falling back to
+    /// `default_alias`. Unknown ids trigger a refresh of the model list (at
+    /// most one a minute, even when the cached list is fresh); until a list
+    /// has been fetched they are passed through unchecked.
+    pub async fn resolve_model(&self, requested: Option<&str>, default_alias: &str) -> Result<ModelInfo> {
+        let name = requested
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .unwrap_or(default_alias);
+
+        let known = self.registry().contains(name);
+        if !known {
+            if let Err(e) = self.refresh_models().await {
+                warn!("Could not refresh the model list: {}", e);
+            }
+        }
+
+        self.registry().resolve(name).map_err(GeminiError::InvalidModel)
+    }
+
+    /// Fetches the model list again once a fetched or cached list is older
+    /// than `models::CACHE_TTL`, so retired models drop off it
+    pub async fn refresh_stale_models(&self) -> Result<()> {
+        let stale = {
+            let registry = self.registry();
+            registry.is_authoritative() && registry.is_stale()
+        };
+        if !stale {
+            return Ok(());
+        }
+        self.refresh_models().await
+    }
+
+    /// Fetches the model list, replaces the registry and updates the cache.
+    /// Attempts closer together than a minute are skipped.
+    pub async fn refresh_models(&self) -> Result<()> {
+        let mut last_attempt = self.models_refresh.lock().await;
+        if last_attempt.is_some_and(|t| t.elapsed() < MODELS_RETRY_INTERVAL) {
+            return Ok(());
+        }
+        *last_attempt = Some(Instant::now());
+
+        let fetched = self.fetch_models().await?;
+        info!("Fetched {} models", fetched.len());
+
+        let mut registry = self.models.write().unwrap_or_else(|e| e.into_inner());
+        registry.replace_models(fetched, RegistrySource::Api, SystemTime::now());
+        if let Some(path) = &self.models_cache {
+            if let Err(e) = registry.save_cache(path) {
+                warn!("Could not write model cache {}: {}", path.display(), e);
+            }
+        }
+        Ok(())
+    }
+
+    /// All pages of `models.list`
+    async fn fetch_models(&self) -> Result<Vec<ModelInfo>> {
+        let mut fetched = Vec::new();
+        let mut page_token = None;
+        loop {
+            let page = self.list_models_page(1000, page_token.as_deref()).await?;
+            fetched.extend(page.models.into_iter().map(ModelInfo::from));
+            match page.next_page_token.filter(|t| !t.is_empty()) {
+                Some(token) => page_token = Some(token),
+                None => return Ok(fetched),
+            }
+        }
+    }
+
+    /// Context window of a model, in input tokens
+    pub fn input_token_limit(&self, model: &str) -> u32 {
+        self.registry()
+            .get(model)
+            .map(|info| info.input_token_limit)
+            .unwrap_or_else(|| models::input_token_limit(model))
+    }
+
+    /// Exact token count of a prompt and system instruction, via `:countTokens`
+    pub async fn count_tokens(
+        &self,
+        prompt: &str,
+        system: Option<&str>,
+        model: &str,
+    ) -> Result<u32> {
+        let request = GenerateContentRequest {
+            contents: vec![Content::user(prompt)],
+            system_instruction: system.map(Content::system),
+            generation_config: None,
+            safety_settings: None,
+            tools: None,
+            tool_config: None,
+        };
+        self.count_request_tokens(model, &request).await
+    }
+
+    async fn count_request_tokens(
+        &self,
+        model: &str,
+        request: &GenerateContentRequest,
+    ) -> Result<u32> {
+        let url = format!("{}/models/{}:countTokens", self.settings().base_url, model);
+        let body = CountTokensRequest {
+            generate_content_request: CountTokensContent {
+                model: format!("models/{}", model),
+                request,
+            },
+        };
+
+        // countTokens has its own quota, so the generate rate limits do not apply
+        self.with_key(
+            |key| {
+                let request = self.settings().http_client.post(&url);
+                let body = &body;
+                async move {
+                    let response = request
+                        .header(API_KEY_HEADER, key.secret())
+                        .json(body)
+                        .send()
+                        .await
+                        .map_err(GeminiError::HttpClient)?;
+                    match response.status() {
+                        StatusCode::OK => Ok(response.json::<CountTokensResponse>().await?.total_tokens),
+                        status => Err(Self::api_error(status, response).await),
+                    }
+                }
+            },
+            |_| None,
+        )
+        .await
+    }
+
+    /// Fails with `InputTooLarge` when the request exceeds the model's input
+    /// window. A token spans at least one byte, so requests whose text fits
+    /// in the window byte for byte skip the `:countTokens` round trip.
+    async fn preflight(&self, model: &str, request: &GenerateContentRequest) -> Result<()> {
+        let limit = self.input_token_limit(model);
+        if text_bytes(request) <= limit as usize {
+            return Ok(());
+        }
+
+        let tokens = self.count_request_tokens(model, request).await?;
+        debug!("Pre-flight: {} tokens (limit {})", tokens, limit);
+        if tokens > limit {
+            return Err(GeminiError::InputTooLarge {
+                model: model.to_string(),
+                tokens,
+                limit,
+            });
+        }
+        Ok(())
+    }
+
+    /// Generates a response, continuing after MAX_TOKENS stops up to the
+    /// configured default (`client.max_continuations`)
     pub async fn generate_content(
         &self,
         prompt: &str,
-        model: GeminiModel,
+        system: Option<&str>,
+        model: &str,
         config: Option<GenerationConfig>,
+        safety: &[SafetySetting],
     ) -> Result<GenerationResponse> {
-        let model_name = match model {
-            GeminiModel::Pro => &self.pro_model,
-            GeminiModel::Flash => &self.flash_model,
-        };
+        self.generate_content_with_continuation(prompt, system, model, config, safety, None)
+            .await
+    }
 
-        let request = GenerateContentRequest {
-            contents: vec![Content {
-                role: "user".to_string(),
-                parts: vec![Part::Text {
-                    text: prompt.to_string(),
-                }],
-            }],
+    /// Like `generate_content`, but while the first candidate stops at
+    /// MAX_TOKENS, asks the model to continue and stitches the parts
+    /// together, at most `max_continuations` times (`None` uses the server
+    /// default). Calls requesting several candidates are never continued.
+    pub async fn generate_content_with_continuation(
+        &self,
+        prompt: &str,
+        system: Option<&str>,
+        model: &str,
+        config: Option<GenerationConfig>,
+        safety: &[SafetySetting],
+        max_continuations: Option<u32>,
+    ) -> Result<GenerationResponse> {
+        let max_continuations = max_continuations
+            .unwrap_or(self.settings().max_continuations)
+            .min(MAX_CONTINUATIONS);
+        let single_candidate = config
+            .as_ref()
+            .and_then(|c| c.candidate_count)
+            .is_none_or(|n| n <= 1);
+
+        let mut request = GenerateContentRequest {
+            contents: vec![Content::user(prompt)],
+            system_instruction: system.map(Content::system),
             generation_config: config,
-            safety_settings: None,
+            safety_settings: safety::merge(&self.settings().safety_settings, safety),
+            tools: None,
+            tool_config: None,
         };
 
-        let url = format!(
-            "{}/models/{}:generateContent?key={}",
-            BASE_URL, model_name, self.api_key
-        );
+        self.preflight(model, &request).await?;
+        let (resp, mut answered) = self.send_with_fallback(model, &request).await?;
 
-        debug!("Sending request to {}", model_name);
+        let mut usage = resp.usage_metadata.clone().unwrap_or_default();
+        let mut safety_ratings = safety::ratings(&resp);
 
-        let response = self
-            .http_client
-            .post(&url)
-            .json(&request)
-            .send()
-            .await
-            .map_err(GeminiError::HttpClient)?;
+        let mut candidates: Vec<String> = resp
+            .candidates
+            .iter()
+            .map(candidate_text)
+            .filter(|text| !text.is_empty())
+            .collect();
 
-        match response.status() {
-            StatusCode::OK => {
-                let resp: GenerateContentResponse = response.json().await?;
-
-                let usage = resp.usage_metadata.clone().unwrap_or_default();
-
-                debug!(
-                    "Tokens - prompt: {}, response: {}, total: {}",
-                    usage.prompt_token_count,
-                    usage.candidates_token_count,
-                    usage.total_token_count
-                );
-
-                let text = resp.candidates
-                    .first()
-                    .and_then(|c| c.content.parts.first())
-                    .and_then(|p| match p {
-                        Part::Text { text } => Some(text.clone()),
-                        _ => None,
-                    })
-                    .ok_or(GeminiError::EmptyResponse)?;
-
-                Ok(GenerationResponse { text, usage })
-            }
-            status => {
-                let error_body = response
-                    .text()
-                    .await
-                    .unwrap_or_else(|_| "Unknown error".to_string());
-                Err(GeminiError::ApiError {
-                    status: status.as_u16(),
-                    message: error_body,
-                })
+        // Text and metadata come from the first candidate with text. When
+        // none has any, thinking may have used up the budget of the first,
+        // and continuing it can still produce an answer.
+        let primary = resp
+            .candidates
+            .iter()
+            .position(|c| !candidate_text(c).is_empty())
+            .or_else(|| {
+                let first = resp.candidates.first()?;
+                (first.finish_reason.as_deref() == Some(FINISH_MAX_TOKENS)).then_some(0)
+            })
+            .ok_or(GeminiError::EmptyResponse)?;
+        let candidate = resp.candidates.into_iter().nth(primary).ok_or(GeminiError::EmptyResponse)?;
+
+        let mut text = candidate_text(&candidate);
+        let mut finish_reason = candidate.finish_reason.clone();
+        let avg_logprobs = candidate.avg_logprobs;
+        let mut thoughts = None;
+        push_thoughts(&mut thoughts, candidate_thoughts(&candidate));
+        let mut last_turn = Some(candidate.content);
+        let mut continuations = 0;
+
+        while finish_reason.as_deref() == Some(FINISH_MAX_TOKENS)
+            && single_candidate
+            && continuations < max_continuations
+        {
+            // A turn with no parts at all is rejected by the API
+            if let Some(content) = last_turn.take().filter(|c| !c.parts.is_empty()) {
+                request.contents.push(content);
             }
+            request.contents.push(Content::user(CONTINUE_PROMPT));
+
+            let (resp, model_used) = self.send_with_fallback(&answered, &request).await?;
+            answered = model_used;
+            continuations += 1;
+
+            if let Some(u) = &resp.usage_metadata {
+                usage.add(u);
+            }
+            safety_ratings = safety::ratings(&resp);
+
+            let Some(candidate) = resp.candidates.into_iter().next() else {
+                break;
+            };
+            finish_reason = candidate.finish_reason.clone();
+            text.push_str(&candidate_text(&candidate));
+            push_thoughts(&mut thoughts, candidate_thoughts(&candidate));
+            last_turn = Some(candidate.content);
         }
+
+        if finish_reason.as_deref() == Some(FINISH_MAX_TOKENS) {
+            if text.is_empty() {
+                return Err(GeminiError::Truncated { continuations });
+            }
+            warn!(
+                "Response truncated at max_output_tokens after {} continuation(s)",
+                continuations
+            );
+        }
+        if text.is_empty() {
+            return Err(GeminiError::EmptyResponse);
+        }
+
+        debug!(
+            "Tokens - prompt: {}, response: {}, thoughts: {}, cached: {}, total: {}, continuations: {}",
+            usage.prompt_token_count,
+            usage.candidates_token_count,
+            usage.thoughts_token_count,
+            usage.cached_content_token_count,
+            usage.total_token_count,
+            continuations
+        );
+
+        // The primary candidate is the first with text, or the only one
+        match candidates.first_mut() {
+            Some(first) => first.clone_from(&text),
+            None => candidates.push(text.clone()),
+        }
+
+        Ok(GenerationResponse {
+            text,
+            candidates,
+            usage,
+            safety_ratings,
+            finish_reason,
+            continuations,
+            avg_logprobs,
+            thoughts,
+            fallback_from: (answered != model).then(|| model.to_string()),
+            model: answered,
+        })
     }
 
     pub async fn generate_with_history(
         &self,
         messages: Vec<(String, String)>, // (role, content)
-        model: GeminiModel,
+        system: Option<&str>,
+        model: &str,
         config: Option<GenerationConfig>,
     ) -> Result<String> {
-        let model_name = match model {
-            GeminiModel::Pro => &self.pro_model,
-            GeminiModel::Flash => &self.flash_model,
-        };
-
         let contents: Vec<Content> = messages
             .into_iter()
             .map(|(role, text)| Content {
                 role,
-                parts: vec![Part::Text { text }],
+                parts: vec![Part::text(text)],
             })
             .collect();
 
         let request = GenerateContentRequest {
             contents,
+            system_instruction: system.map(Content::system),
             generation_config: config,
-            safety_settings: None,
+            safety_settings: safety::merge(&self.settings().safety_settings, &[]),
+            tools: None,
+            tool_config: None,
         };
 
-        let url = format!(
-            "{}/models/{}:generateContent?key={}",
-            BASE_URL, model_name, self.api_key
-        );
+        self.preflight(model, &request).await?;
+        let (resp, _) = self.send_with_fallback(model, &request).await?;
+
+        resp.candidates
+            .first()
+            .map(candidate_text)
+            .filter(|text| !text.is_empty())
+            .ok_or(GeminiError::EmptyResponse)
+    }
+
+    /// Runs a tool-use loop: the model may call any function in `functions`,
+    /// each call is executed by its handler and the results are sent back in
+    /// history, until the model answers with text or a limit is reached.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn generate_with_functions(
+        &self,
+        contents: Vec<Content>,
+        system: Option<&str>,
+        model: &str,
+        config: Option<GenerationConfig>,
+        safety: &[SafetySetting],
+        functions: &FunctionRegistry,
+        options: &FunctionLoopOptions,
+    ) -> Result<FunctionLoopResponse> {
+        if functions.is_empty() {
+            return Err(GeminiError::ConfigError(
+                "generate_with_functions requires at least one function".to_string(),
+            ));
+        }
+
+        let tool_config = ToolConfig {
+            function_calling_config: FunctionCallingConfig {
+                mode: options.mode,
+                allowed_function_names: None,
+            },
+        };
+
+        let mut request = GenerateContentRequest {
+            contents,
+            system_instruction: system.map(Content::system),
+            generation_config: config,
+            safety_settings: safety::merge(&self.settings().safety_settings, safety),
+            tools: Some(vec![functions.as_tool()]),
+            tool_config: Some(tool_config),
+        };
+
+        let mut usage = UsageMetadata::default();
+        let mut calls = Vec::new();
+        let mut thoughts = None;
+        let mut answered = model.to_string();
+        let max_steps = options.max_steps.max(1);
+
+        for step in 1..=max_steps {
+            // Force a text answer on the last turn or once the budget is spent
+            let forced = if options
+                .max_total_tokens
+                .is_some_and(|budget| usage.total_token_count >= budget)
+            {
+                Some(LoopStopReason::TokenBudget)
+            } else if step == max_steps {
+                Some(LoopStopReason::StepLimit)
+            } else {
+                None
+            };
+            if forced.is_some() {
+                if let Some(config) = request.tool_config.as_mut() {
+                    config.function_calling_config.mode = FunctionCallingMode::None;
+                }
+            }
+
+            let (resp, model_used) = self.send_with_fallback(&answered, &request).await?;
+            answered = model_used;
+            if let Some(u) = &resp.usage_metadata {
+                usage.add(u);
+            }
+
+            let safety_ratings = safety::ratings(&resp);
+            let candidate = resp
+                .candidates
+                .into_iter()
+                .next()
+                .ok_or(GeminiError::EmptyResponse)?;
+            let text = candidate_text(&candidate);
+            push_thoughts(&mut thoughts, candidate_thoughts(&candidate));
+            let content = candidate.content;
+
+            let requested: Vec<FunctionCall> = content
+                .parts
+                .iter()
+                .filter_map(|p| match p {
+                    Part::FunctionCall { function_call, .. } => Some(function_call.clone()),
+                    _ => None,
+                })
+                .collect();
+
+            if requested.is_empty() || forced.is_some() {
+                let stop_reason = forced.unwrap_or(LoopStopReason::Completed);
+                debug!("Function loop finished after {} step(s): {:?}", step, stop_reason);
+                if stop_reason != LoopStopReason::Completed {
+                    info!(
+                        "Function loop forced to answer ({:?}) after {} call(s)",
+                        stop_reason,
+                        calls.len()
+                    );
+                }
+                return Ok(FunctionLoopResponse {
+                    text,
+                    usage,
+                    calls,
+                    steps: step,
+                    stop_reason,
+                    safety_ratings,
+                    thoughts,
+                    fallback_from: (answered != model).then(|| model.to_string()),
+                    model: answered,
+                });
+            }
+
+            // The model turn, including thought signatures, goes back verbatim
+            request.contents.push(content);
+
+            let mut responses = Vec::with_capacity(requested.len());
+            for call in requested {
+                debug!("Step {}: calling function {}", step, call.name);
+                let started = Instant::now();
+                let (response, is_error) = match functions.call(&call.name, call.args.clone()).await {
+                    Ok(value) => (value, false),
+                    Err(message) => (serde_json::json!({ "error": message }), true),
+                };
+
+                calls.push(FunctionCallRecord {
+                    step,
+                    name: call.name.clone(),
+                    args: call.args,
+                    response: response.clone(),
+                    is_error,
+                    duration_ms: started.elapsed().as_millis() as u64,
+                });
+
+                // The API expects an object in `response`
+                let response = if response.is_object() {
+                    response
+                } else {
+                    serde_json::json!({ "result": response })
+                };
+
+                responses.push(Part::FunctionResponse {
+                    function_response: FunctionResponse {
+                        id: call.id,
+                        name: call.name,
+                        response,
+                    },
+                });
+            }
+
+            request.contents.push(Content {
+                role: "user".to_string(),
+                parts: responses,
+            });
+        }
+
+        // The final step always returns above
+        Err(GeminiError::EmptyResponse)
+    }
+
+    /// Sends `request` to `model`, moving down its fallback chain while the
+    /// error is one the policy falls back on. Returns the response and the
+    /// model that produced it.
+    async fn send_with_fallback(
+        &self,
+        model: &str,
+        request: &GenerateContentRequest,
+    ) -> Result<(GenerateContentResponse, String)> {
+        let mut error = match self.send_request(model, request).await {
+            Ok(resp) => return Ok((resp, model.to_string())),
+            Err(e) => e,
+        };
+        let fallbacks = self.settings().fallbacks.clone();
+        if fallbacks.is_empty() || !fallbacks.applies_to(&error) {
+            return Err(error);
+        }
+
+        let chain = {
+            let registry = self.registry();
+            fallbacks.fallbacks_for(model, |name| registry.resolve(name).ok().map(|m| m.id))
+        };
+        let mut failed = model.to_string();
+        for next in chain {
+            if !fallbacks.applies_to(&error) {
+                break;
+            }
+            warn!("{} failed ({}), falling back to {}", failed, error, next);
+            match self.send_request(&next, request).await {
+                Ok(resp) => return Ok((resp, next)),
+                Err(e) => {
+                    error = e;
+                    failed = next;
+                }
+            }
+        }
+        Err(error)
+    }
+
+    /// Sends `request` to `model` with the next API key, moving on to the
+    /// other keys while the error is specific to the key (quota or auth)
+    async fn send_request(
+        &self,
+        model: &str,
+        request: &GenerateContentRequest,
+    ) -> Result<GenerateContentResponse> {
+        let limit = {
+            let registry = self.registry();
+            self.settings().rate_limits
+                .limit_for(model, |name| registry.resolve(name).ok().map(|m| m.id))
+        };
+        let estimate = ratelimit::estimate_tokens(text_bytes(request));
+
+        let resp = self
+            .with_key(
+                |key| async move {
+                    // Quotas belong to the key, so each key gets its own buckets
+                    let bucket = if self.keys.len() > 1 {
+                        format!("{}@{}", model, key.label())
+                    } else {
+                        model.to_string()
+                    };
+                    let reservation = self.rate_limiter.acquire(&bucket, limit, estimate).await;
+
+                    let result = self.post_generate(model, request, &key).await;
+                    let usage = result.as_ref().ok().and_then(|resp| resp.usage_metadata.as_ref());
+                    reservation.reconcile(usage.map_or(0, |u| u.prompt_token_count));
+                    result
+                },
+                |resp| resp.usage_metadata.as_ref(),
+            )
+            .await?;
+        safety::check_blocked(&resp)?;
+        Ok(resp)
+    }
+
+    /// Runs `call` with a leased key and records the outcome against it.
+    /// Keys that hit their quota or are rejected cool down and the next one
+    /// is tried; a rejected key is read again from its source once.
+    async fn with_key<T, F, Fut>(&self, mut call: F, usage: impl Fn(&T) -> Option<&UsageMetadata>) -> Result<T>
+    where
+        F: FnMut(KeyLease) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempts = self.keys.len();
+        let mut reloaded = false;
+        loop {
+            let key = self.keys.lease();
+            match call(key.clone()).await {
+                Ok(value) => {
+                    self.keys.record_success(&key, usage(&value));
+                    return Ok(value);
+                }
+                // A rotated key is picked up without restarting the server
+                Err(GeminiError::AuthError(_)) if !reloaded && self.keys.reload(&key).await => {
+                    reloaded = true;
+                    continue;
+                }
+                Err(e) => {
+                    attempts -= 1;
+                    if self.keys.record_failure(&key, &e) && attempts > 0 {
+                        warn!("API key {} failed ({}), rotating to the next key", key.label(), e);
+                        continue;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Per-key request and token counts
+    pub fn key_usage(&self) -> Vec<KeyUsage> {
+        self.keys.usage()
+    }
+
+    async fn post_generate(
+        &self,
+        model: &str,
+        request: &GenerateContentRequest,
+        key: &KeyLease,
+    ) -> Result<GenerateContentResponse> {
+        if let Some(cassette) = self.cassette.as_ref().filter(|c| c.mode() == CassetteMode::Replay) {
+            let request = serde_json::to_value(request)?;
+            let body = cassette.lookup(model, &request, &[key.secret()]);
+            if let Some(tap) = &self.tap {
+                tap(&Exchange {
+                    model: model.to_string(),
+                    request,
+                    response: body.as_ref().ok().cloned(),
+                    error: body.as_ref().err().map(|e| e.to_string()),
+                });
+            }
+            return Ok(serde_json::from_value(body?)?);
+        }
+
+        let url = format!("{}/models/{}:generateContent", self.settings().base_url, model);
+
+        debug!("Sending request to {}", model);
 
         let response = self
+            .settings()
             .http_client
             .post(&url)
-            .json(&request)
+            .header(API_KEY_HEADER, key.secret())
+            .json(request)
             .send()
             .await
-            .map_err(GeminiError::HttpClient)?;
+            .map_err(GeminiError::HttpClient);
 
-        match response.status() {
-            StatusCode::OK => {
-                let resp: GenerateContentResponse = response.json().await?;
-
-                resp.candidates
-                    .first()
-                    .and_then(|c| c.content.parts.first())
-                    .and_then(|p| match p {
-                        Part::Text { text } => Some(text.clone()),
-                        _ => None,
-                    })
-                    .ok_or(GeminiError::EmptyResponse)
-            }
-            status => {
-                let error_body = response
-                    .text()
-                    .await
-                    .unwrap_or_else(|_| "Unknown error".to_string());
-                Err(GeminiError::ApiError {
-                    status: status.as_u16(),
-                    message: error_body,
-                })
+        if self.tap.is_none() && self.cassette.is_none() {
+            let response = response?;
+            return match response.status() {
+                StatusCode::OK => Ok(response.json().await?),
+                status => Err(Self::api_error(status, response).await),
+            };
+        }
+
+        let body = match response {
+            Ok(response) if response.status() == StatusCode::OK => {
+                response.json::<serde_json::Value>().await.map_err(GeminiError::HttpClient)
+            }
+            Ok(response) => Err(Self::api_error(response.status(), response).await),
+            Err(e) => Err(e),
+        };
+        let exchange = Exchange {
+            model: model.to_string(),
+            request: serde_json::to_value(request)?,
+            response: body.as_ref().ok().cloned(),
+            error: body.as_ref().err().map(|e| e.to_string()),
+        };
+        if let Some(tap) = &self.tap {
+            tap(&exchange);
+        }
+        if let Some(cassette) = &self.cassette {
+            if let Err(e) = cassette.record_exchange(&exchange, &[key.secret()]) {
+                warn!("{}", e);
             }
         }
+        Ok(serde_json::from_value(body?)?)
     }
 
+    /// Cheap connectivity check: fetches a single page of `models.list`
+    /// instead of spending a generation request.
     pub async fn test_connection(&self) -> Result<()> {
-        info!("Testing connection to Gemini API...");
-        self.generate_content("Test", GeminiModel::Pro, None)
-            .await?;
-        info!("Connection test successful");
+        debug!("Testing connection to Gemini API...");
+        self.list_models_page(1, None).await?;
+        debug!("Connection test successful");
         Ok(())
     }
+
+    async fn list_models_page(&self, page_size: u32, page_token: Option<&str>) -> Result<ListModelsResponse> {
+        let url = format!("{}/models", self.settings().base_url);
+        let mut query = vec![("pageSize", page_size.to_string())];
+        if let Some(token) = page_token {
+            query.push(("pageToken", token.to_string()));
+        }
+
+        self.with_key(
+            |key| {
+                let request = self.settings().http_client.get(&url).query(&query);
+                async move {
+                    let response = request
+                        .header(API_KEY_HEADER, key.secret())
+                        .send()
+                        .await
+                        .map_err(GeminiError::HttpClient)?;
+                    match response.status() {
+                        StatusCode::OK => Ok(response.json().await?),
+                        status => Err(Self::api_error(status, response).await),
+                    }
+                }
+            },
+            |_| None,
+        )
+        .await
+    }
+
+    async fn api_error(status: StatusCode, response: reqwest::Response) -> GeminiError {
+        let error_body = response
+            .text()
+            .await
+            .unwrap_or_else(|_| "Unknown error".to_string());
+
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => GeminiError::AuthError(error_body),
+            StatusCode::BAD_REQUEST if error_body.contains("API_KEY_INVALID") => {
+                GeminiError::AuthError(error_body)
+            }
+            _ => GeminiError::ApiError {
+                status: status.as_u16(),
+                message: error_body,
+            },
+        }
+    }
+}
+
+/// Points `pro`, `flash` and the configured aliases at their models
+fn apply_aliases(registry: &mut ModelRegistry, config: &Config) {
+    let pro = config.models.pro.as_deref().unwrap_or(models::DEFAULT_PRO_MODEL);
+    let flash = config.models.flash.as_deref().unwrap_or(models::DEFAULT_FLASH_MODEL);
+    let extra = config.models.aliases.iter().map(|(alias, id)| (alias.as_str(), id.as_str()));
+    registry.set_aliases([(models::PRO_ALIAS, pro), (models::FLASH_ALIAS, flash)].into_iter().chain(extra));
+}
+
+/// Bytes of text in the system instruction and contents of a request
+fn text_bytes(request: &GenerateContentRequest) -> usize {
+    request
+        .system_instruction
+        .iter()
+        .chain(request.contents.iter())
+        .flat_map(|c| c.parts.iter())
+        .filter_map(|p| match p {
+            Part::Text { text, .. } => Some(text.len()),
+            _ => None,
+        })
+        .sum()
+}
+
+/// All answer text of a candidate, concatenated; thoughts and other parts
+/// are skipped
+fn candidate_text(candidate: &Candidate) -> String {
+    candidate.content.parts.iter().filter_map(Part::answer_text).collect()
+}
+
+/// Thought summaries of a candidate, concatenated
+fn candidate_thoughts(candidate: &Candidate) -> String {
+    candidate.content.parts.iter().filter_map(Part::thought_text).collect()
+}
+
+/// Appends a step's thoughts to those gathered so far, one paragraph each
+fn push_thoughts(thoughts: &mut Option<String>, more: String) {
+    if more.trim().is_empty() {
+        return;
+    }
+    match thoughts {
+        Some(existing) => {
+            existing.push_str("\n\n");
+            existing.push_str(more.trim());
+        }
+        None => *thoughts = Some(more.trim().to_string()),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::temp_path;
 
     #[test]
     fn test_client_creation() {
         let client = GeminiClient::new("test_key".to_string());
         assert!(client.is_ok());
     }
+
+    #[test]
+    fn test_client_rejects_empty_key() {
+        let client = GeminiClient::new("  ".to_string());
+        assert!(matches!(client, Err(GeminiError::ConfigError(_))));
+    }
+
+    #[test]
+    fn test_apply_config_swaps_settings() {
+        let client = GeminiClient::new("test_key".to_string())
+            .unwrap()
+            .with_models_cache(None);
+
+        let mut config = Config::builtin();
+        config.client.max_continuations = Some(3);
+        config.models.flash = Some("gemini-2.5-flash".to_string());
+        config.models.aliases.insert("lite".to_string(), "gemini-2.5-flash-lite".to_string());
+        client.apply_config(&config).unwrap();
+
+        assert_eq!(client.settings().max_continuations, 3);
+        assert_eq!(client.registry().alias_target("flash"), Some("gemini-2.5-flash"));
+        assert_eq!(client.registry().alias_target("lite"), Some("gemini-2.5-flash-lite"));
+
+        // An invalid config leaves the previous settings in place
+        config.client.max_continuations = Some(1);
+        config.models.fallbacks = Some(vec!["pro".to_string()]);
+        assert!(client.apply_config(&config).is_err());
+        assert_eq!(client.settings().max_continuations, 3);
+    }
+
+    fn weather_registry() -> FunctionRegistry {
+        let mut registry = FunctionRegistry::new();
+        registry.register(
+            FunctionDeclaration {
+                name: "get_weather".to_string(),
+                description: "Current weather for a city".to_string(),
+                parameters: Some(serde_json::json!({
+                    "type": "object",
+                    "properties": {"city": {"type": "string"}},
+                    "required": ["city"]
+                })),
+            },
+            |args| async move {
+                let city = args["city"].as_str().ok_or("city is required")?.to_string();
+                Ok(serde_json::json!({"city": city, "forecast": "sunny"}))
+            },
+        );
+        registry
+    }
+
+    #[tokio::test]
+    async fn test_function_loop_executes_calls_and_returns_answer() {
+        let mut server = mockito::Server::new_async().await;
+        let path = "/models/gemini-3-flash-preview:generateContent";
+
+        // Second turn: history carries the function response back
+        let answer = server
+            .mock("POST", path)
+            .match_body(mockito::Matcher::Regex("function_response".to_string()))
+            .with_body(
+                r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "It is sunny in Paris."}]}}],
+                    "usageMetadata": {"promptTokenCount": 20, "candidatesTokenCount": 6, "totalTokenCount": 26}}"#,
+            )
+            .create_async()
+            .await;
+        // First turn: the model asks for the weather
+        let call = server
+            .mock("POST", path)
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "system_instruction": {"parts": [{"text": "You are a weather assistant."}]}
+            })))
+            .with_body(
+                r#"{"candidates": [{"content": {"role": "model", "parts": [
+                    {"functionCall": {"name": "get_weather", "args": {"city": "Paris"}}, "thoughtSignature": "sig"}
+                ]}}]}"#,
+            )
+            .create_async()
+            .await;
+
+        let client = GeminiClient::new("test_key".to_string())
+            .unwrap()
+            .with_base_url(server.url());
+        let contents = vec![Content::user("Weather in Paris?")];
+
+        let response = client
+            .generate_with_functions(
+                contents,
+                Some("You are a weather assistant."),
+                "gemini-3-flash-preview",
+                None,
+                &[],
+                &weather_registry(),
+                &FunctionLoopOptions::default(),
+            )
+            .await
+            .unwrap();
+
+        call.assert_async().await;
+        answer.assert_async().await;
+        assert_eq!(response.text, "It is sunny in Paris.");
+        assert_eq!(response.steps, 2);
+        assert_eq!(response.usage.total_token_count, 26);
+        assert_eq!(response.stop_reason, LoopStopReason::Completed);
+        assert_eq!(response.calls.len(), 1);
+        assert_eq!(response.calls[0].name, "get_weather");
+        assert_eq!(response.calls[0].response["forecast"], "sunny");
+        assert!(!response.calls[0].is_error);
+    }
+
+    #[tokio::test]
+    async fn test_function_loop_forces_answer_when_budget_spent() {
+        let mut server = mockito::Server::new_async().await;
+        let path = "/models/gemini-3-flash-preview:generateContent";
+        let answer = server
+            .mock("POST", path)
+            .match_body(mockito::Matcher::Regex(r#""mode":"NONE""#.to_string()))
+            .with_body(r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "Partial report"}]}}]}"#)
+            .create_async()
+            .await;
+        let call = server
+            .mock("POST", path)
+            .match_body(mockito::Matcher::Regex(r#""mode":"AUTO""#.to_string()))
+            .with_body(
+                r#"{"candidates": [{"content": {"role": "model", "parts": [
+                    {"functionCall": {"name": "get_weather", "args": {"city": "Oslo"}}}
+                ]}}], "usageMetadata": {"promptTokenCount": 900, "candidatesTokenCount": 200, "totalTokenCount": 1100}}"#,
+            )
+            .create_async()
+            .await;
+
+        let client = GeminiClient::new("test_key".to_string())
+            .unwrap()
+            .with_base_url(server.url());
+        let options = FunctionLoopOptions {
+            max_total_tokens: Some(1000),
+            ..Default::default()
+        };
+
+        let response = client
+            .generate_with_functions(vec![], None, "gemini-3-flash-preview", None, &[], &weather_registry(), &options)
+            .await
+            .unwrap();
+
+        call.assert_async().await;
+        answer.assert_async().await;
+        assert_eq!(response.stop_reason, LoopStopReason::TokenBudget);
+        assert_eq!(response.text, "Partial report");
+        assert_eq!(response.steps, 2);
+    }
+
+    #[tokio::test]
+    async fn test_function_loop_stops_at_step_limit() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/models/gemini-3-flash-preview:generateContent")
+            .with_body(
+                r#"{"candidates": [{"content": {"role": "model", "parts": [
+                    {"functionCall": {"name": "get_weather", "args": {}}}
+                ]}}]}"#,
+            )
+            .expect(2)
+            .create_async()
+            .await;
+
+        let client = GeminiClient::new("test_key".to_string())
+            .unwrap()
+            .with_base_url(server.url());
+        let options = FunctionLoopOptions {
+            max_steps: 2,
+            ..Default::default()
+        };
+
+        let response = client
+            .generate_with_functions(vec![], None, "gemini-3-flash-preview", None, &[], &weather_registry(), &options)
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(response.stop_reason, LoopStopReason::StepLimit);
+        // Calls requested on the forced final turn are not executed
+        assert_eq!(response.calls.len(), 1);
+        // Missing argument surfaces as a handler error sent back to the model
+        assert!(response.calls[0].is_error);
+        assert_eq!(response.calls[0].response["error"], "city is required");
+    }
+
+    #[tokio::test]
+    async fn test_tap_sees_raw_exchanges() {
+        let mut server = mockito::Server::new_async().await;
+        let ok = server
+            .mock("POST", "/models/gemini-3-flash-preview:generateContent")
+            .with_body(r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "Hi."}]}}]}"#)
+            .create_async()
+            .await;
+        let failing = server
+            .mock("POST", "/models/gemini-3-pro-preview:generateContent")
+            .with_status(500)
+            .with_body("boom")
+            .create_async()
+            .await;
+
+        let exchanges = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&exchanges);
+        let client = GeminiClient::new("test_key".to_string())
+            .unwrap()
+            .with_base_url(server.url())
+            .with_tap(move |exchange| seen.lock().unwrap().push(exchange.clone()));
+
+        client
+            .generate_content("Greet me", Some("Be brief."), "gemini-3-flash-preview", None, &[])
+            .await
+            .unwrap();
+        assert!(client
+            .generate_content("Greet me", None, "gemini-3-pro-preview", None, &[])
+            .await
+            .is_err());
+
+        ok.assert_async().await;
+        failing.assert_async().await;
+        let exchanges = exchanges.lock().unwrap();
+        assert_eq!(exchanges.len(), 2);
+        assert_eq!(exchanges[0].model, "gemini-3-flash-preview");
+        assert_eq!(exchanges[0].request["contents"][0]["parts"][0]["text"], "Greet me");
+        assert_eq!(exchanges[0].output_text().as_deref(), Some("Hi."));
+        assert!(exchanges[0].error.is_none());
+        assert!(exchanges[1].response.is_none());
+        assert!(exchanges[1].error.as_deref().unwrap().contains("boom"));
+    }
+
+    #[tokio::test]
+    async fn test_cassette_records_then_replays_offline() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/models/gemini-3-flash-preview:generateContent")
+            .with_body(r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "Hi."}]}}]}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let path = temp_path("client-cassette.json");
+
+        let recorder = GeminiClient::new("s3cret-key".to_string())
+            .unwrap()
+            .with_base_url(server.url())
+            .with_cassette(Cassette::record(&path).unwrap());
+        recorder
+            .generate_content("My key is s3cret-key", None, "gemini-3-flash-preview", None, &[])
+            .await
+            .unwrap();
+        mock.assert_async().await;
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("s3cret-key"));
+
+        // Nothing listens here, so only the cassette can answer
+        let player = GeminiClient::new("s3cret-key".to_string())
+            .unwrap()
+            .with_base_url("http://127.0.0.1:9")
+            .with_cassette(Cassette::replay(&path).unwrap());
+        let response = player
+            .generate_content("My key is s3cret-key", None, "gemini-3-flash-preview", None, &[])
+            .await
+            .unwrap();
+        assert_eq!(response.text, "Hi.");
+        assert!(player
+            .generate_content("Something else", None, "gemini-3-flash-preview", None, &[])
+            .await
+            .is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_generate_content_joins_parts_of_every_candidate() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/models/gemini-3-flash-preview:generateContent")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "generation_config": {"candidate_count": 3}
+            })))
+            .with_body(
+                r#"{"candidates": [
+                    {"content": {"role": "model", "parts": [{"text": "Hello, "}, {"text": "world."}]}},
+                    {"content": {"role": "model", "parts": [
+                        {"functionCall": {"name": "noop", "args": {}}}, {"text": "Hi there."}
+                    ]}},
+                    {"finishReason": "RECITATION"}
+                ]}"#,
+            )
+            .create_async()
+            .await;
+
+        let client = GeminiClient::new("test_key".to_string())
+            .unwrap()
+            .with_base_url(server.url());
+        let config = GenerationConfig {
+            candidate_count: Some(3),
+            ..Default::default()
+        };
+
+        let response = client
+            .generate_content("Greet me", None, "gemini-3-flash-preview", Some(config), &[])
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(response.text, "Hello, world.");
+        assert_eq!(response.candidates, vec!["Hello, world.", "Hi there."]);
+    }
+
+    #[tokio::test]
+    async fn test_generate_content_continues_after_max_tokens() {
+        let mut server = mockito::Server::new_async().await;
+        let path = "/models/gemini-3-flash-preview:generateContent";
+
+        // Continuation: history carries the truncated turn and the nudge
+        let rest = server
+            .mock("POST", path)
+            .match_body(mockito::Matcher::Regex("Continue exactly".to_string()))
+            .with_body(
+                r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": " lazy dog."}]}, "finishReason": "STOP"}],
+                    "usageMetadata": {"promptTokenCount": 30, "candidatesTokenCount": 4, "totalTokenCount": 34}}"#,
+            )
+            .create_async()
+            .await;
+        let first = server
+            .mock("POST", path)
+            .with_body(
+                r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "The quick brown fox jumps over the"}]}, "finishReason": "MAX_TOKENS"}],
+                    "usageMetadata": {"promptTokenCount": 10, "candidatesTokenCount": 8, "totalTokenCount": 18}}"#,
+            )
+            .expect(1)
+            .create_async()
+            .await;
+
+        let client = GeminiClient::new("test_key".to_string())
+            .unwrap()
+            .with_base_url(server.url());
+
+        let response = client
+            .generate_content_with_continuation("Write", None, "gemini-3-flash-preview", None, &[], Some(2))
+            .await
+            .unwrap();
+
+        first.assert_async().await;
+        rest.assert_async().await;
+        assert_eq!(response.text, "The quick brown fox jumps over the lazy dog.");
+        assert_eq!(response.candidates, vec![response.text.clone()]);
+        assert_eq!(response.finish_reason.as_deref(), Some("STOP"));
+        assert_eq!(response.continuations, 1);
+        assert_eq!(response.usage.total_token_count, 52);
+
+        // Without continuations the truncation is reported instead
+        let truncated = client
+            .generate_content_with_continuation("Write", None, "gemini-3-flash-preview", None, &[], Some(0))
+            .await
+            .unwrap();
+        assert_eq!(truncated.finish_reason.as_deref(), Some("MAX_TOKENS"));
+        assert_eq!(truncated.continuations, 0);
+    }
+
+    #[tokio::test]
+    async fn test_generate_content_continues_when_thinking_used_the_budget() {
+        let mut server = mockito::Server::new_async().await;
+        let path = "/models/gemini-3-flash-preview:generateContent";
+        let rest = server
+            .mock("POST", path)
+            .match_body(mockito::Matcher::Regex("Continue exactly".to_string()))
+            .with_body(r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "42"}]}, "finishReason": "STOP"}]}"#)
+            .create_async()
+            .await;
+        let first = server
+            .mock("POST", path)
+            .with_body(
+                r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "Adding up", "thought": true}]},
+                    "finishReason": "MAX_TOKENS", "avgLogprobs": -0.25}]}"#,
+            )
+            .expect(2)
+            .create_async()
+            .await;
+
+        let client = GeminiClient::new("test_key".to_string())
+            .unwrap()
+            .with_base_url(server.url());
+
+        let response = client
+            .generate_content_with_continuation("Sum", None, "gemini-3-flash-preview", None, &[], Some(1))
+            .await
+            .unwrap();
+        rest.assert_async().await;
+        assert_eq!(response.text, "42");
+        assert_eq!(response.candidates, ["42"]);
+        assert_eq!(response.thoughts.as_deref(), Some("Adding up"));
+        assert_eq!(response.avg_logprobs, Some(-0.25));
+
+        let err = client
+            .generate_content_with_continuation("Sum", None, "gemini-3-flash-preview", None, &[], Some(0))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, GeminiError::Truncated { continuations: 0 }));
+        first.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_generate_content_reads_metadata_from_the_answering_candidate() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/models/gemini-3-flash-preview:generateContent")
+            .with_body(
+                r#"{"candidates": [
+                    {"content": {"role": "model", "parts": []}, "finishReason": "OTHER", "avgLogprobs": -3.0},
+                    {"content": {"role": "model", "parts": [{"text": "Hello"}]}, "finishReason": "STOP", "avgLogprobs": -0.5}
+                ]}"#,
+            )
+            .create_async()
+            .await;
+
+        let client = GeminiClient::new("test_key".to_string())
+            .unwrap()
+            .with_base_url(server.url());
+        let config = GenerationConfig {
+            candidate_count: Some(2),
+            ..Default::default()
+        };
+        let response = client
+            .generate_content("Hi", None, "gemini-3-flash-preview", Some(config), &[])
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(response.text, "Hello");
+        assert_eq!(response.finish_reason.as_deref(), Some("STOP"));
+        assert_eq!(response.avg_logprobs, Some(-0.5));
+    }
+
+    #[tokio::test]
+    async fn test_generate_content_separates_thoughts() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/models/gemini-3-flash-preview:generateContent")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "generation_config": {"thinking_config": {"include_thoughts": true}}
+            })))
+            .with_body(
+                r#"{"candidates": [{"content": {"role": "model", "parts": [
+                        {"text": "The user wants a number.", "thought": true},
+                        {"text": "42", "thoughtSignature": "sig"}
+                    ]}, "finishReason": "STOP"}],
+                    "usageMetadata": {"promptTokenCount": 5, "candidatesTokenCount": 1, "thoughtsTokenCount": 30,
+                                      "cachedContentTokenCount": 4, "totalTokenCount": 36}}"#,
+            )
+            .create_async()
+            .await;
+
+        let client = GeminiClient::new("test_key".to_string())
+            .unwrap()
+            .with_base_url(server.url());
+        let config = GenerationConfig {
+            thinking_config: Some(ThinkingConfig {
+                thinking_budget: None,
+                include_thoughts: Some(true),
+            }),
+            ..Default::default()
+        };
+
+        let response = client
+            .generate_content("Pick a number", None, "gemini-3-flash-preview", Some(config), &[])
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(response.text, "42");
+        assert_eq!(response.thoughts.as_deref(), Some("The user wants a number."));
+        assert_eq!(response.usage.thoughts_token_count, 30);
+        assert_eq!(response.usage.cached_content_token_count, 4);
+    }
+
+    #[tokio::test]
+    async fn test_count_tokens() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/models/gemini-3-pro-preview:countTokens")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "generate_content_request": {
+                    "model": "models/gemini-3-pro-preview",
+                    "system_instruction": {"parts": [{"text": "Be brief."}]}
+                }
+            })))
+            .with_body(r#"{"totalTokens": 12}"#)
+            .create_async()
+            .await;
+
+        let client = GeminiClient::new("test_key".to_string())
+            .unwrap()
+            .with_base_url(server.url());
+        let tokens = client
+            .count_tokens("Hello there", Some("Be brief."), "gemini-3-pro-preview")
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(tokens, 12);
+    }
+
+    #[tokio::test]
+    async fn test_preflight_rejects_input_over_the_window() {
+        let mut server = mockito::Server::new_async().await;
+        let count = server
+            .mock("POST", "/models/gemini-3-flash-preview:countTokens")
+            .with_body(r#"{"totalTokens": 2500000}"#)
+            .create_async()
+            .await;
+        let generate = server
+            .mock("POST", "/models/gemini-3-flash-preview:generateContent")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let client = GeminiClient::new("test_key".to_string())
+            .unwrap()
+            .with_base_url(server.url());
+        let prompt = "word ".repeat(500_000);
+
+        let err = client
+            .generate_content(&prompt, None, "gemini-3-flash-preview", None, &[])
+            .await
+            .unwrap_err();
+
+        count.assert_async().await;
+        generate.assert_async().await;
+        assert!(matches!(
+            err,
+            GeminiError::InputTooLarge { tokens: 2_500_000, limit: 1_048_576, .. }
+        ));
+        let message = err.to_string();
+        assert!(message.contains("2500000 tokens"));
+        assert!(message.contains("at least 3 chunks"));
+    }
+
+    #[tokio::test]
+    async fn test_generate_content_reports_blocked_prompt() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/models/gemini-3-flash-preview:generateContent")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "safety_settings": [{"category": "HARM_CATEGORY_HARASSMENT", "threshold": "BLOCK_NONE"}]
+            })))
+            .with_body(
+                r#"{"promptFeedback": {"blockReason": "SAFETY", "safetyRatings": [
+                    {"category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "HIGH", "blocked": true}
+                ]}}"#,
+            )
+            .create_async()
+            .await;
+
+        let client = GeminiClient::new("test_key".to_string())
+            .unwrap()
+            .with_base_url(server.url());
+        let overrides = [SafetySetting {
+            category: HarmCategory::Harassment,
+            threshold: HarmBlockThreshold::BlockNone,
+        }];
+
+        let err = client
+            .generate_content("How do I ...", None, "gemini-3-flash-preview", None, &overrides)
+            .await
+            .unwrap_err();
+
+        mock.assert_async().await;
+        match err {
+            GeminiError::Blocked {
+                target,
+                reason,
+                ratings,
+            } => {
+                assert_eq!(target, safety::BlockTarget::Prompt);
+                assert_eq!(reason, "SAFETY");
+                assert_eq!(ratings.len(), 1);
+            }
+            other => panic!("expected Blocked, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_model_fetches_registry_for_unknown_ids() {
+        let mut server = mockito::Server::new_async().await;
+        let second_page = server
+            .mock("GET", "/models")
+            .match_query(mockito::Matcher::UrlEncoded("pageToken".into(), "p2+/=".into()))
+            .with_body(
+                r#"{"models": [{"name": "models/text-embedding-004", "inputTokenLimit": 2048,
+                    "supportedGenerationMethods": ["embedContent"]}]}"#,
+            )
+            .create_async()
+            .await;
+        let first_page = server
+            .mock("GET", "/models")
+            .match_query(mockito::Matcher::UrlEncoded("pageSize".into(), "1000".into()))
+            .with_body(
+                r#"{"models": [{"name": "models/gemini-2.5-flash-lite", "inputTokenLimit": 500000,
+                    "outputTokenLimit": 65536, "supportedGenerationMethods": ["generateContent"]}],
+                    "nextPageToken": "p2+/="}"#,
+            )
+            .create_async()
+            .await;
+
+        let cache = temp_path("client-models").join("models.json");
+        let client = GeminiClient::new("test_key".to_string())
+            .unwrap()
+            .with_base_url(server.url())
+            .with_models_cache(Some(cache.clone()));
+
+        // Aliases resolve without touching the network
+        let flash = client.resolve_model(None, "flash").await.unwrap();
+        assert_eq!(flash.id, client.registry().alias_target("flash").unwrap());
+
+        let lite = client
+            .resolve_model(Some("models/gemini-2.5-flash-lite"), "pro")
+            .await
+            .unwrap();
+        first_page.assert_async().await;
+        second_page.assert_async().await;
+        assert_eq!(lite.input_token_limit, 500_000);
+        assert_eq!(client.input_token_limit("gemini-2.5-flash-lite"), 500_000);
+        assert!(cache.exists());
+
+        let err = client.resolve_model(Some("text-embedding-004"), "pro").await.unwrap_err();
+        assert!(matches!(err, GeminiError::InvalidModel(_)));
+        let err = client.resolve_model(Some("gemini-2.5-flash"), "pro").await.unwrap_err();
+        assert!(err.to_string().contains("Did you mean: gemini-2.5-flash-lite?"));
+
+        let _ = std::fs::remove_dir_all(cache.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_model_refreshes_fresh_registry_for_new_ids() {
+        let mut server = mockito::Server::new_async().await;
+        let list = server
+            .mock("GET", "/models")
+            .match_query(mockito::Matcher::Any)
+            .with_body(
+                r#"{"models": [{"name": "models/gemini-9-pro", "inputTokenLimit": 2000000,
+                    "supportedGenerationMethods": ["generateContent"]}]}"#,
+            )
+            .create_async()
+            .await;
+
+        let client = GeminiClient::new("test_key".to_string())
+            .unwrap()
+            .with_base_url(server.url())
+            .with_models_cache(None);
+        client.models.write().unwrap().replace_models(
+            vec![ModelInfo::fallback("gemini-2.5-flash")],
+            RegistrySource::Api,
+            SystemTime::now(),
+        );
+        assert!(!client.registry().is_stale());
+
+        let model = client.resolve_model(Some("gemini-9-pro"), "flash").await.unwrap();
+        list.assert_async().await;
+        assert_eq!(model.input_token_limit, 2_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_stale_model_lists_are_refreshed() {
+        let mut server = mockito::Server::new_async().await;
+        let list = server
+            .mock("GET", "/models")
+            .match_query(mockito::Matcher::Any)
+            .with_body(
+                r#"{"models": [{"name": "models/gemini-9-pro", "inputTokenLimit": 2000000,
+                    "supportedGenerationMethods": ["generateContent"]}]}"#,
+            )
+            .expect(1)
+            .create_async()
+            .await;
+
+        let client = GeminiClient::new("test_key".to_string())
+            .unwrap()
+            .with_base_url(server.url())
+            .with_models_cache(None);
+        // Nothing fetched yet: unknown ids do that on demand
+        client.refresh_stale_models().await.unwrap();
+
+        client.models.write().unwrap().replace_models(
+            vec![ModelInfo::fallback("gemini-retired")],
+            RegistrySource::Cache,
+            SystemTime::now() - models::CACHE_TTL * 2,
+        );
+        client.refresh_stale_models().await.unwrap();
+        assert!(!client.registry().contains("gemini-retired"));
+        assert!(client.registry().contains("gemini-9-pro"));
+
+        // Fresh again
+        client.refresh_stale_models().await.unwrap();
+        list.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_generate_content_falls_back_on_quota_errors() {
+        let mut server = mockito::Server::new_async().await;
+        let pro = server
+            .mock("POST", "/models/gemini-3-pro-preview:generateContent")
+            .with_status(429)
+            .with_body(r#"{"error": {"status": "RESOURCE_EXHAUSTED"}}"#)
+            .create_async()
+            .await;
+        let flash = server
+            .mock("POST", "/models/gemini-3-flash-preview:generateContent")
+            .with_status(503)
+            .with_body(r#"{"error": {"status": "UNAVAILABLE"}}"#)
+            .create_async()
+            .await;
+        let lite = server
+            .mock("POST", "/models/gemini-2.5-flash-lite:generateContent")
+            .with_body(r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "Hi"}]}}]}"#)
+            .create_async()
+            .await;
+
+        let chain = "gemini-3-pro-preview -> gemini-3-flash-preview -> gemini-2.5-flash-lite".to_string();
+        let client = GeminiClient::new("test_key".to_string())
+            .unwrap()
+            .with_base_url(server.url())
+            .with_fallbacks(FallbackPolicy::new(&[chain], None).unwrap());
+
+        let response = client
+            .generate_content("Hello", None, "gemini-3-pro-preview", None, &[])
+            .await
+            .unwrap();
+
+        pro.assert_async().await;
+        flash.assert_async().await;
+        lite.assert_async().await;
+        assert_eq!(response.text, "Hi");
+        assert_eq!(response.model, "gemini-2.5-flash-lite");
+        assert_eq!(response.fallback_from.as_deref(), Some("gemini-3-pro-preview"));
+
+        // Models without a chain return the error as is
+        let err = client
+            .generate_content("Hello", None, "gemini-3-flash-preview", None, &[])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, GeminiError::ApiError { status: 503, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_send_request_rotates_keys_on_quota_errors() {
+        let mut server = mockito::Server::new_async().await;
+        let path = "/models/gemini-3-flash-preview:generateContent";
+        let exhausted = server
+            .mock("POST", path)
+            .match_header(API_KEY_HEADER, "key-a")
+            .with_status(429)
+            .with_body(r#"{"error": {"status": "RESOURCE_EXHAUSTED"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let healthy = server
+            .mock("POST", path)
+            .match_header(API_KEY_HEADER, "key-b")
+            .with_body(
+                r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "Hi"}]}}],
+                    "usageMetadata": {"promptTokenCount": 3, "candidatesTokenCount": 1, "totalTokenCount": 4}}"#,
+            )
+            .expect(2)
+            .create_async()
+            .await;
+
+        let keys = KeyPool::from_lookup(|key| {
+            (key == crate::gemini::keys::KEYS_ENV).then(|| "a=key-a, b=key-b".to_string())
+        })
+        .unwrap();
+        let client = GeminiClient::with_keys(keys).unwrap().with_base_url(server.url());
+
+        for _ in 0..2 {
+            let response = client
+                .generate_content("Hello", None, "gemini-3-flash-preview", None, &[])
+                .await
+                .unwrap();
+            assert_eq!(response.text, "Hi");
+        }
+
+        // The exhausted key sits out the second call
+        exhausted.assert_async().await;
+        healthy.assert_async().await;
+
+        let usage = client.key_usage();
+        assert_eq!((usage[0].label.as_str(), usage[0].errors), ("a", 1));
+        assert!(usage[0].cooling_down_secs.is_some());
+        assert_eq!((usage[1].requests, usage[1].prompt_tokens), (2, 6));
+    }
+
+    #[tokio::test]
+    async fn test_model_list_requests_rotate_past_rejected_keys() {
+        let mut server = mockito::Server::new_async().await;
+        let rejected = server
+            .mock("GET", "/models")
+            .match_query(mockito::Matcher::Any)
+            .match_header(API_KEY_HEADER, "key-a")
+            .with_status(401)
+            .with_body(r#"{"error": {"status": "UNAUTHENTICATED"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let listed = server
+            .mock("GET", "/models")
+            .match_query(mockito::Matcher::Any)
+            .match_header(API_KEY_HEADER, "key-b")
+            .with_body(r#"{"models": []}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let generate = server
+            .mock("POST", "/models/gemini-3-flash-preview:generateContent")
+            .match_header(API_KEY_HEADER, "key-b")
+            .with_body(r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "Hi"}]}}]}"#)
+            .create_async()
+            .await;
+
+        let keys = KeyPool::from_lookup(|key| {
+            (key == crate::gemini::keys::KEYS_ENV).then(|| "a=key-a, b=key-b".to_string())
+        })
+        .unwrap();
+        let client = GeminiClient::with_keys(keys)
+            .unwrap()
+            .with_base_url(server.url())
+            .with_models_cache(None);
+
+        client.refresh_models().await.unwrap();
+        rejected.assert_async().await;
+        listed.assert_async().await;
+
+        // The rejected key cools down for generation requests too
+        client
+            .generate_content("Hello", None, "gemini-3-flash-preview", None, &[])
+            .await
+            .unwrap();
+        generate.assert_async().await;
+        let usage = client.key_usage();
+        assert_eq!((usage[0].errors, usage[0].cooling_down_secs.is_some()), (1, true));
+        assert_eq!(usage[1].requests, 2);
+    }
+
+    #[tokio::test]
+    async fn test_send_request_reloads_rejected_key() {
+        let mut server = mockito::Server::new_async().await;
+        let path = "/models/gemini-3-flash-preview:generateContent";
+        let rejected = server
+            .mock("POST", path)
+            .match_header(API_KEY_HEADER, "old-key")
+            .with_status(401)
+            .with_body(r#"{"error": {"status": "UNAUTHENTICATED"}}"#)
+            .create_async()
+            .await;
+        let accepted = server
+            .mock("POST", path)
+            .match_header(API_KEY_HEADER, "new-key")
+            .with_body(r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "Hi"}]}}]}"#)
+            .create_async()
+            .await;
+
+        let key_file = temp_path("client-key");
+        std::fs::write(&key_file, "old-key").unwrap();
+        let keys = KeyPool::from_lookup(|name| {
+            (name == crate::gemini::keys::KEY_FILE_ENV).then(|| key_file.display().to_string())
+        })
+        .unwrap();
+        let client = GeminiClient::with_keys(keys).unwrap().with_base_url(server.url());
+
+        // The key is rotated on disk after the server started
+        std::fs::write(&key_file, "new-key").unwrap();
+        let response = client
+            .generate_content("Hello", None, "gemini-3-flash-preview", None, &[])
+            .await
+            .unwrap();
+        std::fs::remove_file(&key_file).unwrap();
+
+        rejected.assert_async().await;
+        accepted.assert_async().await;
+        assert_eq!(response.text, "Hi");
+    }
 }
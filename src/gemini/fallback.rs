@@ -0,0 +1,199 @@
+// Model fallback chains
+//
+// When a model is out of quota or overloaded, a request can be retried on
+// the next model of its chain, e.g. `pro -> flash -> lite`. Which error
+// classes trigger a fallback is configurable.
+
+use crate::error::{GeminiError, Result};
+
+/// Chains separated by `;`, models by `->`: `pro->flash->gemini-2.5-flash-lite`
+pub const CHAINS_ENV: &str = "GEMINI_MODEL_FALLBACKS";
+/// Error classes that trigger a fallback: `rate_limited`, `overloaded`, `server_error`
+pub const ON_ENV: &str = "GEMINI_FALLBACK_ON";
+const CHAINS_KEY: &str = "models.fallbacks";
+const ON_KEY: &str = "models.fallback_on";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// 429: quota or rate limit exhausted
+    RateLimited,
+    /// 503: model overloaded or temporarily unavailable
+    Overloaded,
+    /// 500, 502 and 504
+    ServerError,
+}
+
+impl ErrorClass {
+    pub fn of(error: &GeminiError) -> Option<Self> {
+        match error {
+            GeminiError::ApiError { status: 429, .. } => Some(Self::RateLimited),
+            GeminiError::ApiError { status: 503, .. } => Some(Self::Overloaded),
+            GeminiError::ApiError {
+                status: 500 | 502 | 504,
+                ..
+            } => Some(Self::ServerError),
+            _ => None,
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "rate_limited" | "429" => Some(Self::RateLimited),
+            "overloaded" | "503" => Some(Self::Overloaded),
+            "server_error" | "5xx" => Some(Self::ServerError),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FallbackPolicy {
+    /// Model names (ids or aliases); the first entry of each chain is the
+    /// model it applies to
+    chains: Vec<Vec<String>>,
+    on: Vec<ErrorClass>,
+}
+
+impl FallbackPolicy {
+    /// Policy from chains such as `pro -> flash` and error class names;
+    /// `on` defaults to rate_limited and overloaded
+    pub fn new(chains: &[String], on: Option<&[String]>) -> Result<Self> {
+        Self::parse(chains, on, (CHAINS_KEY, ON_KEY))
+    }
+
+    /// Like `new`, with errors naming `keys` (the chains and error class
+    /// settings), e.g. environment variables
+    pub fn parse(chains: &[String], on: Option<&[String]>, keys: (&str, &str)) -> Result<Self> {
+        let chains = chains
+            .iter()
+            .map(|chain| chain.trim())
+            .filter(|chain| !chain.is_empty())
+            .map(|chain| parse_chain(chain, keys.0))
+            .collect::<Result<Vec<_>>>()?;
+
+        let on = match on {
+            Some(names) => names
+                .iter()
+                .filter(|s| !s.trim().is_empty())
+                .map(|name| {
+                    ErrorClass::parse(name).ok_or_else(|| {
+                        GeminiError::ConfigError(format!(
+                            "Invalid {} entry '{}': expected rate_limited, overloaded or server_error",
+                            keys.1,
+                            name.trim()
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?,
+            None => vec![ErrorClass::RateLimited, ErrorClass::Overloaded],
+        };
+
+        Ok(Self { chains, on })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chains.is_empty()
+    }
+
+    /// Whether `error` should move the request to the next model
+    pub fn applies_to(&self, error: &GeminiError) -> bool {
+        ErrorClass::of(error).is_some_and(|class| self.on.contains(&class))
+    }
+
+    /// Models to try after `model`, resolved to ids with `resolve`. Names
+    /// that do not resolve are skipped.
+    pub fn fallbacks_for(&self, model: &str, resolve: impl Fn(&str) -> Option<String>) -> Vec<String> {
+        let Some(chain) = self
+            .chains
+            .iter()
+            .find(|chain| resolve(&chain[0]).is_some_and(|id| id == model))
+        else {
+            return Vec::new();
+        };
+
+        let mut fallbacks: Vec<String> = Vec::new();
+        for id in chain[1..].iter().filter_map(|name| resolve(name)) {
+            if id != model && !fallbacks.contains(&id) {
+                fallbacks.push(id);
+            }
+        }
+        fallbacks
+    }
+}
+
+fn parse_chain(chain: &str, key: &str) -> Result<Vec<String>> {
+    let models: Vec<String> = chain.split("->").map(|m| m.trim().to_string()).collect();
+    if models.len() < 2 || models.iter().any(String::is_empty) {
+        return Err(GeminiError::ConfigError(format!(
+            "Invalid {} chain '{}': expected model->fallback[->fallback...]",
+            key, chain
+        )));
+    }
+    Ok(models)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chains(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    fn resolve(name: &str) -> Option<String> {
+        match name {
+            "pro" => Some("gemini-3-pro-preview".to_string()),
+            "flash" => Some("gemini-3-flash-preview".to_string()),
+            "bad id" => None,
+            id => Some(id.to_string()),
+        }
+    }
+
+    fn api_error(status: u16) -> GeminiError {
+        GeminiError::ApiError {
+            status,
+            message: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_chains_resolve_aliases() {
+        let policy = FallbackPolicy::new(
+            &chains(&["pro -> flash -> bad id -> gemini-2.5-flash-lite", "flash->gemini-2.5-flash-lite"]),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            policy.fallbacks_for("gemini-3-pro-preview", resolve),
+            vec!["gemini-3-flash-preview", "gemini-2.5-flash-lite"]
+        );
+        assert_eq!(
+            policy.fallbacks_for("gemini-3-flash-preview", resolve),
+            vec!["gemini-2.5-flash-lite"]
+        );
+        assert!(policy.fallbacks_for("gemini-2.5-flash-lite", resolve).is_empty());
+    }
+
+    #[test]
+    fn test_error_classes() {
+        let policy = FallbackPolicy::new(&[], None).unwrap();
+        assert!(policy.is_empty());
+        assert!(policy.applies_to(&api_error(429)));
+        assert!(policy.applies_to(&api_error(503)));
+        assert!(!policy.applies_to(&api_error(500)));
+        assert!(!policy.applies_to(&GeminiError::AuthError(String::new())));
+
+        let policy = FallbackPolicy::new(&[], Some(&chains(&["server_error"]))).unwrap();
+        assert!(policy.applies_to(&api_error(502)));
+        assert!(!policy.applies_to(&api_error(429)));
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(FallbackPolicy::new(&chains(&["pro"]), None).is_err());
+        assert!(FallbackPolicy::new(&chains(&["pro->"]), None).is_err());
+        let err = FallbackPolicy::new(&[], Some(&chains(&["teapot"]))).unwrap_err();
+        assert!(err.to_string().contains("models.fallback_on entry 'teapot'"));
+    }
+}
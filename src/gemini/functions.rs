@@ -0,0 +1,149 @@
+// Function calling: caller-supplied handlers and the bounded tool-use loop types
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::gemini::types::{FunctionCallingMode, FunctionDeclaration, Tool};
+
+pub type HandlerFuture = Pin<Box<dyn Future<Output = Result<serde_json::Value, String>> + Send>>;
+type Handler = Arc<dyn Fn(serde_json::Value) -> HandlerFuture + Send + Sync>;
+
+/// Function declarations paired with the Rust handlers that execute them.
+///
+/// Handler errors are not fatal: they are sent back to the model as
+/// `{"error": "..."}` so it can correct its arguments or try another function.
+#[derive(Clone, Default)]
+pub struct FunctionRegistry {
+    functions: Vec<(FunctionDeclaration, Handler)>,
+}
+
+impl FunctionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<F, Fut>(&mut self, declaration: FunctionDeclaration, handler: F) -> &mut Self
+    where
+        F: Fn(serde_json::Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<serde_json::Value, String>> + Send + 'static,
+    {
+        let handler: Handler = Arc::new(move |args| Box::pin(handler(args)));
+        self.functions.retain(|(d, _)| d.name != declaration.name);
+        self.functions.push((declaration, handler));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.functions.iter().map(|(d, _)| d.name.clone()).collect()
+    }
+
+    /// The `tools` entry for a generateContent request
+    pub fn as_tool(&self) -> Tool {
+        Tool {
+            function_declarations: self.functions.iter().map(|(d, _)| d.clone()).collect(),
+        }
+    }
+
+    pub async fn call(&self, name: &str, args: serde_json::Value) -> Result<serde_json::Value, String> {
+        match self.functions.iter().find(|(d, _)| d.name == name) {
+            Some((_, handler)) => handler(args).await,
+            None => Err(format!("Unknown function: {}", name)),
+        }
+    }
+}
+
+/// Limits for `GeminiClient::generate_with_functions`
+#[derive(Debug, Clone)]
+pub struct FunctionLoopOptions {
+    /// Maximum number of model turns, including the final answer. The last
+    /// turn is sent with function calling disabled so the model must answer.
+    pub max_steps: u32,
+    /// Total token budget across all turns. Checked between turns: once it
+    /// is spent, one final turn without functions asks for the answer.
+    pub max_total_tokens: Option<u32>,
+    /// Whether the model may, must or must not call functions
+    pub mode: FunctionCallingMode,
+}
+
+impl Default for FunctionLoopOptions {
+    fn default() -> Self {
+        Self {
+            max_steps: 8,
+            max_total_tokens: None,
+            mode: FunctionCallingMode::Auto,
+        }
+    }
+}
+
+/// One executed function call, kept for tracing
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct FunctionCallRecord {
+    pub step: u32,
+    pub name: String,
+    pub args: serde_json::Value,
+    pub response: serde_json::Value,
+    pub is_error: bool,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LoopStopReason {
+    /// The model answered with text on its own
+    Completed,
+    /// `max_steps` was reached and the answer was forced
+    StepLimit,
+    /// `max_total_tokens` was spent and the answer was forced
+    TokenBudget,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn declaration(name: &str) -> FunctionDeclaration {
+        FunctionDeclaration {
+            name: name.to_string(),
+            description: format!("{} function", name),
+            parameters: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registry_dispatches_by_name() {
+        let mut registry = FunctionRegistry::new();
+        registry
+            .register(declaration("echo"), |args| async move { Ok(args) })
+            .register(declaration("fail"), |_| async { Err("boom".to_string()) });
+
+        assert_eq!(registry.names(), vec!["echo", "fail"]);
+        assert_eq!(
+            registry.call("echo", serde_json::json!({"a": 1})).await,
+            Ok(serde_json::json!({"a": 1}))
+        );
+        assert_eq!(registry.call("fail", serde_json::Value::Null).await, Err("boom".to_string()));
+        assert!(registry.call("missing", serde_json::Value::Null).await.is_err());
+    }
+
+    #[test]
+    fn test_calling_mode_serialize() {
+        let modes = [FunctionCallingMode::Auto, FunctionCallingMode::Any, FunctionCallingMode::None];
+        let json = serde_json::to_string(&modes).unwrap();
+        assert_eq!(json, r#"["AUTO","ANY","NONE"]"#);
+    }
+
+    #[test]
+    fn test_register_replaces_same_name() {
+        let mut registry = FunctionRegistry::new();
+        registry.register(declaration("a"), |_| async { Ok(serde_json::Value::Null) });
+        registry.register(declaration("a"), |_| async { Ok(serde_json::Value::Bool(true)) });
+        assert_eq!(registry.as_tool().function_declarations.len(), 1);
+    }
+}
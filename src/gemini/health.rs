@@ -0,0 +1,208 @@
+// Deferred connectivity tracking for the Gemini API
+//
+// The server starts without touching the network. Health is derived from a
+// cheap `models.list` probe that runs in the background when the cached state
+// goes stale, and from the outcome of real tool calls in between.
+
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::error::GeminiError;
+use crate::gemini::backend::GenerationBackend;
+
+/// How long a probe or call outcome is trusted before re-probing
+pub const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    /// No probe or call has completed yet
+    Unknown,
+    Healthy,
+    /// Reachable but failing, e.g. quota exhausted or server errors
+    Degraded,
+    /// Network failure or timeout
+    Unavailable,
+    /// The API key was rejected
+    Unauthorized,
+}
+
+/// Snapshot of the current health state, returned by `ping` and `gemini-status`
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub checked_secs_ago: Option<u64>,
+    pub latency_ms: Option<u64>,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    pub check_in_progress: bool,
+}
+
+#[derive(Debug)]
+struct HealthState {
+    status: HealthStatus,
+    checked_at: Option<Instant>,
+    latency: Option<Duration>,
+    consecutive_failures: u32,
+    last_error: Option<String>,
+}
+
+pub struct HealthMonitor {
+    backend: Arc<dyn GenerationBackend>,
+    state: RwLock<HealthState>,
+    probe_lock: Mutex<()>,
+    stale_after: Duration,
+}
+
+impl HealthMonitor {
+    pub fn new(backend: Arc<dyn GenerationBackend>, stale_after: Duration) -> Self {
+        Self {
+            backend,
+            state: RwLock::new(HealthState {
+                status: HealthStatus::Unknown,
+                checked_at: None,
+                latency: None,
+                consecutive_failures: 0,
+                last_error: None,
+            }),
+            probe_lock: Mutex::new(()),
+            stale_after,
+        }
+    }
+
+    pub fn report(&self) -> HealthReport {
+        let state = self.state.read().unwrap();
+        HealthReport {
+            status: state.status,
+            checked_secs_ago: state.checked_at.map(|t| t.elapsed().as_secs()),
+            latency_ms: state.latency.map(|d| d.as_millis() as u64),
+            consecutive_failures: state.consecutive_failures,
+            last_error: state.last_error.clone(),
+            check_in_progress: self.probe_lock.try_lock().is_err(),
+        }
+    }
+
+    pub fn is_stale(&self) -> bool {
+        let state = self.state.read().unwrap();
+        state
+            .checked_at
+            .is_none_or(|t| t.elapsed() >= self.stale_after)
+    }
+
+    /// Starts a background probe if the cached state is stale. Never blocks.
+    pub fn refresh_if_stale(self: &Arc<Self>) {
+        if !self.is_stale() {
+            return;
+        }
+
+        let monitor = Arc::clone(self);
+        tokio::spawn(async move {
+            // Another probe is already running; its result will do
+            let Ok(_guard) = monitor.probe_lock.try_lock() else {
+                return;
+            };
+            monitor.run_probe().await;
+        });
+    }
+
+    /// Runs a probe now and waits for its result.
+    pub async fn check(&self) -> HealthReport {
+        {
+            let _guard = self.probe_lock.lock().await;
+            self.run_probe().await;
+        }
+        self.report()
+    }
+
+    async fn run_probe(&self) {
+        debug!("Running Gemini health probe");
+        let started = Instant::now();
+        match self.backend.test_connection().await {
+            Ok(()) => {
+                self.record_success();
+                self.state.write().unwrap().latency = Some(started.elapsed());
+            }
+            Err(e) => self.record_failure(&e),
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.write().unwrap();
+        state.status = HealthStatus::Healthy;
+        state.checked_at = Some(Instant::now());
+        state.consecutive_failures = 0;
+        state.last_error = None;
+    }
+
+    pub fn record_failure(&self, error: &GeminiError) {
+        let status = classify(error);
+        warn!("Gemini health: {:?} ({})", status, error);
+
+        let mut state = self.state.write().unwrap();
+        state.status = status;
+        state.checked_at = Some(Instant::now());
+        state.consecutive_failures += 1;
+        state.last_error = Some(error.to_string());
+    }
+}
+
+fn classify(error: &GeminiError) -> HealthStatus {
+    match error {
+        GeminiError::AuthError(_) => HealthStatus::Unauthorized,
+        GeminiError::HttpClient(e) if e.is_connect() || e.is_timeout() => HealthStatus::Unavailable,
+        _ => HealthStatus::Degraded,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemini::client::GeminiClient;
+
+    fn monitor() -> HealthMonitor {
+        let client = Arc::new(GeminiClient::new("test_key".to_string()).unwrap());
+        HealthMonitor::new(client, DEFAULT_STALE_AFTER)
+    }
+
+    #[test]
+    fn test_initial_state_is_unknown_and_stale() {
+        let monitor = monitor();
+        let report = monitor.report();
+        assert_eq!(report.status, HealthStatus::Unknown);
+        assert_eq!(report.checked_secs_ago, None);
+        assert!(monitor.is_stale());
+    }
+
+    #[test]
+    fn test_record_failure_then_success() {
+        let monitor = monitor();
+
+        monitor.record_failure(&GeminiError::ApiError {
+            status: 429,
+            message: "quota".to_string(),
+        });
+        monitor.record_failure(&GeminiError::AuthError("bad key".to_string()));
+        let report = monitor.report();
+        assert_eq!(report.status, HealthStatus::Unauthorized);
+        assert_eq!(report.consecutive_failures, 2);
+        assert!(!monitor.is_stale());
+
+        monitor.record_success();
+        let report = monitor.report();
+        assert_eq!(report.status, HealthStatus::Healthy);
+        assert_eq!(report.consecutive_failures, 0);
+        assert_eq!(report.last_error, None);
+    }
+
+    #[test]
+    fn test_classify_api_error_as_degraded() {
+        let error = GeminiError::ApiError {
+            status: 503,
+            message: "overloaded".to_string(),
+        };
+        assert_eq!(classify(&error), HealthStatus::Degraded);
+    }
+}
@@ -0,0 +1,577 @@
+// API key pool
+//
+// Several project keys can share the load. Requests go to the keys in turn;
+// a key that hits its quota or is rejected cools down while the others take
+// over. Keys are only ever shown by label, never by value.
+
+use serde::Serialize;
+use std::fmt;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::error::{GeminiError, Result};
+use crate::gemini::types::UsageMetadata;
+
+pub const KEY_ENV: &str = "GEMINI_API_KEY";
+/// Several keys separated by commas or whitespace, each optionally `label=key`
+pub const KEYS_ENV: &str = "GEMINI_API_KEYS";
+/// File with one key per line, each optionally `label=key`; `#` starts a comment
+pub const KEYS_FILE_ENV: &str = "GEMINI_API_KEYS_FILE";
+/// File holding a single key, e.g. a container secret
+pub const KEY_FILE_ENV: &str = "GEMINI_API_KEY_FILE";
+/// Shell command printing a key, e.g. `pass show gemini`
+pub const KEY_COMMAND_ENV: &str = "GEMINI_API_KEY_COMMAND";
+/// Secret Service account holding a key (service `gemini-mcp`)
+pub const KEYRING_ENV: &str = "GEMINI_API_KEY_KEYRING";
+/// Seconds a key sits out after a quota error
+pub const COOLDOWN_ENV: &str = "GEMINI_KEY_COOLDOWN";
+
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(60);
+const KEYRING_SERVICE: &str = "gemini-mcp";
+/// Rejected keys are unlikely to recover soon
+const AUTH_COOLDOWN_FACTOR: u32 = 10;
+/// Limit for key commands, so a hung password manager cannot block a request
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where a key is read from, so it can be read again after a 401
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeySource {
+    File(PathBuf),
+    Command(String),
+    /// Secret Service account, looked up with `secret-tool`
+    Keyring(String),
+}
+
+impl KeySource {
+    fn label(&self) -> &'static str {
+        match self {
+            KeySource::File(_) => "file",
+            KeySource::Command(_) => "command",
+            KeySource::Keyring(_) => "keyring",
+        }
+    }
+
+    /// Reads the key; only the first non-empty line counts
+    pub fn read(&self) -> Result<String> {
+        let output = match self {
+            KeySource::File(path) => std::fs::read_to_string(path).map_err(|e| {
+                GeminiError::ConfigError(format!("Cannot read API key file '{}': {}", path.display(), e))
+            })?,
+            KeySource::Command(command) => run(shell(command), "api_key_command", COMMAND_TIMEOUT)?,
+            KeySource::Keyring(account) => {
+                let mut command = Command::new("secret-tool");
+                command.args(["lookup", "service", KEYRING_SERVICE, "account", account]);
+                run(command, "Secret Service lookup", COMMAND_TIMEOUT)?
+            }
+        };
+
+        output
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .map(str::to_string)
+            .ok_or_else(|| GeminiError::ConfigError(format!("API key from {} is empty", self.label())))
+    }
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// Runs a command and returns its stdout, killing it after `timeout`.
+/// Stderr is discarded, since password managers may print hints about the
+/// secret there.
+fn run(mut command: Command, what: &str, timeout: Duration) -> Result<String> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| GeminiError::ConfigError(format!("{} failed to start: {}", what, e)))?;
+
+    // Read on another thread so a full pipe cannot stall the child
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        stdout.read_to_end(&mut buf).map(|_| buf)
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(GeminiError::ConfigError(format!(
+                    "{} timed out after {}s",
+                    what,
+                    timeout.as_secs_f32()
+                )));
+            }
+            Err(e) => return Err(GeminiError::ConfigError(format!("{} failed: {}", what, e))),
+        }
+    };
+    if !status.success() {
+        return Err(GeminiError::ConfigError(format!("{} failed ({})", what, status)));
+    }
+
+    let stdout = reader
+        .join()
+        .ok()
+        .and_then(|read| read.ok())
+        .ok_or_else(|| GeminiError::ConfigError(format!("Cannot read the output of {}", what)))?;
+    String::from_utf8(stdout).map_err(|_| GeminiError::ConfigError(format!("{} printed invalid UTF-8", what)))
+}
+
+/// A key handed out for one request
+#[derive(Clone)]
+pub struct KeyLease {
+    index: usize,
+    label: String,
+    secret: String,
+}
+
+impl KeyLease {
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn secret(&self) -> &str {
+        &self.secret
+    }
+}
+
+impl fmt::Debug for KeyLease {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyLease").field("label", &self.label).finish_non_exhaustive()
+    }
+}
+
+/// Usage of one key, for `gemini-status`
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyUsage {
+    pub label: String,
+    pub requests: u64,
+    pub errors: u64,
+    pub prompt_tokens: u64,
+    pub response_tokens: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cooling_down_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct KeyState {
+    requests: u64,
+    errors: u64,
+    prompt_tokens: u64,
+    response_tokens: u64,
+    cooldown_until: Option<Instant>,
+    last_error: Option<String>,
+}
+
+struct PooledKey {
+    label: String,
+    secret: RwLock<String>,
+    /// `None` for keys given literally
+    source: Option<KeySource>,
+    state: Mutex<KeyState>,
+}
+
+/// A configured key before it joins the pool
+struct KeySpec {
+    label: String,
+    secret: String,
+    source: Option<KeySource>,
+}
+
+pub struct KeyPool {
+    keys: Vec<PooledKey>,
+    next: AtomicUsize,
+    cooldown: Duration,
+}
+
+impl fmt::Debug for KeyPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyPool")
+            .field("keys", &self.labels())
+            .field("cooldown", &self.cooldown)
+            .finish()
+    }
+}
+
+impl KeyPool {
+    /// Pool holding a single key
+    pub fn single(key: String) -> Result<Self> {
+        let spec = KeySpec {
+            label: "key-1".to_string(),
+            secret: key,
+            source: None,
+        };
+        Self::new(vec![spec], DEFAULT_COOLDOWN)
+    }
+
+    /// Keys from `GEMINI_API_KEYS`, `GEMINI_API_KEYS_FILE`, `GEMINI_API_KEY`,
+    /// `GEMINI_API_KEY_FILE`, `GEMINI_API_KEY_COMMAND` and the keyring
+    pub fn from_env() -> Result<Self> {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut specs = Vec::new();
+        if let Some(spec) = lookup(KEYS_ENV) {
+            specs.extend(spec.split([',', ' ', '\n', '\t']).map(str::to_string));
+        }
+        if let Some(path) = lookup(KEYS_FILE_ENV).filter(|p| !p.trim().is_empty()) {
+            let contents = std::fs::read_to_string(path.trim()).map_err(|e| {
+                GeminiError::ConfigError(format!("Cannot read {} '{}': {}", KEYS_FILE_ENV, path, e))
+            })?;
+            specs.extend(
+                contents
+                    .lines()
+                    .map(|line| line.split('#').next().unwrap_or_default().to_string()),
+            );
+        }
+        if let Some(key) = lookup(KEY_ENV) {
+            specs.push(key);
+        }
+
+        let mut keys: Vec<KeySpec> = Vec::new();
+        for spec in specs.iter().map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            let (label, secret) = match spec.split_once('=') {
+                Some((label, secret)) => (label.trim().to_string(), secret.trim().to_string()),
+                None => (format!("key-{}", keys.len() + 1), spec.to_string()),
+            };
+            push_unique(&mut keys, label, secret, None);
+        }
+
+        let sources = [
+            lookup(KEY_FILE_ENV).map(|path| KeySource::File(PathBuf::from(path.trim()))),
+            lookup(KEY_COMMAND_ENV).map(KeySource::Command),
+            lookup(KEYRING_ENV).map(|account| KeySource::Keyring(account.trim().to_string())),
+        ];
+        for source in sources.into_iter().flatten() {
+            let secret = source.read()?;
+            push_unique(&mut keys, source.label().to_string(), secret, Some(source));
+        }
+
+        if keys.is_empty() {
+            return Err(GeminiError::ConfigError(format!(
+                "No API key configured: set {}, {}, {}, {}, {} or {}",
+                KEY_ENV, KEYS_ENV, KEYS_FILE_ENV, KEY_FILE_ENV, KEY_COMMAND_ENV, KEYRING_ENV
+            )));
+        }
+
+        let cooldown = match lookup(COOLDOWN_ENV) {
+            Some(value) => value
+                .trim()
+                .parse::<u64>()
+                .map(Duration::from_secs)
+                .map_err(|_| {
+                    GeminiError::ConfigError(format!(
+                        "Invalid {} '{}': expected seconds",
+                        COOLDOWN_ENV, value
+                    ))
+                })?,
+            None => DEFAULT_COOLDOWN,
+        };
+
+        Self::new(keys, cooldown)
+    }
+
+    fn new(keys: Vec<KeySpec>, cooldown: Duration) -> Result<Self> {
+        if keys.iter().any(|key| key.secret.trim().is_empty()) {
+            return Err(GeminiError::ConfigError("API key is empty".to_string()));
+        }
+        Ok(Self {
+            keys: keys
+                .into_iter()
+                .map(|key| PooledKey {
+                    label: key.label,
+                    secret: RwLock::new(key.secret),
+                    source: key.source,
+                    state: Mutex::new(KeyState::default()),
+                })
+                .collect(),
+            next: AtomicUsize::new(0),
+            cooldown,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Always false: construction fails without at least one key
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn labels(&self) -> Vec<&str> {
+        self.keys.iter().map(|k| k.label.as_str()).collect()
+    }
+
+    /// Next key in turn that is not cooling down; when all are, the one
+    /// that recovers first
+    pub fn lease(&self) -> KeyLease {
+        let now = Instant::now();
+        let start = self.next.load(Ordering::Relaxed);
+        let cooldown_until = |index: usize| {
+            self.keys[index]
+                .state
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .cooldown_until
+                .filter(|until| *until > now)
+        };
+
+        let index = (0..self.keys.len())
+            .map(|offset| (start + offset) % self.keys.len())
+            .find(|&index| cooldown_until(index).is_none())
+            .unwrap_or_else(|| {
+                (0..self.keys.len())
+                    .min_by_key(|&index| cooldown_until(index))
+                    .unwrap_or(0)
+            });
+
+        self.next.store(index + 1, Ordering::Relaxed);
+
+        let key = &self.keys[index];
+        KeyLease {
+            index,
+            label: key.label.clone(),
+            secret: key.secret.read().unwrap_or_else(|e| e.into_inner()).clone(),
+        }
+    }
+
+    pub fn record_success(&self, lease: &KeyLease, usage: Option<&UsageMetadata>) {
+        let mut state = self.state(lease);
+        state.requests += 1;
+        state.cooldown_until = None;
+        if let Some(usage) = usage {
+            state.prompt_tokens += usage.prompt_token_count as u64;
+            state.response_tokens += usage.candidates_token_count as u64;
+        }
+    }
+
+    /// Records a failed request. Returns true when the error is specific to
+    /// the key, which then cools down, and another key may succeed.
+    pub fn record_failure(&self, lease: &KeyLease, error: &GeminiError) -> bool {
+        let cooldown = match error {
+            GeminiError::ApiError { status: 429, .. } => Some(self.cooldown),
+            GeminiError::AuthError(_) => Some(self.cooldown * AUTH_COOLDOWN_FACTOR),
+            _ => None,
+        };
+
+        let mut state = self.state(lease);
+        state.requests += 1;
+        state.errors += 1;
+        state.last_error = Some(match error {
+            GeminiError::ApiError { status, .. } => format!("API error ({})", status),
+            GeminiError::AuthError(_) => "authentication failed".to_string(),
+            other => other.to_string(),
+        });
+        if let Some(cooldown) = cooldown {
+            state.cooldown_until = Some(Instant::now() + cooldown);
+        }
+        cooldown.is_some() && self.keys.len() > 1
+    }
+
+    /// Reads a rejected key again from its file, command or keyring.
+    /// Returns true when the value changed and the request is worth retrying.
+    pub async fn reload(&self, lease: &KeyLease) -> bool {
+        let key = &self.keys[lease.index];
+        let Some(source) = key.source.clone() else {
+            return false;
+        };
+
+        let secret = match tokio::task::spawn_blocking(move || source.read()).await {
+            Ok(Ok(secret)) => secret,
+            Ok(Err(e)) => {
+                warn!("Could not reload API key {}: {}", key.label, e);
+                return false;
+            }
+            Err(_) => return false,
+        };
+        if secret == lease.secret {
+            return false;
+        }
+
+        info!("Reloaded API key {}", key.label);
+        *key.secret.write().unwrap_or_else(|e| e.into_inner()) = secret;
+        self.state(lease).cooldown_until = None;
+        true
+    }
+
+    pub fn usage(&self) -> Vec<KeyUsage> {
+        let now = Instant::now();
+        self.keys
+            .iter()
+            .map(|key| {
+                let state = key.state.lock().unwrap_or_else(|e| e.into_inner());
+                KeyUsage {
+                    label: key.label.clone(),
+                    requests: state.requests,
+                    errors: state.errors,
+                    prompt_tokens: state.prompt_tokens,
+                    response_tokens: state.response_tokens,
+                    cooling_down_secs: state
+                        .cooldown_until
+                        .filter(|until| *until > now)
+                        .map(|until| (until - now).as_secs().max(1)),
+                    last_error: state.last_error.clone(),
+                }
+            })
+            .collect()
+    }
+
+    fn state(&self, lease: &KeyLease) -> std::sync::MutexGuard<'_, KeyState> {
+        self.keys[lease.index]
+            .state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn push_unique(keys: &mut Vec<KeySpec>, label: String, secret: String, source: Option<KeySource>) {
+    if !keys.iter().any(|key| key.secret == secret) {
+        keys.push(KeySpec { label, secret, source });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{env, temp_path};
+
+    fn quota_error() -> GeminiError {
+        GeminiError::ApiError {
+            status: 429,
+            message: "RESOURCE_EXHAUSTED".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_keys_from_env_and_file() {
+        let path = temp_path("keys");
+        std::fs::write(&path, "# team keys\nteam=AIza-team\n\nAIza-one  # duplicate\n").unwrap();
+
+        let pool = KeyPool::from_lookup(env(&[
+            (KEYS_ENV, "AIza-one, ci=AIza-ci"),
+            (KEYS_FILE_ENV, path.to_str().unwrap()),
+            (KEY_ENV, "AIza-single"),
+        ]))
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(pool.labels(), vec!["key-1", "ci", "team", "key-4"]);
+        assert!(!format!("{:?}", pool).contains("AIza"));
+        assert!(!format!("{:?}", pool.lease()).contains("AIza"));
+
+        assert!(KeyPool::from_lookup(env(&[])).is_err());
+        assert!(KeyPool::from_lookup(env(&[(KEY_ENV, "k"), (COOLDOWN_ENV, "soon")])).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_keys_from_command() {
+        let pool = KeyPool::from_lookup(env(&[(KEY_COMMAND_ENV, "printf '\\nAIza-cmd\\n'")])).unwrap();
+        assert_eq!(pool.labels(), vec!["command"]);
+        assert_eq!(pool.lease().secret(), "AIza-cmd");
+
+        let err = KeyPool::from_lookup(env(&[(KEY_COMMAND_ENV, "exit 3")])).unwrap_err();
+        assert!(err.to_string().contains("api_key_command failed"));
+
+        let started = Instant::now();
+        let err = run(shell("sleep 5"), "api_key_command", Duration::from_millis(100)).unwrap_err();
+        assert!(matches!(err, GeminiError::ConfigError(_)));
+        assert!(err.to_string().contains("api_key_command timed out after 0.1s"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_reload_rereads_key_file() {
+        let path = temp_path("key");
+        std::fs::write(&path, "old-key\n").unwrap();
+        let pool = KeyPool::from_lookup(env(&[(KEY_FILE_ENV, path.to_str().unwrap())])).unwrap();
+
+        let lease = pool.lease();
+        assert_eq!((lease.label(), lease.secret()), ("file", "old-key"));
+        assert!(!pool.reload(&lease).await);
+
+        std::fs::write(&path, "new-key\n").unwrap();
+        assert!(pool.reload(&lease).await);
+        assert_eq!(pool.lease().secret(), "new-key");
+
+        std::fs::remove_file(&path).unwrap();
+        let missing = KeyPool::from_lookup(env(&[(KEY_FILE_ENV, path.to_str().unwrap())]));
+        assert!(missing.is_err());
+
+        // Literal keys have nowhere to be reloaded from
+        let literal = KeyPool::single("k".to_string()).unwrap();
+        assert!(!literal.reload(&literal.lease()).await);
+    }
+
+    #[test]
+    fn test_rotation_and_cooldown() {
+        let pool = KeyPool::from_lookup(env(&[(KEYS_ENV, "a=k1,b=k2,c=k3")])).unwrap();
+
+        let first = pool.lease();
+        assert_eq!(first.label(), "a");
+        assert_eq!(pool.lease().label(), "b");
+
+        // A quota error benches the key; the others keep rotating
+        assert!(pool.record_failure(&first, &quota_error()));
+        let labels: Vec<String> = (0..4).map(|_| pool.lease().label().to_string()).collect();
+        assert_eq!(labels, vec!["c", "b", "c", "b"]);
+
+        // Errors unrelated to the key do not bench it
+        let other = pool.lease();
+        assert!(!pool.record_failure(&other, &GeminiError::EmptyResponse));
+
+        let usage = pool.usage();
+        assert!(usage[0].cooling_down_secs.is_some());
+        assert_eq!(usage[0].last_error.as_deref(), Some("API error (429)"));
+        assert!(!serde_json::to_string(&usage).unwrap().contains("k1"));
+    }
+
+    #[test]
+    fn test_usage_is_tracked_per_key() {
+        let pool = KeyPool::single("only".to_string()).unwrap();
+        let lease = pool.lease();
+        let usage = UsageMetadata {
+            prompt_token_count: 10,
+            candidates_token_count: 5,
+            total_token_count: 15,
+            ..Default::default()
+        };
+        pool.record_success(&lease, Some(&usage));
+        pool.record_success(&lease, Some(&usage));
+
+        // With a single key there is nothing to rotate to
+        assert!(!pool.record_failure(&lease, &quota_error()));
+        assert_eq!(pool.lease().label(), "key-1");
+
+        let usage = &pool.usage()[0];
+        assert_eq!((usage.requests, usage.errors), (3, 1));
+        assert_eq!((usage.prompt_tokens, usage.response_tokens), (20, 10));
+    }
+}
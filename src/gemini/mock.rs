@@ -0,0 +1,329 @@
+// Scripted backend for offline tests
+//
+// `MockBackend` answers each generation request with the next scripted
+// reply and records what it was asked, so tests can drive tools and the MCP
+// server end to end and assert on the prompts they sent. Function calls in
+// a script are executed through the real `FunctionRegistry` handlers.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::error::{GeminiError, Result};
+use crate::gemini::backend::{BackendFuture, GenerationBackend};
+use crate::gemini::functions::{FunctionCallRecord, FunctionLoopOptions, FunctionRegistry, LoopStopReason};
+use crate::gemini::models::{self, ModelInfo, ModelRegistry};
+use crate::gemini::types::*;
+
+const FINISH_STOP: &str = "STOP";
+
+/// One scripted model turn
+#[derive(Debug)]
+pub enum MockReply {
+    Text(String),
+    /// Function calls, honoured by `generate_with_functions` only
+    FunctionCalls(Vec<FunctionCall>),
+    Error(GeminiError),
+}
+
+/// A generation request as the mock received it
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub model: String,
+    pub system: Option<String>,
+    pub contents: Vec<Content>,
+    pub config: Option<GenerationConfig>,
+}
+
+impl MockRequest {
+    /// Answer text of the first turn, the prompt a tool rendered
+    pub fn prompt(&self) -> String {
+        self.contents
+            .first()
+            .map(|content| content.parts.iter().filter_map(Part::answer_text).collect())
+            .unwrap_or_default()
+    }
+}
+
+pub struct MockBackend {
+    registry: ModelRegistry,
+    replies: Mutex<VecDeque<MockReply>>,
+    requests: Mutex<Vec<MockRequest>>,
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockBackend {
+    /// Backend with no replies, resolving the built-in model aliases
+    pub fn new() -> Self {
+        Self {
+            registry: ModelRegistry::builtin(models::DEFAULT_PRO_MODEL, models::DEFAULT_FLASH_MODEL),
+            replies: Mutex::new(VecDeque::new()),
+            requests: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn with_reply(self, text: impl Into<String>) -> Self {
+        self.push(MockReply::Text(text.into()));
+        self
+    }
+
+    pub fn with_function_call(self, name: impl Into<String>, args: serde_json::Value) -> Self {
+        self.push(MockReply::FunctionCalls(vec![FunctionCall {
+            id: None,
+            name: name.into(),
+            args,
+        }]));
+        self
+    }
+
+    pub fn with_error(self, error: GeminiError) -> Self {
+        self.push(MockReply::Error(error));
+        self
+    }
+
+    /// Appends a reply, also after the backend has been shared
+    pub fn push(&self, reply: MockReply) {
+        self.replies.lock().unwrap_or_else(|e| e.into_inner()).push_back(reply);
+    }
+
+    /// Requests received so far, oldest first
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Scripted replies not yet used
+    pub fn remaining(&self) -> usize {
+        self.replies.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Records a request and takes the reply to it
+    fn next(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        contents: &[Content],
+        config: &Option<GenerationConfig>,
+    ) -> Result<MockReply> {
+        self.requests.lock().unwrap_or_else(|e| e.into_inner()).push(MockRequest {
+            model: model.to_string(),
+            system: system.map(str::to_string),
+            contents: contents.to_vec(),
+            config: config.clone(),
+        });
+        match self.replies.lock().unwrap_or_else(|e| e.into_inner()).pop_front() {
+            Some(MockReply::Error(e)) => Err(e),
+            Some(reply) => Ok(reply),
+            None => Err(GeminiError::ConfigError(
+                "MockBackend has no scripted reply left".to_string(),
+            )),
+        }
+    }
+}
+
+impl GenerationBackend for MockBackend {
+    fn resolve_model<'a>(&'a self, requested: Option<&'a str>, default_alias: &'a str) -> BackendFuture<'a, ModelInfo> {
+        let name = requested
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .unwrap_or(default_alias);
+        let model = self.registry.resolve(name).map_err(GeminiError::InvalidModel);
+        Box::pin(async move { model })
+    }
+
+    fn generate_content_with_continuation<'a>(
+        &'a self,
+        prompt: &'a str,
+        system: Option<&'a str>,
+        model: &'a str,
+        config: Option<GenerationConfig>,
+        _safety: &'a [SafetySetting],
+        _max_continuations: Option<u32>,
+    ) -> BackendFuture<'a, GenerationResponse> {
+        Box::pin(async move {
+            let text = match self.next(model, system, &[Content::user(prompt)], &config)? {
+                MockReply::Text(text) => text,
+                reply => {
+                    return Err(GeminiError::ConfigError(format!(
+                        "MockBackend reply {:?} needs generate_with_functions",
+                        reply
+                    )))
+                }
+            };
+            Ok(GenerationResponse {
+                candidates: vec![text.clone()],
+                text,
+                usage: UsageMetadata::default(),
+                safety_ratings: Vec::new(),
+                finish_reason: Some(FINISH_STOP.to_string()),
+                continuations: 0,
+                avg_logprobs: None,
+                thoughts: None,
+                model: model.to_string(),
+                fallback_from: None,
+            })
+        })
+    }
+
+    fn generate_with_functions<'a>(
+        &'a self,
+        mut contents: Vec<Content>,
+        system: Option<&'a str>,
+        model: &'a str,
+        config: Option<GenerationConfig>,
+        _safety: &'a [SafetySetting],
+        functions: &'a FunctionRegistry,
+        options: &'a FunctionLoopOptions,
+    ) -> BackendFuture<'a, FunctionLoopResponse> {
+        Box::pin(async move {
+            let max_steps = options.max_steps.max(1);
+            let mut calls = Vec::new();
+
+            for step in 1..=max_steps {
+                let requested = match self.next(model, system, &contents, &config)? {
+                    MockReply::Text(text) => {
+                        // Like the real loop, the last step is always forced
+                        let stop_reason = if step == max_steps {
+                            LoopStopReason::StepLimit
+                        } else {
+                            LoopStopReason::Completed
+                        };
+                        return Ok(FunctionLoopResponse {
+                            text,
+                            usage: UsageMetadata::default(),
+                            calls,
+                            steps: step,
+                            stop_reason,
+                            safety_ratings: Vec::new(),
+                            thoughts: None,
+                            model: model.to_string(),
+                            fallback_from: None,
+                        });
+                    }
+                    MockReply::FunctionCalls(_) if step == max_steps => {
+                        return Err(GeminiError::ConfigError(format!(
+                            "MockBackend scripted function calls on step {}, where an answer is forced",
+                            step
+                        )))
+                    }
+                    MockReply::FunctionCalls(requested) => requested,
+                    MockReply::Error(e) => return Err(e),
+                };
+
+                contents.push(Content {
+                    role: "model".to_string(),
+                    parts: requested
+                        .iter()
+                        .map(|call| Part::FunctionCall {
+                            function_call: call.clone(),
+                            thought_signature: None,
+                        })
+                        .collect(),
+                });
+
+                let mut responses = Vec::with_capacity(requested.len());
+                for call in requested {
+                    let started = Instant::now();
+                    let (response, is_error) = match functions.call(&call.name, call.args.clone()).await {
+                        Ok(value) => (value, false),
+                        Err(message) => (serde_json::json!({ "error": message }), true),
+                    };
+                    calls.push(FunctionCallRecord {
+                        step,
+                        name: call.name.clone(),
+                        args: call.args,
+                        response: response.clone(),
+                        is_error,
+                        duration_ms: started.elapsed().as_millis() as u64,
+                    });
+                    responses.push(Part::FunctionResponse {
+                        function_response: FunctionResponse {
+                            id: call.id,
+                            name: call.name,
+                            response,
+                        },
+                    });
+                }
+                contents.push(Content {
+                    role: "user".to_string(),
+                    parts: responses,
+                });
+            }
+
+            // The final step always returns above
+            Err(GeminiError::EmptyResponse)
+        })
+    }
+
+    fn test_connection(&self) -> BackendFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemini::types::FunctionDeclaration;
+
+    #[tokio::test]
+    async fn test_replies_in_order_and_records_requests() {
+        let mock = MockBackend::new().with_reply("first").with_error(GeminiError::EmptyResponse);
+
+        let model = mock.resolve_model(None, "flash").await.unwrap();
+        assert_eq!(model.id, models::DEFAULT_FLASH_MODEL);
+
+        let response = mock.generate_content("Hello", Some("Be brief."), &model.id, None, &[]).await.unwrap();
+        assert_eq!(response.text, "first");
+        assert!(matches!(
+            mock.generate_content("Again", None, &model.id, None, &[]).await,
+            Err(GeminiError::EmptyResponse)
+        ));
+        assert!(mock.generate_content("More", None, &model.id, None, &[]).await.is_err());
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].prompt(), "Hello");
+        assert_eq!(requests[0].system.as_deref(), Some("Be brief."));
+    }
+
+    #[tokio::test]
+    async fn test_function_calls_run_through_the_registry() {
+        let mut functions = FunctionRegistry::new();
+        functions.register(
+            FunctionDeclaration {
+                name: "echo".to_string(),
+                description: "Echoes its arguments".to_string(),
+                parameters: None,
+            },
+            |args| async move { Ok(args) },
+        );
+        let mock = MockBackend::new()
+            .with_function_call("echo", serde_json::json!({"word": "hi"}))
+            .with_reply("done");
+
+        let response = mock
+            .generate_with_functions(
+                vec![Content::user("Go")],
+                None,
+                "m",
+                None,
+                &[],
+                &functions,
+                &FunctionLoopOptions::default(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.text, "done");
+        assert_eq!(response.steps, 2);
+        assert_eq!(response.stop_reason, LoopStopReason::Completed);
+        assert_eq!(response.calls[0].response, serde_json::json!({"word": "hi"}));
+        // The second request carries the call and its response
+        assert_eq!(mock.requests()[1].contents.len(), 3);
+        assert_eq!(mock.remaining(), 0);
+    }
+}
@@ -1,7 +1,20 @@
+pub mod backend;
+pub mod cassette;
 pub mod client;
+pub mod fallback;
+pub mod functions;
+pub mod health;
+pub mod keys;
+pub mod mock;
 pub mod models;
+pub mod ratelimit;
+pub mod safety;
+pub mod tap;
 pub mod types;
 
+pub use backend::GenerationBackend;
 pub use client::GeminiClient;
-pub use models::GeminiModel;
+pub use keys::KeyPool;
+pub use mock::MockBackend;
+pub use models::{ModelInfo, ModelRegistry};
 pub use types::*;
@@ -1,50 +1,434 @@
-#[derive(Debug, Clone, Copy)]
-pub enum GeminiModel {
-    Pro,
-    Flash,
+// Model registry
+//
+// Any model the API serves can be chosen per call. The registry is filled
+// from `GET /models` and cached on disk; until then it holds only the
+// configured defaults and accepts other ids unchecked. Aliases map short
+// names such as `pro` and `flash` to model ids.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+pub const DEFAULT_PRO_MODEL: &str = "gemini-3-pro-preview";
+pub const DEFAULT_FLASH_MODEL: &str = "gemini-3-flash-preview";
+
+pub const PRO_ALIAS: &str = "pro";
+pub const FLASH_ALIAS: &str = "flash";
+
+/// Extra aliases, e.g. `lite=gemini-2.5-flash-lite,exp=gemini-exp-1206`
+pub const ALIASES_ENV: &str = "GEMINI_MODEL_ALIASES";
+/// Overrides the cache location; empty disables the cache
+pub const CACHE_ENV: &str = "GEMINI_MODELS_CACHE";
+/// How long a fetched model list is trusted
+pub const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Input window used for models missing from the registry and `INPUT_TOKEN_LIMITS`
+pub const DEFAULT_INPUT_TOKEN_LIMIT: u32 = 1_048_576;
+pub const DEFAULT_OUTPUT_TOKEN_LIMIT: u32 = 65_536;
+
+/// Known input windows by model-name prefix, most specific first
+const INPUT_TOKEN_LIMITS: &[(&str, u32)] = &[
+    ("gemini-1.5-pro", 2_097_152),
+    ("gemini-1.5-flash", 1_048_576),
+    ("gemini-2.0-flash", 1_048_576),
+    ("gemini-2.5", 1_048_576),
+    ("gemini-3", 1_048_576),
+    ("gemma-3", 131_072),
+];
+
+const GENERATE_METHOD: &str = "generateContent";
+
+/// Maximum prompt size in tokens for a model id such as `gemini-3-pro-preview`,
+/// from the built-in table
+pub fn input_token_limit(model_name: &str) -> u32 {
+    let name = model_name.trim_start_matches("models/");
+    INPUT_TOKEN_LIMITS
+        .iter()
+        .find(|(prefix, _)| name.starts_with(prefix))
+        .map(|(_, limit)| *limit)
+        .unwrap_or(DEFAULT_INPUT_TOKEN_LIMIT)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelInfo {
+    /// Id without the `models/` prefix
+    pub id: String,
+    #[serde(default)]
+    pub display_name: String,
+    pub input_token_limit: u32,
+    pub output_token_limit: u32,
+    /// API methods such as `generateContent`; empty when unknown
+    #[serde(default)]
+    pub supported_methods: Vec<String>,
+}
+
+impl ModelInfo {
+    /// Entry for a model the API has not described, with table limits
+    pub fn fallback(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            display_name: String::new(),
+            input_token_limit: input_token_limit(id),
+            output_token_limit: DEFAULT_OUTPUT_TOKEN_LIMIT,
+            supported_methods: Vec::new(),
+        }
+    }
+
+    pub fn supports(&self, method: &str) -> bool {
+        self.supported_methods.is_empty() || self.supported_methods.iter().any(|m| m == method)
+    }
 }
 
-impl GeminiModel {
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            Self::Pro => "gemini-3-pro-preview",
-            Self::Flash => "gemini-3-flash-preview",
+/// One page of `models.list`
+#[derive(Debug, Deserialize)]
+pub struct ListModelsResponse {
+    #[serde(default)]
+    pub models: Vec<ApiModel>,
+    #[serde(default, alias = "nextPageToken")]
+    pub next_page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApiModel {
+    pub name: String,
+    #[serde(default, alias = "displayName")]
+    pub display_name: String,
+    #[serde(default, alias = "inputTokenLimit")]
+    pub input_token_limit: u32,
+    #[serde(default, alias = "outputTokenLimit")]
+    pub output_token_limit: u32,
+    #[serde(default, alias = "supportedGenerationMethods")]
+    pub supported_generation_methods: Vec<String>,
+}
+
+impl From<ApiModel> for ModelInfo {
+    fn from(model: ApiModel) -> Self {
+        let id = model.name.trim_start_matches("models/").to_string();
+        Self {
+            input_token_limit: if model.input_token_limit > 0 {
+                model.input_token_limit
+            } else {
+                input_token_limit(&id)
+            },
+            output_token_limit: if model.output_token_limit > 0 {
+                model.output_token_limit
+            } else {
+                DEFAULT_OUTPUT_TOKEN_LIMIT
+            },
+            id,
+            display_name: model.display_name,
+            supported_methods: model.supported_generation_methods,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistrySource {
+    /// Only the configured defaults; other ids are accepted unchecked
+    Builtin,
+    Cache,
+    Api,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile {
+    fetched_at: u64,
+    models: Vec<ModelInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ModelRegistry {
+    models: BTreeMap<String, ModelInfo>,
+    aliases: BTreeMap<String, String>,
+    source: RegistrySource,
+    fetched_at: Option<SystemTime>,
+}
+
+impl ModelRegistry {
+    /// Registry holding only the defaults behind the `pro` and `flash` aliases
+    pub fn builtin(pro_model: &str, flash_model: &str) -> Self {
+        let mut registry = Self {
+            models: BTreeMap::new(),
+            aliases: BTreeMap::new(),
+            source: RegistrySource::Builtin,
+            fetched_at: None,
+        };
+        registry.set_alias(PRO_ALIAS, pro_model);
+        registry.set_alias(FLASH_ALIAS, flash_model);
+        registry.forget_models();
+        registry
+    }
+
+    /// Drops fetched models, keeping fallback entries for the alias targets
+    pub fn forget_models(&mut self) {
+        self.models = self
+            .aliases
+            .values()
+            .map(|id| (id.clone(), ModelInfo::fallback(id)))
+            .collect();
+        self.source = RegistrySource::Builtin;
+        self.fetched_at = None;
+    }
+
+    pub fn set_alias(&mut self, alias: &str, id: &str) {
+        self.aliases.insert(
+            alias.to_lowercase(),
+            id.trim_start_matches("models/").to_string(),
+        );
+    }
+
+    /// Replaces every alias, e.g. after a configuration reload. Without a
+    /// fetched list, the fallback entries follow the new targets.
+    pub fn set_aliases<'a>(&mut self, aliases: impl IntoIterator<Item = (&'a str, &'a str)>) {
+        self.aliases.clear();
+        for (alias, id) in aliases {
+            self.set_alias(alias, id);
+        }
+        if !self.is_authoritative() {
+            self.forget_models();
+        }
+    }
+
+    /// Replaces the known models with a fetched or cached list. Aliases stay.
+    pub fn replace_models(&mut self, models: Vec<ModelInfo>, source: RegistrySource, fetched_at: SystemTime) {
+        self.models = models.into_iter().map(|m| (m.id.clone(), m)).collect();
+        self.source = source;
+        self.fetched_at = Some(fetched_at);
+    }
+
+    pub fn source(&self) -> RegistrySource {
+        self.source
+    }
+
+    /// True when the model list came from the API, directly or via the cache
+    pub fn is_authoritative(&self) -> bool {
+        self.source != RegistrySource::Builtin
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.fetched_at
+            .and_then(|t| t.elapsed().ok())
+            .is_none_or(|age| age > CACHE_TTL)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&ModelInfo> {
+        self.models.get(id.trim_start_matches("models/"))
+    }
+
+    pub fn alias_target(&self, alias: &str) -> Option<&str> {
+        self.aliases.get(&alias.to_lowercase()).map(String::as_str)
+    }
+
+    /// True when `name` is an alias or a listed model id
+    pub fn contains(&self, name: &str) -> bool {
+        let name = name.trim();
+        self.alias_target(name)
+            .or(Some(name))
+            .is_some_and(|id| self.get(id).is_some())
+    }
+
+    /// Resolves an alias or model id to a model that can generate content
+    pub fn resolve(&self, name: &str) -> Result<ModelInfo, String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("Model name cannot be empty".to_string());
+        }
+
+        let id = self
+            .alias_target(name)
+            .unwrap_or_else(|| name.trim_start_matches("models/"));
+
+        if !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'))
+        {
+            return Err(format!(
+                "Invalid model id '{}': use letters, digits, '-', '.' or '_'",
+                id
+            ));
+        }
+
+        match self.models.get(id) {
+            Some(info) if !info.supports(GENERATE_METHOD) => Err(format!(
+                "Model '{}' does not support {} (supports: {})",
+                id,
+                GENERATE_METHOD,
+                info.supported_methods.join(", ")
+            )),
+            Some(info) => Ok(info.clone()),
+            None if !self.is_authoritative() => Ok(ModelInfo::fallback(id)),
+            None => Err(self.unknown_model_message(name)),
         }
     }
 
-    pub fn from_str(s: &str) -> Self {
-        if s.contains("flash") {
-            Self::Flash
-        } else {
-            Self::Pro
+    fn unknown_model_message(&self, name: &str) -> String {
+        let needle = name.to_lowercase();
+        let stem = needle.split('-').take(2).collect::<Vec<_>>().join("-");
+        let mut similar: Vec<&str> = self
+            .models
+            .values()
+            .filter(|m| m.supports(GENERATE_METHOD))
+            .map(|m| m.id.as_str())
+            .filter(|id| id.contains(&needle) || needle.contains(id) || id.starts_with(&stem))
+            .collect();
+        similar.truncate(8);
+
+        let aliases: Vec<String> = self
+            .aliases
+            .iter()
+            .map(|(alias, id)| format!("{} ({})", alias, id))
+            .collect();
+
+        let mut message = format!("Unknown model '{}'.", name);
+        if !similar.is_empty() {
+            message.push_str(&format!(" Did you mean: {}?", similar.join(", ")));
         }
+        message.push_str(&format!(" Aliases: {}.", aliases.join(", ")));
+        message
+    }
+
+    /// Ids of models that can generate content, sorted
+    pub fn generation_models(&self) -> Vec<&str> {
+        self.models
+            .values()
+            .filter(|m| m.supports(GENERATE_METHOD))
+            .map(|m| m.id.as_str())
+            .collect()
+    }
+
+    /// Loads a cached model list, whatever its age
+    pub fn load_cache(&mut self, path: &Path) -> bool {
+        let cache: CacheFile = match std::fs::read(path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        {
+            Some(cache) => cache,
+            None => return false,
+        };
+        let fetched_at = UNIX_EPOCH + Duration::from_secs(cache.fetched_at);
+        debug!("Loaded {} models from {}", cache.models.len(), path.display());
+        self.replace_models(cache.models, RegistrySource::Cache, fetched_at);
+        true
+    }
+
+    pub fn save_cache(&self, path: &Path) -> std::io::Result<()> {
+        let cache = CacheFile {
+            fetched_at: self
+                .fetched_at
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            models: self.models.values().cloned().collect(),
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Write then rename so a concurrent reader never sees a partial file
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&cache)?)?;
+        std::fs::rename(&tmp, path)
     }
 }
 
+/// Cache file used when `models.cache` is unset:
+/// `$XDG_CACHE_HOME/gemini-mcp/models.json`, else `~/.cache/gemini-mcp/models.json`
+pub fn default_cache_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(base.join("gemini-mcp").join("models.json"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::temp_path;
+
+    fn api_registry() -> ModelRegistry {
+        let mut registry = ModelRegistry::builtin(DEFAULT_PRO_MODEL, DEFAULT_FLASH_MODEL);
+        let listed: ListModelsResponse = serde_json::from_value(serde_json::json!({
+            "models": [
+                {"name": "models/gemini-3-pro-preview", "inputTokenLimit": 1048576, "outputTokenLimit": 65536,
+                 "supportedGenerationMethods": ["generateContent", "countTokens"]},
+                {"name": "models/gemini-3-flash-preview", "inputTokenLimit": 1048576, "outputTokenLimit": 65536,
+                 "supportedGenerationMethods": ["generateContent", "countTokens"]},
+                {"name": "models/gemini-2.5-flash-lite", "displayName": "Gemini 2.5 Flash-Lite",
+                 "inputTokenLimit": 1048576, "outputTokenLimit": 65536,
+                 "supportedGenerationMethods": ["generateContent"]},
+                {"name": "models/text-embedding-004", "inputTokenLimit": 2048, "outputTokenLimit": 1,
+                 "supportedGenerationMethods": ["embedContent"]}
+            ]
+        }))
+        .unwrap();
+        registry.replace_models(
+            listed.models.into_iter().map(ModelInfo::from).collect(),
+            RegistrySource::Api,
+            SystemTime::now(),
+        );
+        registry
+    }
 
     #[test]
-    fn test_model_as_str() {
-        assert_eq!(GeminiModel::Pro.as_str(), "gemini-3-pro-preview");
-        assert_eq!(GeminiModel::Flash.as_str(), "gemini-3-flash-preview");
+    fn test_input_token_limit() {
+        assert_eq!(input_token_limit("gemini-3-pro-preview"), 1_048_576);
+        assert_eq!(input_token_limit("models/gemini-1.5-pro-002"), 2_097_152);
+        assert_eq!(input_token_limit("gemma-3-27b-it"), 131_072);
+        assert_eq!(input_token_limit("some-future-model"), DEFAULT_INPUT_TOKEN_LIMIT);
     }
 
     #[test]
-    fn test_model_from_str() {
-        assert!(matches!(GeminiModel::from_str("pro"), GeminiModel::Pro));
-        assert!(matches!(
-            GeminiModel::from_str("flash"),
-            GeminiModel::Flash
-        ));
-        assert!(matches!(
-            GeminiModel::from_str("gemini-flash"),
-            GeminiModel::Flash
-        ));
-        assert!(matches!(
-            GeminiModel::from_str("anything else"),
-            GeminiModel::Pro
-        ));
+    fn test_builtin_registry_resolves_aliases_and_accepts_any_id() {
+        let mut registry = ModelRegistry::builtin(DEFAULT_PRO_MODEL, DEFAULT_FLASH_MODEL);
+        registry.set_alias("lite", "gemini-2.5-flash-lite");
+
+        assert_eq!(registry.resolve("pro").unwrap().id, DEFAULT_PRO_MODEL);
+        assert_eq!(registry.resolve("FLASH").unwrap().id, DEFAULT_FLASH_MODEL);
+        assert_eq!(registry.resolve("lite").unwrap().id, "gemini-2.5-flash-lite");
+        assert_eq!(registry.resolve("models/gemini-exp-1206").unwrap().id, "gemini-exp-1206");
+        assert!(!registry.is_authoritative());
+        assert!(registry.is_stale());
+
+        assert!(registry.resolve("").is_err());
+        assert!(registry.resolve("gemini 3 pro").unwrap_err().contains("Invalid model id"));
+    }
+
+    #[test]
+    fn test_api_registry_validates_ids() {
+        let registry = api_registry();
+
+        let lite = registry.resolve("gemini-2.5-flash-lite").unwrap();
+        assert_eq!(lite.display_name, "Gemini 2.5 Flash-Lite");
+        assert_eq!(lite.output_token_limit, 65536);
+
+        let err = registry.resolve("text-embedding-004").unwrap_err();
+        assert!(err.contains("does not support generateContent"));
+
+        let err = registry.resolve("gemini-2.5-flash").unwrap_err();
+        assert!(err.contains("Unknown model 'gemini-2.5-flash'"));
+        assert!(err.contains("gemini-2.5-flash-lite"));
+        assert!(err.contains("pro (gemini-3-pro-preview)"));
+
+        assert_eq!(
+            registry.generation_models(),
+            vec!["gemini-2.5-flash-lite", "gemini-3-flash-preview", "gemini-3-pro-preview"]
+        );
+    }
+
+    #[test]
+    fn test_cache_round_trip() {
+        let path = temp_path("models").join("models.json");
+        let registry = api_registry();
+        registry.save_cache(&path).unwrap();
+
+        let mut loaded = ModelRegistry::builtin(DEFAULT_PRO_MODEL, DEFAULT_FLASH_MODEL);
+        assert!(loaded.load_cache(&path));
+        assert_eq!(loaded.source(), RegistrySource::Cache);
+        assert!(!loaded.is_stale());
+        assert_eq!(loaded.get("gemini-2.5-flash-lite"), registry.get("gemini-2.5-flash-lite"));
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
     }
 }
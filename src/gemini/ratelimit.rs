@@ -0,0 +1,395 @@
+// Client-side rate limiting
+//
+// Each model gets a token bucket for requests per minute and one for input
+// tokens per minute. Calls wait in line until both buckets have room instead
+// of failing with 429. Token use is estimated before a request and corrected
+// from `usage_metadata` once the response arrives.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::error::{GeminiError, Result};
+
+/// Requests per minute for every model without its own limit
+pub const RPM_ENV: &str = "GEMINI_RPM";
+/// Input tokens per minute for every model without its own limit
+pub const TPM_ENV: &str = "GEMINI_TPM";
+/// Per-model limits as `model=RPM/TPM`, separated by `;`; `0` means unlimited
+pub const LIMITS_ENV: &str = "GEMINI_RATE_LIMITS";
+
+/// Rough bytes per token, used to estimate a request before sending it
+const BYTES_PER_TOKEN: usize = 4;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    pub rpm: Option<u32>,
+    pub tpm: Option<u32>,
+}
+
+impl RateLimit {
+    pub fn is_unlimited(&self) -> bool {
+        self.rpm.is_none() && self.tpm.is_none()
+    }
+}
+
+/// Configured limits; model names may be ids or aliases
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitConfig {
+    default: RateLimit,
+    per_model: Vec<(String, RateLimit)>,
+}
+
+impl RateLimitConfig {
+    pub fn new(default: RateLimit, per_model: Vec<(String, RateLimit)>) -> Self {
+        Self { default, per_model }
+    }
+
+    /// Limits for a model id; `resolve` maps configured names to ids
+    pub fn limit_for(&self, model: &str, resolve: impl Fn(&str) -> Option<String>) -> RateLimit {
+        self.per_model
+            .iter()
+            .find(|(name, _)| resolve(name).is_some_and(|id| id == model))
+            .map(|(_, limit)| *limit)
+            .unwrap_or(self.default)
+    }
+}
+
+/// Reads `model=RPM/TPM` entries separated by `;`, as in `GEMINI_RATE_LIMITS`
+pub fn parse_model_limits(spec: &str) -> Result<Vec<(String, RateLimit)>> {
+    let mut per_model = Vec::new();
+    for entry in spec.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let invalid = || {
+            GeminiError::ConfigError(format!(
+                "Invalid {} entry '{}': expected model=RPM/TPM",
+                LIMITS_ENV, entry
+            ))
+        };
+        let (model, limits) = entry.split_once('=').ok_or_else(invalid)?;
+        let (rpm, tpm) = limits.split_once('/').ok_or_else(invalid)?;
+        if model.trim().is_empty() {
+            return Err(invalid());
+        }
+        per_model.push((
+            model.trim().to_string(),
+            RateLimit {
+                rpm: parse_limit(LIMITS_ENV, rpm)?,
+                tpm: parse_limit(LIMITS_ENV, tpm)?,
+            },
+        ));
+    }
+    Ok(per_model)
+}
+
+fn parse_limit(key: &str, value: &str) -> Result<Option<u32>> {
+    let value = value.trim();
+    value
+        .parse::<u32>()
+        .map(|n| Some(n).filter(|n| *n > 0))
+        .map_err(|_| GeminiError::ConfigError(format!("Invalid {} value '{}': expected a number", key, value)))
+}
+
+/// Estimated input tokens for `bytes` of request text
+pub fn estimate_tokens(bytes: usize) -> u32 {
+    bytes.div_ceil(BYTES_PER_TOKEN).min(u32::MAX as usize) as u32
+}
+
+#[derive(Debug)]
+struct BucketState {
+    /// Swapped in place on reload, so queued and new callers share one bucket
+    limit: RateLimit,
+    requests: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl BucketState {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let minutes = now.duration_since(self.updated).as_secs_f64() / 60.0;
+        self.updated = now;
+        if let Some(rpm) = self.limit.rpm {
+            self.requests = (self.requests + minutes * rpm as f64).min(rpm as f64);
+        }
+        if let Some(tpm) = self.limit.tpm {
+            self.tokens = (self.tokens + minutes * tpm as f64).min(tpm as f64);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Held while waiting, so queued calls are served in order
+    queue: tokio::sync::Mutex<()>,
+    state: Mutex<BucketState>,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            queue: tokio::sync::Mutex::new(()),
+            state: Mutex::new(BucketState {
+                limit,
+                requests: limit.rpm.unwrap_or(0) as f64,
+                tokens: limit.tpm.unwrap_or(0) as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, BucketState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Switches to `limit`, keeping the current fill level clamped to the
+    /// new capacity; a limit that was unset starts full
+    fn resize(&self, limit: RateLimit) {
+        let mut state = self.state();
+        if state.limit == limit {
+            return;
+        }
+        state.refill();
+        state.requests = match (state.limit.rpm, limit.rpm) {
+            (Some(_), Some(rpm)) => state.requests.min(rpm as f64),
+            (_, rpm) => rpm.unwrap_or(0) as f64,
+        };
+        state.tokens = match (state.limit.tpm, limit.tpm) {
+            (Some(_), Some(tpm)) => state.tokens.min(tpm as f64),
+            (_, tpm) => tpm.unwrap_or(0) as f64,
+        };
+        state.limit = limit;
+    }
+
+    /// Takes one request and `tokens` if available, returning the tokens
+    /// charged, otherwise returns how long to wait before trying again
+    fn try_take(&self, tokens: u32) -> std::result::Result<u32, Duration> {
+        let mut state = self.state();
+        state.refill();
+        let limit = state.limit;
+
+        let mut wait_minutes: f64 = 0.0;
+        if let Some(rpm) = limit.rpm {
+            wait_minutes = wait_minutes.max((1.0 - state.requests) / rpm as f64);
+        }
+        // A request larger than the whole bucket waits for a full bucket
+        let charged = limit.tpm.map_or(0, |tpm| tokens.min(tpm));
+        if let Some(tpm) = limit.tpm {
+            wait_minutes = wait_minutes.max((charged as f64 - state.tokens) / tpm as f64);
+        }
+
+        if wait_minutes > 0.0 {
+            return Err(Duration::from_secs_f64(wait_minutes * 60.0));
+        }
+        if limit.rpm.is_some() {
+            state.requests -= 1.0;
+        }
+        state.tokens -= charged as f64;
+        Ok(charged)
+    }
+
+    /// Charges the difference between actual and estimated tokens. The
+    /// balance may go negative, which delays later calls.
+    fn adjust(&self, delta: f64) {
+        let mut state = self.state();
+        if state.limit.tpm.is_some() {
+            state.tokens -= delta;
+        }
+    }
+}
+
+/// Token buckets per model id
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Arc<Bucket>>>,
+}
+
+/// Tokens reserved for one request, to be corrected with actual usage
+#[derive(Debug)]
+#[must_use]
+pub struct Reservation {
+    bucket: Option<Arc<Bucket>>,
+    /// Tokens taken from the bucket, at most its capacity
+    charged: u32,
+}
+
+impl Reservation {
+    /// Replaces the charged tokens with the tokens the API reported; pass 0
+    /// when the request failed
+    pub fn reconcile(self, actual: u32) {
+        if let Some(bucket) = self.bucket {
+            bucket.adjust(actual as f64 - self.charged as f64);
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Waits until `model` has room for one request of `estimate` tokens
+    pub async fn acquire(&self, model: &str, limit: RateLimit, estimate: u32) -> Reservation {
+        if limit.is_unlimited() {
+            return Reservation {
+                bucket: None,
+                charged: 0,
+            };
+        }
+
+        let bucket = {
+            let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+            let bucket = buckets
+                .entry(model.to_string())
+                .or_insert_with(|| Arc::new(Bucket::new(limit)));
+            Arc::clone(bucket)
+        };
+        // Limits changed since the bucket was created
+        bucket.resize(limit);
+
+        let charged = {
+            let _turn = bucket.queue.lock().await;
+            loop {
+                match bucket.try_take(estimate) {
+                    Ok(charged) => break charged,
+                    Err(wait) => {
+                        debug!("Rate limit for {}: waiting {:?}", model, wait);
+                        tokio::time::sleep(wait).await;
+                    }
+                }
+            }
+        };
+
+        Reservation {
+            bucket: Some(bucket),
+            charged,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn same(name: &str) -> Option<String> {
+        Some(name.to_string())
+    }
+
+    #[test]
+    fn test_per_model_limits_override_the_default() {
+        let per_model = parse_model_limits("gemini-2.5-flash-lite = 15/0; gemini-3-pro-preview=5/250000").unwrap();
+        let config = RateLimitConfig::new(RateLimit { rpm: Some(10), tpm: None }, per_model);
+
+        assert_eq!(
+            config.limit_for("gemini-3-flash-preview", same),
+            RateLimit { rpm: Some(10), tpm: None }
+        );
+        assert_eq!(
+            config.limit_for("gemini-2.5-flash-lite", same),
+            RateLimit { rpm: Some(15), tpm: None }
+        );
+        assert_eq!(
+            config.limit_for("gemini-3-pro-preview", same),
+            RateLimit { rpm: Some(5), tpm: Some(250_000) }
+        );
+        assert!(parse_model_limits("pro=5").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_requests_queue_when_rpm_is_spent() {
+        let limiter = RateLimiter::new();
+        let limit = RateLimit { rpm: Some(2), tpm: None };
+        let start = Instant::now();
+
+        limiter.acquire("m", limit, 0).await.reconcile(0);
+        limiter.acquire("m", limit, 0).await.reconcile(0);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // One request comes back every 30 seconds
+        limiter.acquire("m", limit, 0).await.reconcile(0);
+        assert!(start.elapsed() >= Duration::from_secs(30));
+        assert!(start.elapsed() < Duration::from_secs(31));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tokens_are_reconciled_with_usage() {
+        let limiter = RateLimiter::new();
+        let limit = RateLimit { rpm: None, tpm: Some(600) };
+        let start = Instant::now();
+
+        // Estimated 100, actually used 600: the bucket is now empty
+        limiter.acquire("m", limit, 100).await.reconcile(600);
+
+        // 60 tokens refill in 6 seconds
+        limiter.acquire("m", limit, 60).await.reconcile(60);
+        assert!(start.elapsed() >= Duration::from_secs(6));
+        assert!(start.elapsed() < Duration::from_secs(7));
+
+        // Unlimited models never wait
+        let unlimited = limiter.acquire("other", RateLimit::default(), 1_000_000).await;
+        unlimited.reconcile(1_000_000);
+        assert!(start.elapsed() < Duration::from_secs(7));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_changed_limits_keep_the_fill_level() {
+        let limiter = RateLimiter::new();
+        let start = Instant::now();
+
+        let limit = RateLimit { rpm: Some(2), tpm: None };
+        limiter.acquire("m", limit, 0).await.reconcile(0);
+        limiter.acquire("m", limit, 0).await.reconcile(0);
+
+        // Raising the limit does not hand out a fresh bucket: one request
+        // comes back every 15 seconds at the new rate
+        let raised = RateLimit { rpm: Some(4), tpm: None };
+        limiter.acquire("m", raised, 0).await.reconcile(0);
+        assert!(start.elapsed() >= Duration::from_secs(15));
+        assert!(start.elapsed() < Duration::from_secs(16));
+
+        // Lowering it clamps a full bucket to the new capacity
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        let lowered = RateLimit { rpm: Some(1), tpm: None };
+        let before = Instant::now();
+        limiter.acquire("m", lowered, 0).await.reconcile(0);
+        limiter.acquire("m", lowered, 0).await.reconcile(0);
+        assert!(before.elapsed() >= Duration::from_secs(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_requests_larger_than_the_bucket_are_charged_in_full() {
+        let limiter = RateLimiter::new();
+        let limit = RateLimit { rpm: None, tpm: Some(1000) };
+        let start = Instant::now();
+
+        // Waits for a full bucket, then owes the 4000 tokens over capacity
+        limiter.acquire("m", limit, 5000).await.reconcile(5000);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // Paying back 4000 and refilling 100 takes 4.1 minutes
+        limiter.acquire("m", limit, 100).await.reconcile(100);
+        assert!(start.elapsed() >= Duration::from_secs(246));
+        assert!(start.elapsed() < Duration::from_secs(247));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_changed_limits_apply_to_callers_already_queued() {
+        let limiter = Arc::new(RateLimiter::new());
+        let slow = RateLimit { rpm: Some(1), tpm: None };
+        let start = Instant::now();
+        limiter.acquire("m", slow, 0).await.reconcile(0);
+
+        let queued = tokio::spawn({
+            let limiter = Arc::clone(&limiter);
+            async move { limiter.acquire("m", slow, 0).await.reconcile(0) }
+        });
+        tokio::task::yield_now().await;
+
+        // The reload resizes the bucket the first caller is queued on, so the
+        // new caller waits its turn instead of drawing from a second bucket
+        let fast = RateLimit { rpm: Some(60), tpm: None };
+        limiter.acquire("m", fast, 0).await.reconcile(0);
+        assert!(start.elapsed() >= Duration::from_secs(60));
+        queued.await.unwrap();
+    }
+}
@@ -0,0 +1,297 @@
+// Safety settings and block detection
+//
+// Thresholds can be set for the whole server in the `[safety]` config
+// section or the environment, and overridden per call. A prompt or candidate the API refuses is reported as
+// `GeminiError::Blocked` instead of an empty response.
+
+use std::fmt;
+
+use crate::config::SafetyConfig;
+use crate::error::{GeminiError, Result};
+use crate::gemini::types::{
+    GenerateContentResponse, HarmBlockThreshold, HarmCategory, SafetyRating, SafetySetting,
+};
+
+/// Threshold applied to every category, e.g. `block_only_high`
+pub const THRESHOLD_ENV: &str = "GEMINI_SAFETY_THRESHOLD";
+/// Per-category thresholds: `GEMINI_SAFETY_HARASSMENT`, `GEMINI_SAFETY_HATE_SPEECH`, ...
+const CATEGORY_ENV_PREFIX: &str = "GEMINI_SAFETY_";
+
+/// Finish reasons that mean the candidate was withheld rather than finished
+const BLOCKING_FINISH_REASONS: &[&str] = &[
+    "SAFETY",
+    "PROHIBITED_CONTENT",
+    "BLOCKLIST",
+    "SPII",
+    "RECITATION",
+    "IMAGE_SAFETY",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockTarget {
+    Prompt,
+    Response,
+}
+
+impl fmt::Display for BlockTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockTarget::Prompt => write!(f, "Prompt"),
+            BlockTarget::Response => write!(f, "Response"),
+        }
+    }
+}
+
+/// Server-wide safety settings from the `[safety]` section
+pub fn settings_from_config(config: &SafetyConfig) -> Result<Vec<SafetySetting>> {
+    let global = config
+        .threshold
+        .as_deref()
+        .map(|value| parse_threshold("safety.threshold", value))
+        .transpose()?;
+
+    let names: Vec<String> = HarmCategory::ALL.into_iter().map(category_name).collect();
+    if let Some(unknown) = config.categories.keys().find(|name| !names.contains(name)) {
+        return Err(GeminiError::ConfigError(format!(
+            "Unknown safety.categories entry '{}'. Available: {}",
+            unknown,
+            names.join(", ")
+        )));
+    }
+
+    let mut settings = Vec::new();
+    for (category, name) in HarmCategory::ALL.into_iter().zip(&names) {
+        let threshold = match config.categories.get(name) {
+            Some(value) => Some(parse_threshold(&format!("safety.categories.{}", name), value)?),
+            None => global,
+        };
+        if let Some(threshold) = threshold {
+            settings.push(SafetySetting {
+                category,
+                threshold,
+            });
+        }
+    }
+
+    Ok(settings)
+}
+
+/// Config name of a category, e.g. `hate_speech`
+pub fn category_name(category: HarmCategory) -> String {
+    serde_json::to_value(category)
+        .ok()
+        .and_then(|v| v.as_str().map(|s| s.trim_start_matches("HARM_CATEGORY_").to_lowercase()))
+        .unwrap_or_default()
+}
+
+/// Environment variable of a category, e.g. `GEMINI_SAFETY_HATE_SPEECH`
+pub fn category_env(category: HarmCategory) -> String {
+    format!("{}{}", CATEGORY_ENV_PREFIX, category_name(category).to_uppercase())
+}
+
+/// Parses a threshold name; errors name `key`, the setting it came from
+pub fn parse_threshold(key: &str, value: &str) -> Result<HarmBlockThreshold> {
+    serde_json::from_value(serde_json::Value::String(value.trim().to_lowercase())).map_err(|_| {
+        GeminiError::ConfigError(format!(
+            "Invalid {} '{}': expected block_none, block_only_high, block_medium_and_above, \
+             block_low_and_above or off",
+            key, value
+        ))
+    })
+}
+
+/// Server defaults with per-call overrides applied category by category.
+/// `None` leaves the API defaults in place.
+pub fn merge(defaults: &[SafetySetting], overrides: &[SafetySetting]) -> Option<Vec<SafetySetting>> {
+    let mut merged: Vec<SafetySetting> = defaults
+        .iter()
+        .filter(|d| !overrides.iter().any(|o| o.category == d.category))
+        .cloned()
+        .collect();
+    merged.extend(overrides.iter().cloned());
+
+    if merged.is_empty() {
+        None
+    } else {
+        Some(merged)
+    }
+}
+
+/// Prompt feedback ratings followed by those of the first candidate
+pub fn ratings(resp: &GenerateContentResponse) -> Vec<SafetyRating> {
+    let mut ratings: Vec<SafetyRating> = resp
+        .prompt_feedback
+        .as_ref()
+        .map(|f| f.safety_ratings.clone())
+        .unwrap_or_default();
+    if let Some(candidate) = resp.candidates.first() {
+        ratings.extend(candidate.safety_ratings.iter().cloned());
+    }
+    ratings
+}
+
+/// Fails with `GeminiError::Blocked` when the prompt was rejected, or when
+/// the first candidate was withheld without any text
+pub fn check_blocked(resp: &GenerateContentResponse) -> Result<()> {
+    if let Some(feedback) = &resp.prompt_feedback {
+        if let Some(reason) = &feedback.block_reason {
+            return Err(GeminiError::Blocked {
+                target: BlockTarget::Prompt,
+                reason: reason.clone(),
+                ratings: feedback.safety_ratings.clone(),
+            });
+        }
+    }
+
+    let Some(candidate) = resp.candidates.first() else {
+        return Ok(());
+    };
+    let Some(reason) = candidate
+        .finish_reason
+        .as_deref()
+        .filter(|r| BLOCKING_FINISH_REASONS.contains(r))
+    else {
+        return Ok(());
+    };
+
+    let has_text = candidate
+        .content
+        .parts
+        .iter()
+        .any(|p| p.answer_text().is_some_and(|text| !text.is_empty()));
+    if has_text {
+        return Ok(());
+    }
+
+    Err(GeminiError::Blocked {
+        target: BlockTarget::Response,
+        reason: reason.to_string(),
+        ratings: candidate.safety_ratings.clone(),
+    })
+}
+
+/// Short description of the ratings that caused a block, for error messages
+pub fn describe(ratings: &[SafetyRating]) -> String {
+    let flagged: Vec<String> = ratings
+        .iter()
+        .filter(|r| r.blocked || matches!(r.probability.as_str(), "MEDIUM" | "HIGH"))
+        .map(|r| format!("{}={}", r.category, r.probability))
+        .collect();
+
+    if flagged.is_empty() {
+        String::new()
+    } else {
+        format!(": {}", flagged.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_global_and_per_category() {
+        assert!(settings_from_config(&SafetyConfig::default()).unwrap().is_empty());
+
+        let config = SafetyConfig {
+            threshold: Some("block_only_high".to_string()),
+            categories: [("harassment".to_string(), "BLOCK_NONE".to_string())].into(),
+        };
+        let settings = settings_from_config(&config).unwrap();
+        assert_eq!(settings.len(), HarmCategory::ALL.len());
+        assert_eq!(settings[0].category, HarmCategory::Harassment);
+        assert_eq!(settings[0].threshold, HarmBlockThreshold::BlockNone);
+        assert_eq!(settings[1].threshold, HarmBlockThreshold::BlockOnlyHigh);
+
+        let invalid = SafetyConfig {
+            categories: [("hate_speech".to_string(), "sometimes".to_string())].into(),
+            ..Default::default()
+        };
+        let err = settings_from_config(&invalid).unwrap_err();
+        assert!(err.to_string().contains("safety.categories.hate_speech"));
+        let unknown = SafetyConfig {
+            categories: [("gossip".to_string(), "off".to_string())].into(),
+            ..Default::default()
+        };
+        assert!(settings_from_config(&unknown).unwrap_err().to_string().contains("gossip"));
+        assert_eq!(category_env(HarmCategory::HateSpeech), "GEMINI_SAFETY_HATE_SPEECH");
+    }
+
+    #[test]
+    fn test_merge_overrides_by_category() {
+        let defaults = vec![
+            SafetySetting {
+                category: HarmCategory::Harassment,
+                threshold: HarmBlockThreshold::BlockOnlyHigh,
+            },
+            SafetySetting {
+                category: HarmCategory::HateSpeech,
+                threshold: HarmBlockThreshold::BlockOnlyHigh,
+            },
+        ];
+        let overrides: Vec<SafetySetting> =
+            serde_json::from_value(serde_json::json!([{"category": "harassment", "threshold": "off"}]))
+                .unwrap();
+
+        let merged = merge(&defaults, &overrides).unwrap();
+        assert_eq!(merged.len(), 2);
+        assert!(merged.contains(&overrides[0]));
+        assert!(merged.contains(&defaults[1]));
+
+        assert_eq!(merge(&[], &[]), None);
+        assert_eq!(
+            serde_json::to_value(&merged[1]).unwrap(),
+            serde_json::json!({"category": "HARM_CATEGORY_HARASSMENT", "threshold": "OFF"})
+        );
+    }
+
+    #[test]
+    fn test_check_blocked_prompt() {
+        let resp: GenerateContentResponse = serde_json::from_value(serde_json::json!({
+            "promptFeedback": {
+                "blockReason": "SAFETY",
+                "safetyRatings": [
+                    {"category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "HIGH", "blocked": true},
+                    {"category": "HARM_CATEGORY_HARASSMENT", "probability": "NEGLIGIBLE"}
+                ]
+            }
+        }))
+        .unwrap();
+
+        let err = check_blocked(&resp).unwrap_err();
+        assert!(matches!(
+            err,
+            GeminiError::Blocked { target: BlockTarget::Prompt, .. }
+        ));
+        let message = err.to_string();
+        assert!(message.contains("Prompt blocked"));
+        assert!(message.contains("SAFETY"));
+        assert!(message.contains("HARM_CATEGORY_DANGEROUS_CONTENT=HIGH"));
+        assert!(!message.contains("HARASSMENT"));
+    }
+
+    #[test]
+    fn test_check_blocked_response() {
+        let blocked: GenerateContentResponse = serde_json::from_value(serde_json::json!({
+            "candidates": [{
+                "finishReason": "SAFETY",
+                "safetyRatings": [{"category": "HARM_CATEGORY_HATE_SPEECH", "probability": "MEDIUM"}]
+            }]
+        }))
+        .unwrap();
+        assert!(matches!(
+            check_blocked(&blocked),
+            Err(GeminiError::Blocked { target: BlockTarget::Response, .. })
+        ));
+        assert_eq!(ratings(&blocked).len(), 1);
+
+        let answered: GenerateContentResponse = serde_json::from_value(serde_json::json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [{"text": "Hello"}]},
+                "finishReason": "STOP"
+            }]
+        }))
+        .unwrap();
+        assert!(check_blocked(&answered).is_ok());
+    }
+}
@@ -0,0 +1,63 @@
+// Observing raw generateContent traffic
+//
+// A tap set on the client sees every generateContent request body and the
+// raw response body (or error) after it is received, including retries on
+// other keys, fallbacks and continuations. API keys travel in a header and
+// never appear in an exchange.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// One generateContent round trip
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Exchange {
+    pub model: String,
+    pub request: serde_json::Value,
+    /// Response body as received; `None` when the call failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Exchange {
+    /// Text of every non-thought part of the response's candidates
+    pub fn output_text(&self) -> Option<String> {
+        let candidates = self.response.as_ref()?.get("candidates")?.as_array()?;
+        let text = candidates
+            .iter()
+            .filter_map(|c| c.pointer("/content/parts").and_then(|p| p.as_array()))
+            .flatten()
+            .filter(|part| part.get("thought").and_then(|t| t.as_bool()) != Some(true))
+            .filter_map(|part| part.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("");
+        Some(text)
+    }
+}
+
+pub type ExchangeTap = Arc<dyn Fn(&Exchange) + Send + Sync>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_output_text_skips_thoughts() {
+        let exchange = Exchange {
+            model: "gemini-pro".to_string(),
+            request: json!({}),
+            response: Some(json!({"candidates": [{"content": {"parts": [
+                {"text": "pondering", "thought": true},
+                {"text": "Hello, "},
+                {"text": "world"}
+            ]}}]})),
+            error: None,
+        };
+        assert_eq!(exchange.output_text().as_deref(), Some("Hello, world"));
+
+        let failed = Exchange { response: None, error: Some("429".to_string()), ..exchange };
+        assert_eq!(failed.output_text(), None);
+    }
+}
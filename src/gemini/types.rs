@@ -1,34 +1,170 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize)]
 pub struct GenerateContentRequest {
     pub contents: Vec<Content>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub generation_config: Option<GenerationConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub safety_settings: Option<Vec<SafetySetting>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_config: Option<ToolConfig>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Content {
+    // Empty for system instructions, which carry no role
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub role: String,
     pub parts: Vec<Part>,
 }
 
+impl Content {
+    pub fn user(text: impl Into<String>) -> Self {
+        Self {
+            role: "user".to_string(),
+            parts: vec![Part::text(text)],
+        }
+    }
+
+    pub fn system(text: impl Into<String>) -> Self {
+        Self {
+            role: String::new(),
+            parts: vec![Part::text(text)],
+        }
+    }
+}
+
+// Requests are sent with snake_case field names; responses come back in
+// camelCase, hence the aliases on deserialized fields.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Part {
-    Text { text: String },
-    InlineData { inline_data: InlineData },
+    Text {
+        text: String,
+        /// Set on thought-summary parts, which are not part of the answer
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        thought: Option<bool>,
+        #[serde(default, alias = "thoughtSignature", skip_serializing_if = "Option::is_none")]
+        thought_signature: Option<String>,
+    },
+    InlineData {
+        #[serde(alias = "inlineData")]
+        inline_data: InlineData,
+    },
+    FunctionCall {
+        #[serde(alias = "functionCall")]
+        function_call: FunctionCall,
+        /// Gemini 3 models require this to be echoed back with the call
+        #[serde(alias = "thoughtSignature", skip_serializing_if = "Option::is_none")]
+        thought_signature: Option<String>,
+    },
+    FunctionResponse {
+        #[serde(alias = "functionResponse")]
+        function_response: FunctionResponse,
+    },
+}
+
+impl Part {
+    pub fn text(text: impl Into<String>) -> Self {
+        Part::Text {
+            text: text.into(),
+            thought: None,
+            thought_signature: None,
+        }
+    }
+
+    /// Answer text of this part; `None` for thoughts and non-text parts
+    pub fn answer_text(&self) -> Option<&str> {
+        match self {
+            Part::Text { text, thought, .. } if *thought != Some(true) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Text of a thought-summary part
+    pub fn thought_text(&self) -> Option<&str> {
+        match self {
+            Part::Text {
+                text,
+                thought: Some(true),
+                ..
+            } => Some(text),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InlineData {
+    #[serde(alias = "mimeType")]
     pub mime_type: String,
     pub data: String, // base64
 }
 
+/// A function invocation requested by the model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCall {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub args: serde_json::Value,
+}
+
+/// The result of a function call, sent back to the model in history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub name: String,
+    pub response: serde_json::Value,
+}
+
 #[derive(Debug, Clone, Serialize)]
+pub struct Tool {
+    pub function_declarations: Vec<FunctionDeclaration>,
+}
+
+/// Declares a function the model may call. `parameters` is an OpenAPI-style
+/// JSON schema object.
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionDeclaration {
+    pub name: String,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolConfig {
+    pub function_calling_config: FunctionCallingConfig,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionCallingConfig {
+    pub mode: FunctionCallingMode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_function_names: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum FunctionCallingMode {
+    /// The model decides between text and function calls
+    Auto,
+    /// The model must call a function
+    Any,
+    /// Function calling is disabled
+    None,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct GenerationConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
@@ -38,47 +174,215 @@ pub struct GenerationConfig {
     pub top_p: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub top_k: Option<u32>,
+    /// Number of alternative responses to generate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub candidate_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i32>,
+    /// Return log probabilities of the chosen tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_logprobs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking_config: Option<ThinkingConfig>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ThinkingConfig {
+    /// Token budget for thinking; 0 disables it, -1 lets the model decide
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking_budget: Option<i32>,
+    /// Return thought summaries as parts flagged `thought`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_thoughts: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct SafetySetting {
+    pub category: HarmCategory,
+    pub threshold: HarmBlockThreshold,
+}
+
+/// Harm categories that accept a blocking threshold. The short lowercase
+/// names are accepted as aliases in tool arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub enum HarmCategory {
+    #[serde(rename = "HARM_CATEGORY_HARASSMENT", alias = "harassment")]
+    Harassment,
+    #[serde(rename = "HARM_CATEGORY_HATE_SPEECH", alias = "hate_speech")]
+    HateSpeech,
+    #[serde(rename = "HARM_CATEGORY_SEXUALLY_EXPLICIT", alias = "sexually_explicit")]
+    SexuallyExplicit,
+    #[serde(rename = "HARM_CATEGORY_DANGEROUS_CONTENT", alias = "dangerous_content")]
+    DangerousContent,
+    #[serde(rename = "HARM_CATEGORY_CIVIC_INTEGRITY", alias = "civic_integrity")]
+    CivicIntegrity,
+}
+
+impl HarmCategory {
+    pub const ALL: [HarmCategory; 5] = [
+        HarmCategory::Harassment,
+        HarmCategory::HateSpeech,
+        HarmCategory::SexuallyExplicit,
+        HarmCategory::DangerousContent,
+        HarmCategory::CivicIntegrity,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum HarmBlockThreshold {
+    #[serde(rename = "BLOCK_LOW_AND_ABOVE", alias = "block_low_and_above")]
+    BlockLowAndAbove,
+    #[serde(rename = "BLOCK_MEDIUM_AND_ABOVE", alias = "block_medium_and_above")]
+    BlockMediumAndAbove,
+    #[serde(rename = "BLOCK_ONLY_HIGH", alias = "block_only_high")]
+    BlockOnlyHigh,
+    #[serde(rename = "BLOCK_NONE", alias = "block_none")]
+    BlockNone,
+    /// Turns the filter off entirely, including its ratings
+    #[serde(rename = "OFF", alias = "off")]
+    Off,
+}
+
+/// Probability rating the API assigned to a prompt or candidate. Categories
+/// are kept as strings so new ones pass through unchanged.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SafetyRating {
     pub category: String,
-    pub threshold: String,
+    pub probability: String,
+    #[serde(default)]
+    pub blocked: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PromptFeedback {
+    #[serde(default, alias = "blockReason")]
+    pub block_reason: Option<String>,
+    #[serde(default, alias = "safetyRatings")]
+    pub safety_ratings: Vec<SafetyRating>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct GenerateContentResponse {
+    // Absent when the prompt itself was blocked
+    #[serde(default)]
     pub candidates: Vec<Candidate>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "usageMetadata")]
     pub usage_metadata: Option<UsageMetadata>,
+    #[serde(default, alias = "promptFeedback")]
+    pub prompt_feedback: Option<PromptFeedback>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Candidate {
+    // Absent when the candidate was blocked
+    #[serde(default)]
     pub content: Content,
+    #[serde(alias = "finishReason")]
     pub finish_reason: Option<String>,
+    #[serde(default, alias = "safetyRatings")]
+    pub safety_ratings: Vec<SafetyRating>,
+    /// Present when `response_logprobs` was requested
+    #[serde(default, alias = "avgLogprobs")]
+    pub avg_logprobs: Option<f64>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// Body of `models/{model}:countTokens`, counting a full request so the
+/// system instruction is included
+#[derive(Debug, Serialize)]
+pub struct CountTokensRequest<'a> {
+    pub generate_content_request: CountTokensContent<'a>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CountTokensContent<'a> {
+    /// Resource name, `models/{model}`
+    pub model: String,
+    #[serde(flatten)]
+    pub request: &'a GenerateContentRequest,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CountTokensResponse {
+    #[serde(default, alias = "totalTokens")]
+    pub total_tokens: u32,
+}
+
+/// Finish reason of a candidate cut off by `max_output_tokens`
+pub const FINISH_MAX_TOKENS: &str = "MAX_TOKENS";
+
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct UsageMetadata {
+    #[serde(default, alias = "promptTokenCount")]
     pub prompt_token_count: u32,
+    #[serde(default, alias = "candidatesTokenCount")]
     pub candidates_token_count: u32,
+    #[serde(default, alias = "totalTokenCount")]
     pub total_token_count: u32,
+    /// Tokens spent thinking; included in the total but not in candidates
+    #[serde(default, alias = "thoughtsTokenCount")]
+    pub thoughts_token_count: u32,
+    /// Prompt tokens served from the context cache
+    #[serde(default, alias = "cachedContentTokenCount")]
+    pub cached_content_token_count: u32,
+}
+
+impl UsageMetadata {
+    /// Adds the usage of another request, for calls spanning several requests
+    pub fn add(&mut self, other: &UsageMetadata) {
+        self.prompt_token_count += other.prompt_token_count;
+        self.candidates_token_count += other.candidates_token_count;
+        self.total_token_count += other.total_token_count;
+        self.thoughts_token_count += other.thoughts_token_count;
+        self.cached_content_token_count += other.cached_content_token_count;
+    }
 }
 
 /// Response from generate_content that tools will use
 #[derive(Debug, Clone)]
 pub struct GenerationResponse {
+    /// Text of the first candidate
     pub text: String,
+    /// Text of every candidate that produced any, in the order returned
+    pub candidates: Vec<String>,
     pub usage: UsageMetadata,
+    /// Prompt and candidate safety ratings
+    pub safety_ratings: Vec<SafetyRating>,
+    /// Finish reason of the first candidate's final request
+    pub finish_reason: Option<String>,
+    /// Continuation requests issued after MAX_TOKENS stops
+    pub continuations: u32,
+    /// Average token log probability of the first candidate, if requested
+    pub avg_logprobs: Option<f64>,
+    /// Thought summaries of the first candidate, when `include_thoughts` was set
+    pub thoughts: Option<String>,
+    /// Model that produced the final response
+    pub model: String,
+    /// Requested model, when a fallback model answered instead
+    pub fallback_from: Option<String>,
 }
 
-impl Default for UsageMetadata {
-    fn default() -> Self {
-        Self {
-            prompt_token_count: 0,
-            candidates_token_count: 0,
-            total_token_count: 0,
-        }
-    }
+/// Response from generate_with_functions
+#[derive(Debug, Clone)]
+pub struct FunctionLoopResponse {
+    /// Text of the final model turn
+    pub text: String,
+    /// Token usage summed over every step
+    pub usage: UsageMetadata,
+    pub calls: Vec<crate::gemini::functions::FunctionCallRecord>,
+    pub steps: u32,
+    pub stop_reason: crate::gemini::functions::LoopStopReason,
+    /// Safety ratings of the final model turn
+    pub safety_ratings: Vec<SafetyRating>,
+    /// Thought summaries from every step, when `include_thoughts` was set
+    pub thoughts: Option<String>,
+    /// Model that produced the final turn
+    pub model: String,
+    /// Requested model, when a fallback model answered instead
+    pub fallback_from: Option<String>,
 }
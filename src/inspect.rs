@@ -0,0 +1,465 @@
+// Interactive inspector: `gemini-mcp inspect`
+//
+// Runs the MCP server in-process over an in-memory pipe and talks JSON-RPC
+// to it, so calls take exactly the path a client's would. Arguments are
+// prompted for from each tool's input schema. After a call the inspector
+// prints every request sent to Gemini, the raw model output and the parsed
+// result, all captured through the client's exchange tap. `save` writes the
+// session (arguments, exchanges and results) as a JSON fixture.
+
+use gemini_mcp::gemini::tap::Exchange;
+use gemini_mcp::mcp::{McpGeminiServer, ShutdownOutcome};
+use gemini_mcp::{Config, GeminiClient};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines, ReadHalf, WriteHalf};
+use tokio::task::JoinHandle;
+
+const HELP: &str = "\
+Commands:
+  tools                 List the enabled tools
+  call <tool> [json]    Call a tool by name or number; prompts for arguments unless given as JSON
+  save <path>           Write the session as a JSON fixture
+  help                  Show this help
+  quit                  Leave the inspector
+
+When prompted, press Enter to skip an optional argument. Start a string
+with @ to read it from a file, e.g. @src/main.rs.";
+
+/// A saved inspector session
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Fixture {
+    pub server_version: String,
+    pub calls: Vec<FixtureCall>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixtureCall {
+    pub tool: String,
+    pub arguments: Value,
+    /// Requests sent to Gemini and their raw responses, in order
+    pub exchanges: Vec<Exchange>,
+    /// Parsed tool result, or the JSON-RPC error
+    pub result: Value,
+    #[serde(default)]
+    pub is_error: bool,
+}
+
+pub struct Inspector {
+    requests: WriteHalf<DuplexStream>,
+    responses: Lines<BufReader<ReadHalf<DuplexStream>>>,
+    server: JoinHandle<anyhow::Result<ShutdownOutcome>>,
+    exchanges: Arc<Mutex<Vec<Exchange>>>,
+    tools: Vec<Value>,
+    calls: Vec<FixtureCall>,
+    next_id: u64,
+}
+
+impl Inspector {
+    pub async fn start(client: GeminiClient, config: Config) -> anyhow::Result<Self> {
+        let exchanges = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&exchanges);
+        let client = client.with_tap(move |exchange| seen.lock().unwrap_or_else(|e| e.into_inner()).push(exchange.clone()));
+        let server = McpGeminiServer::builder()
+            .config(config)
+            .client(Arc::new(client))
+            .build()?;
+
+        let (ours, theirs) = tokio::io::duplex(1 << 20);
+        let (server_reader, server_writer) = tokio::io::split(theirs);
+        let server = tokio::spawn(server.serve(BufReader::new(server_reader), server_writer, std::future::pending()));
+        let (reader, requests) = tokio::io::split(ours);
+
+        let mut inspector = Self {
+            requests,
+            responses: BufReader::new(reader).lines(),
+            server,
+            exchanges,
+            tools: Vec::new(),
+            calls: Vec::new(),
+            next_id: 1,
+        };
+        inspector.request("initialize", json!({})).await?;
+        let list = inspector.request("tools/list", json!({})).await?;
+        inspector.tools = list["result"]["tools"].as_array().cloned().unwrap_or_default();
+        Ok(inspector)
+    }
+
+    /// Sends one JSON-RPC request and waits for its response
+    async fn request(&mut self, method: &str, params: Value) -> anyhow::Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let line = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        self.requests.write_all(format!("{}\n", line).as_bytes()).await?;
+
+        while let Some(line) = self.responses.next_line().await? {
+            let response: Value = serde_json::from_str(&line)?;
+            // Notifications such as tools/list_changed carry no id
+            if response["id"] == id {
+                return Ok(response);
+            }
+        }
+        anyhow::bail!("Server stopped before answering {}", method)
+    }
+
+    /// Tool by name or 1-based number
+    fn tool(&self, name: &str) -> Option<&Value> {
+        match name.parse::<usize>() {
+            Ok(n) => self.tools.get(n.checked_sub(1)?),
+            Err(_) => self.tools.iter().find(|tool| tool["name"] == name),
+        }
+    }
+
+    pub async fn call(&mut self, tool: &str, arguments: Value) -> anyhow::Result<&FixtureCall> {
+        self.exchanges.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        let response = self
+            .request("tools/call", json!({"name": tool, "arguments": arguments}))
+            .await?;
+        let exchanges = std::mem::take(&mut *self.exchanges.lock().unwrap_or_else(|e| e.into_inner()));
+
+        let (result, is_error) = match response.get("error") {
+            Some(error) => (error.clone(), true),
+            None => {
+                // V2 tools return their JSON as text content
+                let text = response.pointer("/result/content/0/text").and_then(Value::as_str).unwrap_or_default();
+                (serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string())), false)
+            }
+        };
+        self.calls.push(FixtureCall {
+            tool: tool.to_string(),
+            arguments,
+            exchanges,
+            result,
+            is_error,
+        });
+        Ok(self.calls.last().expect("call was just recorded"))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let fixture = Fixture {
+            server_version: env!("CARGO_PKG_VERSION").to_string(),
+            calls: self.calls.clone(),
+        };
+        std::fs::write(path, serde_json::to_string_pretty(&fixture)? + "\n")
+            .map_err(|e| anyhow::anyhow!("Cannot write {}: {}", path.display(), e))
+    }
+
+    /// Closes the pipe and waits for the server to drain
+    pub async fn stop(mut self) -> anyhow::Result<()> {
+        self.requests.shutdown().await?;
+        self.server.await??;
+        Ok(())
+    }
+}
+
+/// The read-eval-print loop over `input`
+pub async fn repl<R: AsyncBufRead + Unpin>(mut inspector: Inspector, input: R) -> anyhow::Result<()> {
+    let mut input = input.lines();
+    println!("Gemini MCP inspector. {} tools; type 'help' for commands.", inspector.tools.len());
+    print_tools(&inspector.tools);
+
+    loop {
+        let Some(line) = read_line(&mut input, "\n> ").await? else {
+            break;
+        };
+        let (command, rest) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let rest = rest.trim();
+
+        match command {
+            "" => {}
+            "tools" | "list" => print_tools(&inspector.tools),
+            "help" => println!("{}", HELP),
+            "quit" | "exit" => break,
+            "save" if !rest.is_empty() => match inspector.save(Path::new(rest)) {
+                Ok(()) => println!("Saved {} call(s) to {}", inspector.calls.len(), rest),
+                Err(e) => println!("Error: {}", e),
+            },
+            "call" if !rest.is_empty() => {
+                let (name, json) = rest.split_once(' ').unwrap_or((rest, ""));
+                let Some(tool) = inspector.tool(name).cloned() else {
+                    println!("Unknown tool '{}'; type 'tools' to list them", name);
+                    continue;
+                };
+                let arguments = if json.trim().is_empty() {
+                    match prompt_arguments(&tool["inputSchema"], &mut input).await? {
+                        Some(arguments) => arguments,
+                        None => break,
+                    }
+                } else {
+                    match serde_json::from_str(json) {
+                        Ok(arguments) => arguments,
+                        Err(e) => {
+                            println!("Invalid JSON arguments: {}", e);
+                            continue;
+                        }
+                    }
+                };
+
+                let name = tool["name"].as_str().unwrap_or_default();
+                println!("Calling {}...", name);
+                match inspector.call(name, arguments).await {
+                    Ok(call) => print_call(call),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            _ => println!("Unknown command '{}'; type 'help'", line.trim()),
+        }
+    }
+
+    inspector.stop().await
+}
+
+async fn read_line<R: AsyncBufRead + Unpin>(input: &mut Lines<R>, prompt: &str) -> anyhow::Result<Option<String>> {
+    use std::io::Write;
+    print!("{}", prompt);
+    std::io::stdout().flush()?;
+    Ok(input.next_line().await?)
+}
+
+/// Asks for each property of `schema`; `None` when the input ends
+async fn prompt_arguments<R: AsyncBufRead + Unpin>(
+    schema: &Value,
+    input: &mut Lines<R>,
+) -> anyhow::Result<Option<Value>> {
+    let required: Vec<&str> = schema["required"]
+        .as_array()
+        .map(|names| names.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    let mut arguments = serde_json::Map::new();
+
+    for (name, property) in schema["properties"].as_object().into_iter().flatten() {
+        let is_required = required.contains(&name.as_str());
+        if let Some(description) = property["description"].as_str() {
+            println!("  {}", description);
+        }
+        let prompt = format!("{}{} [{}]: ", name, if is_required { "*" } else { "" }, describe(property));
+
+        loop {
+            let Some(text) = read_line(input, &prompt).await? else {
+                return Ok(None);
+            };
+            if text.trim().is_empty() {
+                if is_required {
+                    println!("  {} is required", name);
+                    continue;
+                }
+                break;
+            }
+            match parse_value(property, text.trim()) {
+                Ok(value) => {
+                    arguments.insert(name.clone(), value);
+                    break;
+                }
+                Err(e) => println!("  {}", e),
+            }
+        }
+    }
+
+    Ok(Some(Value::Object(arguments)))
+}
+
+/// Short type hint for a prompt, e.g. `string: brief|medium, default medium`
+fn describe(property: &Value) -> String {
+    let mut hint = match property["type"].as_str() {
+        Some("array") if property["items"]["type"] == "string" => "comma-separated list".to_string(),
+        Some(kind @ ("string" | "integer" | "number" | "boolean")) => kind.to_string(),
+        _ => "JSON".to_string(),
+    };
+    if let Some(values) = property["enum"].as_array() {
+        let values: Vec<&str> = values.iter().filter_map(Value::as_str).collect();
+        hint = format!("{}: {}", hint, values.join("|"));
+    }
+    if let Some(default) = property.get("default") {
+        hint = format!("{}, default {}", hint, default);
+    }
+    hint
+}
+
+/// Converts typed text into a JSON value for `property`
+fn parse_value(property: &Value, text: &str) -> Result<Value, String> {
+    let text = match text.strip_prefix('@') {
+        Some(path) => std::fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?,
+        None => text.to_string(),
+    };
+
+    let value = match property["type"].as_str() {
+        Some("string") => Value::String(text),
+        Some("integer") => text
+            .parse::<i64>()
+            .map(Value::from)
+            .map_err(|_| format!("'{}' is not an integer", text))?,
+        Some("number") => text
+            .parse::<f64>()
+            .map(Value::from)
+            .map_err(|_| format!("'{}' is not a number", text))?,
+        Some("boolean") => match text.to_lowercase().as_str() {
+            "true" | "yes" | "y" => Value::Bool(true),
+            "false" | "no" | "n" => Value::Bool(false),
+            _ => return Err(format!("'{}' is not true or false", text)),
+        },
+        Some("array") if property["items"]["type"] == "string" && !text.starts_with('[') => {
+            text.split(',').map(|item| Value::String(item.trim().to_string())).collect()
+        }
+        _ => serde_json::from_str(&text).map_err(|e| format!("Invalid JSON: {}", e))?,
+    };
+
+    if let Some(allowed) = property["enum"].as_array() {
+        if !allowed.contains(&value) {
+            return Err(format!("Expected one of {}", Value::Array(allowed.clone())));
+        }
+    }
+    Ok(value)
+}
+
+fn print_tools(tools: &[Value]) {
+    for (i, tool) in tools.iter().enumerate() {
+        println!(
+            "{:>3}. {:<22} {}",
+            i + 1,
+            tool["name"].as_str().unwrap_or_default(),
+            tool["description"].as_str().unwrap_or_default()
+        );
+    }
+}
+
+fn print_call(call: &FixtureCall) {
+    let total = call.exchanges.len();
+    for (i, exchange) in call.exchanges.iter().enumerate() {
+        println!("\n=== Prompt {}/{} -> {} ===", i + 1, total, exchange.model);
+        println!("{}", render_request(&exchange.request));
+        println!("\n=== Raw output {}/{} ===", i + 1, total);
+        match (&exchange.error, exchange.output_text()) {
+            (Some(error), _) => println!("Error: {}", error),
+            (None, Some(text)) if !text.is_empty() => println!("{}", text),
+            _ => println!("{}", pretty(exchange.response.as_ref().unwrap_or(&Value::Null))),
+        }
+    }
+    if total == 0 {
+        println!("\n(no requests were sent to Gemini)");
+    }
+
+    println!("\n=== {} ===", if call.is_error { "Error" } else { "Result" });
+    match &call.result {
+        Value::String(text) => println!("{}", text),
+        value => println!("{}", pretty(value)),
+    }
+}
+
+/// The system instruction, each turn and the generation config of a request
+fn render_request(request: &Value) -> String {
+    let field = |snake: &str, camel: &str| request.get(snake).or_else(|| request.get(camel)).cloned();
+    let mut sections = Vec::new();
+
+    if let Some(system) = field("system_instruction", "systemInstruction") {
+        sections.push(format!("[system]\n{}", render_parts(&system["parts"])));
+    }
+    for content in field("contents", "contents").and_then(|c| c.as_array().cloned()).unwrap_or_default() {
+        let role = content["role"].as_str().unwrap_or("user");
+        sections.push(format!("[{}]\n{}", role, render_parts(&content["parts"])));
+    }
+    if let Some(config) = field("generation_config", "generationConfig") {
+        sections.push(format!("[generation_config] {}", config));
+    }
+    sections.join("\n\n")
+}
+
+fn render_parts(parts: &Value) -> String {
+    parts
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|part| match part["text"].as_str() {
+            Some(text) => text.to_string(),
+            None => part.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gemini_mcp::KeyPool;
+
+    #[test]
+    fn test_parse_value_follows_schema() {
+        let length = json!({"type": "string", "enum": ["brief", "medium"], "default": "medium"});
+        assert_eq!(describe(&length), "string: brief|medium, default \"medium\"");
+        assert_eq!(parse_value(&length, "brief").unwrap(), json!("brief"));
+        assert!(parse_value(&length, "tiny").unwrap_err().contains("brief"));
+
+        assert_eq!(parse_value(&json!({"type": "integer"}), "12").unwrap(), json!(12));
+        assert!(parse_value(&json!({"type": "integer"}), "twelve").is_err());
+        assert_eq!(parse_value(&json!({"type": "boolean"}), "y").unwrap(), json!(true));
+
+        let roots = json!({"type": "array", "items": {"type": "string"}});
+        assert_eq!(parse_value(&roots, "src, tests").unwrap(), json!(["src", "tests"]));
+        assert_eq!(parse_value(&json!({"type": "object"}), r#"{"type": "code"}"#).unwrap(), json!({"type": "code"}));
+        assert!(parse_value(&json!({"type": "string"}), "@/nonexistent/file").is_err());
+    }
+
+    #[test]
+    fn test_render_request() {
+        let request = json!({
+            "system_instruction": {"parts": [{"text": "Be brief."}]},
+            "contents": [{"role": "user", "parts": [{"text": "Summarize this."}]}],
+            "generation_config": {"temperature": 0.4}
+        });
+        assert_eq!(
+            render_request(&request),
+            "[system]\nBe brief.\n\n[user]\nSummarize this.\n\n[generation_config] {\"temperature\":0.4}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_call_records_exchanges_and_result() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", mockito::Matcher::Regex(":generateContent$".to_string()))
+            .with_body(r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "Short summary."}]}}]}"#)
+            .create_async()
+            .await;
+
+        let mut config = Config::builtin();
+        config.client.base_url = Some(server.url());
+        let client = GeminiClient::from_config(KeyPool::single("test-key".to_string()).unwrap(), &config).unwrap();
+        let mut inspector = Inspector::start(client, config)
+            .await
+            .unwrap();
+        assert!(inspector.tool("gemini-summarize-v2").is_some());
+        assert_eq!(inspector.tool("1"), inspector.tools.first());
+
+        let call = inspector
+            .call("gemini-summarize-v2", json!({"content": "A long text.", "length": "brief"}))
+            .await
+            .unwrap()
+            .clone();
+        mock.assert_async().await;
+
+        assert!(!call.is_error);
+        assert_eq!(call.exchanges.len(), 1);
+        assert!(render_request(&call.exchanges[0].request).contains("A long text."));
+        assert_eq!(call.exchanges[0].output_text().as_deref(), Some("Short summary."));
+        assert_eq!(call.result["result"]["summary"], "Short summary.");
+        assert_eq!(call.result["metadata"]["template"]["id"], "summarize");
+
+        let error = inspector.call("gemini-summarize-v2", json!({})).await.unwrap().clone();
+        assert!(error.is_error);
+        assert!(error.exchanges.is_empty());
+
+        let path = std::env::temp_dir().join(format!("gemini-mcp-inspect-{}.json", std::process::id()));
+        inspector.save(&path).unwrap();
+        let fixture: Fixture = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(fixture.calls.len(), 2);
+        assert_eq!(fixture.calls[0].exchanges, call.exchanges);
+        std::fs::remove_file(&path).unwrap();
+
+        inspector.stop().await.unwrap();
+    }
+}
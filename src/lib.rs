@@ -0,0 +1,47 @@
+// Gemini client, tool pipelines and MCP server as a library
+//
+// The `gemini-mcp` binary is a thin CLI over this crate. Features:
+//   - `server`: the MCP JSON-RPC server over any async reader/writer
+//   - `stdio`:  stdin/stdout transport with signal handling (implies `server`)
+//   - `cli`:    the binary and its dependencies (default)
+// Build with `default-features = false` for just the client and tools.
+
+//! Gemini API client, tool pipelines and an embeddable MCP server.
+//!
+//! ```no_run
+//! use gemini_mcp::tools::summarize::{self, SummarizeInput, SummaryLength};
+//! use gemini_mcp::{Config, GeminiClient, KeyPool, ToolSettings};
+//! use std::sync::Arc;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let config = Config::builtin();
+//! let client = Arc::new(GeminiClient::from_config(KeyPool::single("key".into())?, &config)?);
+//! let settings = ToolSettings::from_config(&config)?;
+//!
+//! let input = SummarizeInput {
+//!     length: SummaryLength::Brief,
+//!     ..SummarizeInput::new("Rust is a systems language.")
+//! };
+//! let response = summarize::execute_v2(input, client, &settings).await?;
+//! println!("{}", response.result.summary);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod config;
+pub mod error;
+pub mod eval;
+pub mod gemini;
+#[cfg(feature = "server")]
+pub mod mcp;
+pub mod tools;
+#[cfg(test)]
+mod test_support;
+
+pub use config::Config;
+pub use error::{GeminiError, Result};
+pub use gemini::{GeminiClient, GenerationBackend, KeyPool, MockBackend};
+#[cfg(feature = "server")]
+pub use mcp::{McpGeminiServer, ServerBuilder};
+pub use tools::settings::ToolSettings;
+pub use tools::types::{GenerationParams, ResponseMetadata, ToolResponse};
@@ -1,12 +1,18 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use dotenvy::dotenv;
-use tracing::info;
+use gemini_mcp::eval::{Evaluator, Report, Suite};
+use gemini_mcp::gemini::cassette::Cassette;
+use gemini_mcp::tools::sandbox::Sandbox;
+use gemini_mcp::{config, gemini, mcp};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::{info, warn};
 use tracing_subscriber::EnvFilter;
 
-mod error;
-mod gemini;
-mod mcp;
-mod tools;
+mod inspect;
+mod run;
+
+const REPLAY_KEY: &str = "replay";
 
 #[derive(Parser, Debug)]
 #[command(name = "gemini-mcp")]
@@ -20,43 +26,280 @@ struct Cli {
     /// Run in quiet mode
     #[arg(short, long)]
     quiet: bool,
+
+    /// Seconds to wait for in-flight tool calls on shutdown (overrides
+    /// server.shutdown_timeout_secs)
+    #[arg(long)]
+    shutdown_timeout: Option<u64>,
+
+    /// File containing the API key (overrides GEMINI_API_KEY_FILE)
+    #[arg(long, global = true)]
+    api_key_file: Option<String>,
+
+    /// Shell command that prints the API key, e.g. "pass show gemini"
+    /// (overrides GEMINI_API_KEY_COMMAND)
+    #[arg(long, global = true)]
+    api_key_command: Option<String>,
+
+    /// Project config file (default: nearest gemini-mcp.toml or
+    /// .gemini-mcp.toml in the working directory or a parent)
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Do not reload the config files when they change
+    #[arg(long)]
+    no_watch: bool,
+
+    /// Record Gemini requests and responses to a cassette file
+    #[arg(long, global = true, value_name = "PATH", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Answer Gemini requests from a recorded cassette instead of the network
+    #[arg(long, global = true, value_name = "PATH")]
+    replay: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Run one tool and print its response instead of serving MCP
+    Run(Box<run::RunArgs>),
+    /// Call tools interactively and see the prompts and raw model output
+    Inspect,
+    /// Run golden cases and report pass rates
+    Eval(Box<EvalArgs>),
+}
+
+#[derive(clap::Args, Debug)]
+struct EvalArgs {
+    /// Case files, or directories of `*.toml` case files
+    #[arg(required = true)]
+    cases: Vec<PathBuf>,
+
+    /// Name for this run in reports (default: the templates dir or "builtin")
+    #[arg(long)]
+    label: Option<String>,
+
+    /// Model for cases whose input does not set one
+    #[arg(long)]
+    model: Option<String>,
+
+    /// JSON report of an earlier run to compare against
+    #[arg(long, value_name = "REPORT", conflicts_with = "baseline_templates")]
+    baseline: Option<PathBuf>,
+
+    /// Also run the cases with the templates in DIR and compare against them
+    #[arg(long, value_name = "DIR")]
+    baseline_templates: Option<PathBuf>,
+
+    /// Write the JSON report to PATH
+    #[arg(long, value_name = "PATH")]
+    json: Option<PathBuf>,
+
+    /// Write the Markdown report to PATH
+    #[arg(long, value_name = "PATH")]
+    markdown: Option<PathBuf>,
+
+    /// Exit with status 1 when fewer cases pass (0.0 to 1.0)
+    #[arg(long, default_value_t = 1.0)]
+    min_pass_rate: f64,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the effective configuration after merging all layers
+    Show,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv().ok();
 
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
 
-    // Setup logging
-    let filter = if cli.verbose {
-        EnvFilter::new("debug")
+    // Built-in defaults < user file < project file < environment < CLI
+    let mut overrides = config::Config::default();
+    overrides.server.shutdown_timeout_secs = cli.shutdown_timeout;
+    if cli.verbose {
+        overrides.logging.level = Some("debug".to_string());
     } else if cli.quiet {
-        EnvFilter::new("error")
-    } else {
-        EnvFilter::new("info")
+        overrides.logging.level = Some("error".to_string());
+    }
+    let loaded = match config::load(cli.config.as_deref(), overrides.clone()) {
+        Ok(loaded) => loaded,
+        Err(e) if matches!(cli.command, Some(Command::Run(_))) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(run::EXIT_CONFIG);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let command = match cli.command.take() {
+        Some(Command::Config {
+            action: ConfigCommand::Show,
+        }) => {
+            for file in &loaded.files {
+                println!("# Loaded {}", file.display());
+            }
+            print!("{}", loaded.config.to_toml());
+            return Ok(());
+        }
+        command => command,
     };
 
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_target(false)
-        .init();
+    // Setup logging. Runs and the inspector keep stdout for their output.
+    let level = loaded.config.logging.level.as_deref().unwrap_or("info");
+    let filter = match EnvFilter::try_new(level) {
+        Ok(filter) => filter,
+        Err(e) if matches!(command, Some(Command::Run(_))) => {
+            eprintln!("Error: Invalid logging.level '{}': {}", level, e);
+            std::process::exit(run::EXIT_CONFIG);
+        }
+        Err(e) => anyhow::bail!("Invalid logging.level '{}': {}", level, e),
+    };
+
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter).with_target(false);
+    if command.is_some() {
+        subscriber.with_writer(std::io::stderr).init();
+    } else {
+        subscriber.init();
+    }
+
+    if let Some(Command::Inspect) = command {
+        let inspector = inspect::Inspector::start(build_client(&cli, &loaded.config)?, loaded.config).await?;
+        return inspect::repl(inspector, tokio::io::BufReader::new(tokio::io::stdin())).await;
+    }
+
+    if let Some(Command::Eval(args)) = command {
+        let report = execute_eval(&cli, &loaded.config, args.as_ref()).await?;
+        print!("{}", report.to_markdown());
+        if report.pass_rate < args.min_pass_rate {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Run(args)) = command {
+        let code = match execute_run(&cli, &loaded.config, *args).await {
+            Ok(text) => {
+                println!("{}", text);
+                run::EXIT_OK
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                run::exit_code(&e)
+            }
+        };
+        std::process::exit(code);
+    }
 
     info!("Starting Gemini MCP Server (Rust) v{}", env!("CARGO_PKG_VERSION"));
 
-    // Get API key
-    let api_key = std::env::var("GEMINI_API_KEY").map_err(|_| {
-        anyhow::anyhow!("GEMINI_API_KEY environment variable required")
-    })?;
+    let client = Arc::new(build_client(&cli, &loaded.config)?);
+
+    // Create server. Connectivity is probed lazily in the background so the
+    // server still starts when the network or quota is unavailable.
+    let mut builder = mcp::McpGeminiServer::builder()
+        .config(loaded.config)
+        .client(client);
+    if !cli.no_watch {
+        let watcher = mcp::reload::ConfigWatcher::new(config::user_path(), cli.config.clone(), overrides);
+        builder = builder.watcher(watcher);
+    }
+
+    // Run server until stdin closes or a signal arrives
+    let outcome = builder.build()?.run().await?;
 
-    // Create server
-    let server = mcp::server::McpGeminiServer::new(api_key)?;
+    std::process::exit(outcome.exit_code());
+}
+
+/// API keys from the command line, the environment or the keyring
+fn load_keys(cli: &Cli) -> gemini_mcp::Result<gemini::keys::KeyPool> {
+    gemini::keys::KeyPool::from_lookup(|name| {
+        match name {
+            gemini::keys::KEY_FILE_ENV => cli.api_key_file.clone(),
+            gemini::keys::KEY_COMMAND_ENV => cli.api_key_command.clone(),
+            _ => None,
+        }
+        .or_else(|| std::env::var(name).ok())
+    })
+}
+
+/// Client with the cassette from `--record` or `--replay`, if any
+fn build_client(cli: &Cli, config: &config::Config) -> gemini_mcp::Result<gemini::GeminiClient> {
+    let keys = match load_keys(cli) {
+        // Replays never reach the API, so they need no key
+        Err(_) if cli.replay.is_some() => gemini::keys::KeyPool::single(REPLAY_KEY.to_string())?,
+        keys => keys?,
+    };
+    let client = gemini::GeminiClient::from_config(keys, config)?;
+    Ok(match (&cli.record, &cli.replay) {
+        (Some(path), _) => client.with_cassette(Cassette::record(path)?),
+        (_, Some(path)) => client.with_cassette(Cassette::replay(path)?),
+        _ => client,
+    })
+}
 
-    // Test connection
-    server.test_connection().await?;
+async fn execute_run(cli: &Cli, config: &config::Config, args: run::RunArgs) -> anyhow::Result<String> {
+    let client = Arc::new(build_client(cli, config)?);
+    // A one-shot run has no background to refresh an expired model list in;
+    // replays never touch the API
+    if cli.replay.is_none() {
+        if let Err(e) = client.refresh_stale_models().await {
+            warn!("Could not refresh the model list: {}", e);
+        }
+    }
+    let settings = gemini_mcp::ToolSettings::from_config(config)?;
+    run::execute(args, client, &settings).await
+}
+
+/// Runs the suite, and the baseline templates if given, and writes reports
+async fn execute_eval(cli: &Cli, config: &config::Config, args: &EvalArgs) -> anyhow::Result<Report> {
+    let suite = Suite::load(&args.cases)?;
+    let backend = Arc::new(build_client(cli, config)?);
+    let evaluator = |config: &config::Config| -> anyhow::Result<Evaluator> {
+        Ok(Evaluator::new(backend.clone(), gemini_mcp::ToolSettings::from_config(config)?)
+            .with_sandbox(Sandbox::from_env().ok())
+            .with_model(args.model.clone()))
+    };
+
+    let baseline = match (&args.baseline, &args.baseline_templates) {
+        (Some(path), _) => {
+            let text = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Cannot read {}: {}", path.display(), e))?;
+            Some(serde_json::from_str::<Report>(&text).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?)
+        }
+        (_, Some(dir)) => {
+            let mut baseline_config = config.clone();
+            baseline_config.templates.dir = Some(dir.display().to_string());
+            Some(evaluator(&baseline_config)?.run(&suite, &dir.display().to_string()).await)
+        }
+        _ => None,
+    };
 
-    // Run server
-    server.run().await?;
+    let label = args
+        .label
+        .clone()
+        .unwrap_or_else(|| config.templates.dir.clone().unwrap_or_else(|| "builtin".to_string()));
+    let mut report = evaluator(config)?.run(&suite, &label).await;
+    if let Some(baseline) = &baseline {
+        report.compare(baseline);
+    }
 
-    Ok(())
+    if let Some(path) = &args.json {
+        std::fs::write(path, serde_json::to_string_pretty(&report)? + "\n")
+            .map_err(|e| anyhow::anyhow!("Cannot write {}: {}", path.display(), e))?;
+    }
+    if let Some(path) = &args.markdown {
+        std::fs::write(path, report.to_markdown())
+            .map_err(|e| anyhow::anyhow!("Cannot write {}: {}", path.display(), e))?;
+    }
+    Ok(report)
 }
@@ -1,2 +1,7 @@
-// MCP server module - to be implemented in Week 4
+// MCP server: JSON-RPC dispatch, config reload and graceful shutdown
+pub mod reload;
 pub mod server;
+pub mod shutdown;
+
+pub use server::{McpGeminiServer, ServerBuilder};
+pub use shutdown::{ShutdownOutcome, ShutdownReason};
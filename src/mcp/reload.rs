@@ -0,0 +1,125 @@
+// Configuration hot reload
+//
+// Polls the user and project config files. When one of them changes, all
+// layers are loaded again and validated before the server swaps them in.
+// An invalid file is logged and skipped, so the last good configuration
+// stays in effect until the file is fixed.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::config::{self, Config, Loaded};
+use crate::error::Result;
+
+/// How often the config files are checked for changes
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Modification time and size; `None` while the file does not exist
+type Stamp = Option<(SystemTime, u64)>;
+
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    user: Option<PathBuf>,
+    /// Project file from `--config`; otherwise discovered on every poll, so
+    /// a newly created file is picked up
+    project: Option<PathBuf>,
+    /// Values from the command line, applied on top of every reload
+    overrides: Config,
+    stamps: Vec<(PathBuf, Stamp)>,
+    interval: Duration,
+}
+
+impl ConfigWatcher {
+    pub fn new(user: Option<PathBuf>, project: Option<PathBuf>, overrides: Config) -> Self {
+        let mut watcher = Self {
+            user,
+            project,
+            overrides,
+            stamps: Vec::new(),
+            interval: DEFAULT_POLL_INTERVAL,
+        };
+        watcher.stamps = watcher.stamps();
+        watcher
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    fn project(&self) -> Option<PathBuf> {
+        self.project
+            .clone()
+            .or_else(|| std::env::current_dir().ok().and_then(|dir| config::project_path(&dir)))
+    }
+
+    fn stamps(&self) -> Vec<(PathBuf, Stamp)> {
+        self.user
+            .iter()
+            .cloned()
+            .chain(self.project())
+            .map(|path| {
+                let stamp = stamp(&path);
+                (path, stamp)
+            })
+            .collect()
+    }
+
+    /// Loads the configuration again if a watched file changed since the
+    /// last poll
+    pub fn poll(&mut self) -> Option<Result<Loaded>> {
+        let stamps = self.stamps();
+        if stamps == self.stamps {
+            return None;
+        }
+        self.stamps = stamps;
+
+        let project = self.project();
+        Some(config::load_layers(
+            self.user.as_deref(),
+            project.as_deref(),
+            self.overrides.clone(),
+        ))
+    }
+}
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn test_poll_reloads_changed_files() {
+        let dir = temp_dir("reload");
+        let user = dir.join("config.toml");
+        let project = dir.join("gemini-mcp.toml");
+        std::fs::write(&project, "[tools.brainstorm]\ntemperature = 1.0\n").unwrap();
+
+        let mut watcher = ConfigWatcher::new(Some(user.clone()), Some(project.clone()), Config::default());
+        assert!(watcher.poll().is_none());
+
+        // A new user file counts as a change; the project file still wins
+        std::fs::write(&user, "[tools.brainstorm]\ntemperature = 0.5\nmax_tokens = 100\n").unwrap();
+        let loaded = watcher.poll().unwrap().unwrap();
+        let brainstorm = &loaded.config.tools.defaults["brainstorm"];
+        assert_eq!(brainstorm.temperature, Some(1.0));
+        assert_eq!(brainstorm.max_tokens, Some(100));
+        assert_eq!(loaded.files, vec![user.clone(), project.clone()]);
+        assert!(watcher.poll().is_none());
+
+        std::fs::write(&project, "[tools.brainstorm]\ntemperature = 9.0\n").unwrap();
+        let err = watcher.poll().unwrap().unwrap_err();
+        assert!(err.to_string().contains("temperature"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
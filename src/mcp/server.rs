@@ -1,89 +1,331 @@
-// Simple stdio JSON-RPC MCP server implementation
-// Direct protocol implementation without rust-mcp-sdk due to API complexity
+// Simple JSON-RPC MCP server implementation
+// Direct protocol implementation without rust-mcp-sdk due to API complexity.
+// `serve` speaks newline-delimited JSON-RPC over any async reader/writer
+// pair; `run` (feature `stdio`) binds it to stdin/stdout and process signals.
 
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tracing::{debug, error, info};
-
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::future::Future;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, warn};
+
+use crate::config::Config;
+use crate::error::GeminiError;
+use crate::gemini::backend::GenerationBackend;
 use crate::gemini::client::GeminiClient;
+use crate::gemini::health::{HealthMonitor, DEFAULT_STALE_AFTER};
+use crate::gemini::keys::KeyPool;
+use crate::mcp::reload::ConfigWatcher;
+use crate::mcp::shutdown::{ShutdownCoordinator, ShutdownOutcome, ShutdownReason, DEFAULT_SHUTDOWN_TIMEOUT};
 use crate::tools;
+use crate::tools::settings::ToolSettings;
+use crate::tools::sandbox::Sandbox;
+
+const MODEL_DESCRIPTION: &str =
+    "Model id such as gemini-2.5-flash-lite, or an alias (pro, flash, or one from [models.aliases] in the config file or GEMINI_MODEL_ALIASES)";
 
 pub struct McpGeminiServer {
-    client: Arc<GeminiClient>,
+    backend: Arc<dyn GenerationBackend>,
+    health: Arc<HealthMonitor>,
+    shutdown: ShutdownCoordinator,
+    /// Per-tool defaults and the enabled tool set, swapped on reload
+    tools: RwLock<Arc<ToolSettings>>,
+    /// Reloads the configuration when a config file changes
+    watcher: Option<ConfigWatcher>,
+    /// Allowed roots for gemini-agent; `None` disables the tool
+    sandbox: Option<Sandbox>,
 }
 
-impl McpGeminiServer {
-    pub fn new(api_key: String) -> anyhow::Result<Self> {
-        let client = GeminiClient::new(api_key)?;
-        Ok(Self {
-            client: Arc::new(client),
+/// Builds an [`McpGeminiServer`] from a configuration and either a key pool
+/// or an existing backend
+pub struct ServerBuilder {
+    config: Config,
+    keys: Option<KeyPool>,
+    backend: Option<Arc<dyn GenerationBackend>>,
+    sandbox: Option<Option<Sandbox>>,
+    shutdown_timeout: Option<Duration>,
+    watcher: Option<ConfigWatcher>,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self {
+            config: Config::builtin(),
+            keys: None,
+            backend: None,
+            sandbox: None,
+            shutdown_timeout: None,
+            watcher: None,
+        }
+    }
+}
+
+impl ServerBuilder {
+    /// Merged configuration; defaults to the built-in one
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// API keys for a client built from the configuration
+    pub fn keys(mut self, keys: KeyPool) -> Self {
+        self.keys = Some(keys);
+        self
+    }
+
+    /// Shares an existing client instead of building one; `keys` and the
+    /// client settings of the configuration are then ignored
+    pub fn client(self, client: Arc<GeminiClient>) -> Self {
+        self.backend(client)
+    }
+
+    /// Serves tool calls from any backend, e.g. a `MockBackend` in tests
+    pub fn backend(mut self, backend: Arc<dyn GenerationBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Allowed roots for gemini-agent; `None` disables the tool. Defaults
+    /// to `GEMINI_AGENT_ROOTS` or the working directory.
+    pub fn sandbox(mut self, sandbox: Option<Sandbox>) -> Self {
+        self.sandbox = Some(sandbox);
+        self
+    }
+
+    /// Sets how long shutdown waits for in-flight tool calls, instead of
+    /// `server.shutdown_timeout_secs` from the configuration
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = Some(timeout);
+        self
+    }
+
+    /// Watches the config files and applies changes while running
+    pub fn watcher(mut self, watcher: ConfigWatcher) -> Self {
+        self.watcher = Some(watcher);
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<McpGeminiServer> {
+        let backend = match (self.backend, self.keys) {
+            (Some(backend), _) => backend,
+            (None, Some(keys)) => Arc::new(GeminiClient::from_config(keys, &self.config)?),
+            (None, None) => anyhow::bail!("Server needs API keys or a client"),
+        };
+        let health = Arc::new(HealthMonitor::new(Arc::clone(&backend), DEFAULT_STALE_AFTER));
+        let shutdown_timeout = self.shutdown_timeout.unwrap_or_else(|| {
+            self.config
+                .server
+                .shutdown_timeout_secs
+                .map_or(DEFAULT_SHUTDOWN_TIMEOUT, Duration::from_secs)
+        });
+
+        let sandbox = self.sandbox.unwrap_or_else(|| match Sandbox::from_env() {
+            Ok(sandbox) => Some(sandbox),
+            Err(e) => {
+                warn!("gemini-agent disabled: {}", e);
+                None
+            }
+        });
+        if let Some(sandbox) = &sandbox {
+            debug!("Agent roots: {:?}", sandbox.roots());
+        }
+
+        Ok(McpGeminiServer {
+            backend,
+            health,
+            shutdown: ShutdownCoordinator::new(shutdown_timeout),
+            tools: RwLock::new(Arc::new(ToolSettings::from_config(&self.config)?)),
+            watcher: self.watcher,
+            sandbox,
         })
     }
+}
+
+impl McpGeminiServer {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
 
-    pub async fn test_connection(&self) -> anyhow::Result<()> {
-        self.client.test_connection().await?;
-        Ok(())
+    /// Server with a client built from `keys` and `config`
+    pub fn new(keys: KeyPool, config: &Config) -> anyhow::Result<Self> {
+        Self::builder().keys(keys).config(config.clone()).build()
     }
 
-    pub async fn run(self) -> anyhow::Result<()> {
+    /// The backend shared by all tool calls
+    pub fn backend(&self) -> &Arc<dyn GenerationBackend> {
+        &self.backend
+    }
+
+    fn tools(&self) -> Arc<ToolSettings> {
+        Arc::clone(&self.tools.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Swaps in a new configuration. Invalid settings are rejected before
+    /// anything changes. Returns whether the set of enabled tools changed.
+    pub fn apply_config(&self, config: &Config) -> anyhow::Result<bool> {
+        let settings = ToolSettings::from_config(config)?;
+
+        let mut tools = self.tools.write().unwrap_or_else(|e| e.into_inner());
+        self.backend.apply_config(config)?;
+        let changed = tools.enabled_names() != settings.enabled_names();
+        *tools = Arc::new(settings);
+        Ok(changed)
+    }
+
+    /// Coordinator used to register state that must be flushed on shutdown
+    pub fn shutdown_coordinator(&self) -> &ShutdownCoordinator {
+        &self.shutdown
+    }
+
+    /// Serves stdin/stdout until stdin closes or SIGINT/SIGTERM arrives
+    #[cfg(feature = "stdio")]
+    pub async fn run(self) -> anyhow::Result<ShutdownOutcome> {
         info!("Starting MCP server (stdio JSON-RPC)");
+        let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+        self.serve(stdin, tokio::io::stdout(), crate::mcp::shutdown::wait_for_signal())
+            .await
+    }
+
+    /// Serves newline-delimited JSON-RPC from `reader` to `writer` until the
+    /// reader is exhausted or `shutdown` completes, then drains in-flight
+    /// calls
+    pub async fn serve<R, W, S>(mut self, mut reader: R, writer: W, shutdown: S) -> anyhow::Result<ShutdownOutcome>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin + Send + 'static,
+        S: Future<Output = ShutdownReason>,
+    {
+        let watcher = self.watcher.take();
+        let server = Arc::new(self);
 
-        // Simple stdio message loop
-        let stdin = tokio::io::stdin();
-        let mut stdout = tokio::io::stdout();
-        let mut reader = BufReader::new(stdin);
+        // Requests are handled concurrently; a single writer task serializes
+        // responses onto the writer so lines never interleave
+        let (tx, rx) = mpsc::unbounded_channel::<Outgoing>();
+        let writer = tokio::spawn(write_responses(writer, rx));
 
-        loop {
+        let reloader = watcher.map(|watcher| tokio::spawn(watch_config(Arc::clone(&server), watcher, tx.clone())));
+
+        let mut tasks = JoinSet::new();
+        let mut pending = HashMap::new();
+
+        tokio::pin!(shutdown);
+
+        let reason = loop {
             let mut line = String::new();
-            match reader.read_line(&mut line).await {
-                Ok(0) => {
-                    info!("EOF reached, shutting down");
-                    break;
+            tokio::select! {
+                reason = &mut shutdown => {
+                    info!("Received {:?}, shutting down", reason);
+                    break reason;
                 }
-                Ok(_) => {
-                    let line = line.trim();
-                    if line.is_empty() {
-                        continue;
+                read = reader.read_line(&mut line) => match read {
+                    Ok(0) => {
+                        info!("EOF reached, shutting down");
+                        break ShutdownReason::Eof;
                     }
+                    Ok(_) => {
+                        let line = line.trim();
+                        if line.is_empty() {
+                            continue;
+                        }
 
-                    debug!("Received: {}", line);
+                        debug!("Received: {}", line);
 
-                    // Parse JSON-RPC request
-                    let response = match serde_json::from_str::<JsonRpcRequest>(line) {
-                        Ok(request) => self.handle_request(request).await,
-                        Err(e) => {
-                            error!("Invalid JSON: {}", e);
-                            JsonRpcResponse::error(-32700, "Parse error", None)
-                        }
-                    };
+                        // Parse JSON-RPC request
+                        let request = match serde_json::from_str::<JsonRpcRequest>(line) {
+                            Ok(request) => request,
+                            Err(e) => {
+                                error!("Invalid JSON: {}", e);
+                                let _ = tx.send(JsonRpcResponse::error(-32700, "Parse error", None).into());
+                                continue;
+                            }
+                        };
+
+                        let request_id = request.id.clone();
+                        let server = Arc::clone(&server);
+                        let tx = tx.clone();
+                        let handle = tasks.spawn(async move {
+                            let response = server.handle_request(request).await;
+                            let _ = tx.send(response.into());
+                        });
+                        pending.insert(handle.id(), request_id);
+                    }
+                    Err(e) => {
+                        error!("Error reading request: {}", e);
+                        break ShutdownReason::ReadError;
+                    }
+                },
+            }
 
-                    // Send response
-                    let response_json = serde_json::to_string(&response)?;
-                    stdout.write_all(response_json.as_bytes()).await?;
-                    stdout.write_all(b"\n").await?;
-                    stdout.flush().await?;
-                }
-                Err(e) => {
-                    error!("Error reading stdin: {}", e);
-                    break;
-                }
+            // Reap finished calls so `pending` only holds in-flight requests
+            while let Some(result) = tasks.try_join_next_with_id() {
+                let id = match result {
+                    Ok((id, ())) => id,
+                    Err(e) => e.id(),
+                };
+                pending.remove(&id);
             }
+        };
+
+        if let Some(reloader) = reloader {
+            reloader.abort();
+        }
+
+        // Stop accepting requests, then wait for in-flight calls
+        let (completed, abandoned) = server.shutdown.drain(&mut tasks, &mut pending).await;
+        for id in &abandoned {
+            let _ = tx.send(JsonRpcResponse::error(
+                -32603,
+                "Server shutting down before the call completed",
+                Some(id.clone()),
+            ).into());
         }
 
-        Ok(())
+        // Closing the channel lets the writer finish once everything is written
+        drop(tx);
+        let write_result = writer.await?;
+
+        let mut flush_failures = server.shutdown.flush().await;
+        if let Err(e) = write_result {
+            error!("Failed to write responses: {}", e);
+            flush_failures += 1;
+        }
+
+        let outcome = ShutdownOutcome {
+            reason,
+            completed,
+            abandoned: abandoned.len(),
+            flush_failures,
+        };
+        info!(
+            "Shutdown complete: {} drained, {} abandoned, {} flush failure(s)",
+            outcome.completed, outcome.abandoned, outcome.flush_failures
+        );
+
+        Ok(outcome)
     }
 
     async fn handle_request(&self, request: JsonRpcRequest) -> JsonRpcResponse {
+        if request.jsonrpc != "2.0" {
+            return JsonRpcResponse::error(-32600, "Invalid Request", Some(request.id));
+        }
+
         match request.method.as_str() {
             "initialize" => {
                 info!("Handling initialize request");
+                // Probe connectivity in the background so the first tool call
+                // doesn't pay for it and startup never blocks on the network
+                self.health.refresh_if_stale();
+                self.refresh_stale_models();
                 JsonRpcResponse::success(
                     request.id,
                     serde_json::json!({
                         "protocolVersion": "2024-11-05",
                         "capabilities": {
-                            "tools": {}
+                            "tools": {"listChanged": true}
                         },
                         "serverInfo": {
                             "name": "Gemini MCP Server (Rust)",
@@ -100,12 +342,28 @@ impl McpGeminiServer {
                 info!("Handling tools/call request");
                 self.call_tool(request.id, request.params).await
             }
+            "ping" => {
+                self.health.refresh_if_stale();
+                let report = serde_json::to_value(self.health.report())
+                    .unwrap_or_else(|_| serde_json::json!({}));
+                JsonRpcResponse::success(request.id, report)
+            }
             _ => JsonRpcResponse::error(-32601, "Method not found", Some(request.id)),
         }
     }
 
+    /// Fetches an expired model list in the background, off the request path
+    fn refresh_stale_models(&self) {
+        let backend = Arc::clone(&self.backend);
+        tokio::spawn(async move {
+            if let Err(e) = backend.refresh_stale_models().await {
+                warn!("Could not refresh the model list: {}", e);
+            }
+        });
+    }
+
     fn list_tools(&self, id: serde_json::Value) -> JsonRpcResponse {
-        let tools = serde_json::json!({
+        let mut tools = serde_json::json!({
             "tools": [
                 {
                     "name": "gemini-query",
@@ -114,9 +372,10 @@ impl McpGeminiServer {
                         "type": "object",
                         "properties": {
                             "prompt": {"type": "string"},
-                            "model": {"type": "string", "enum": ["pro", "flash"], "default": "pro"},
+                            "model": {"type": "string", "default": "pro", "description": MODEL_DESCRIPTION},
                             "temperature": {"type": "number"},
-                            "max_output_tokens": {"type": "integer"}
+                            "max_output_tokens": {"type": "integer"},
+                            "candidate_count": {"type": "integer", "minimum": 1, "maximum": 8, "description": "Number of alternative answers to return as numbered variants"}
                         },
                         "required": ["prompt"]
                     }
@@ -201,16 +460,8 @@ impl McpGeminiServer {
                             },
                             "ranking": {"type": "string", "enum": ["relevance", "recency", "popularity"], "default": "relevance"},
                             "include_citations": {"type": "boolean", "default": true},
-                            "model": {"type": "string", "enum": ["pro", "flash"]},
-                            "params": {
-                                "type": "object",
-                                "properties": {
-                                    "temperature": {"type": "number"},
-                                    "max_tokens": {"type": "integer"},
-                                    "top_p": {"type": "number"},
-                                    "top_k": {"type": "integer"}
-                                }
-                            }
+                            "model": {"type": "string", "description": MODEL_DESCRIPTION},
+                            "params": generation_params_schema()
                         },
                         "required": ["query", "sources"]
                     }
@@ -239,8 +490,8 @@ impl McpGeminiServer {
                                     "detail_level": {"type": "string", "enum": ["brief", "standard", "comprehensive"], "default": "standard"}
                                 }
                             },
-                            "model": {"type": "string", "enum": ["pro", "flash"]},
-                            "params": {"type": "object"}
+                            "model": {"type": "string", "description": MODEL_DESCRIPTION},
+                            "params": generation_params_schema()
                         },
                         "required": ["content", "analyzer_type"]
                     }
@@ -255,8 +506,8 @@ impl McpGeminiServer {
                             "length": {"type": "string", "enum": ["brief", "medium", "detailed"], "default": "medium"},
                             "format": {"type": "string", "enum": ["paragraph", "bullet_points", "executive", "key_points"], "default": "paragraph"},
                             "focus": {"type": "string"},
-                            "model": {"type": "string", "enum": ["pro", "flash"]},
-                            "params": {"type": "object"}
+                            "model": {"type": "string", "description": MODEL_DESCRIPTION},
+                            "params": generation_params_schema()
                         },
                         "required": ["content"]
                     }
@@ -271,15 +522,54 @@ impl McpGeminiServer {
                             "num_ideas": {"type": "integer", "default": 10, "minimum": 1, "maximum": 50},
                             "constraints": {"type": "string"},
                             "extract_consensus": {"type": "boolean", "default": true},
-                            "model": {"type": "string", "enum": ["pro", "flash"]},
-                            "params": {"type": "object"}
+                            "model": {"type": "string", "description": MODEL_DESCRIPTION},
+                            "params": generation_params_schema()
                         },
                         "required": ["prompt"]
                     }
+                },
+                {
+                    "name": "gemini-agent",
+                    "description": "Let Gemini investigate local files with read-only tools (list directory, read file, grep, git log) and return a cited report",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "task": {"type": "string", "description": "What to investigate"},
+                            "roots": {"type": "array", "items": {"type": "string"}, "description": "Restrict to these paths inside the server's allowed roots"},
+                            "max_steps": {"type": "integer", "default": 12, "minimum": 1, "maximum": 40},
+                            "token_budget": {"type": "integer", "default": 300000},
+                            "model": {"type": "string", "description": MODEL_DESCRIPTION},
+                            "params": generation_params_schema()
+                        },
+                        "required": ["task"]
+                    }
+                },
+                {
+                    "name": "gemini-status",
+                    "description": "Report Gemini API connectivity, server health and per-key usage",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "refresh": {"type": "boolean", "default": false, "description": "Probe the API now instead of returning the cached state"}
+                        }
+                    }
                 }
             ]
         });
 
+        if let Some(list) = tools["tools"].as_array_mut() {
+            let enabled = self.tools();
+            list.retain(|tool| tool["name"].as_str().is_some_and(|name| enabled.is_enabled(name)));
+
+            // Every tool except gemini-status generates text
+            let persona = persona_properties();
+            for tool in list.iter_mut().filter(|tool| tool["name"] != "gemini-status") {
+                if let Some(properties) = tool["inputSchema"]["properties"].as_object_mut() {
+                    properties.extend(persona.clone());
+                }
+            }
+        }
+
         JsonRpcResponse::success(id, tools)
     }
 
@@ -309,98 +599,117 @@ impl McpGeminiServer {
 
         debug!("Calling tool: {}", tool_name);
 
+        if !self.tools().is_enabled(tool_name) {
+            let message = format!("Tool '{}' is disabled by configuration", tool_name);
+            return JsonRpcResponse::error(-32601, &message, Some(id));
+        }
+        self.refresh_stale_models();
+
         let result = match tool_name {
             // V1 tools (legacy - backward compatibility)
-            "gemini-query" => {
-                match self.execute_query(arguments).await {
-                    Ok(r) => serde_json::json!({"content": [{"type": "text", "text": r}]}),
-                    Err(e) => return JsonRpcResponse::error(-32603, &e.to_string(), Some(id)),
-                }
-            }
-            "gemini-analyze-code" => {
-                match self.execute_analyze_code(arguments).await {
-                    Ok(r) => serde_json::json!({"content": [{"type": "text", "text": r}]}),
-                    Err(e) => return JsonRpcResponse::error(-32603, &e.to_string(), Some(id)),
-                }
-            }
-            "gemini-analyze-text" => {
-                match self.execute_analyze_text(arguments).await {
-                    Ok(r) => serde_json::json!({"content": [{"type": "text", "text": r}]}),
-                    Err(e) => return JsonRpcResponse::error(-32603, &e.to_string(), Some(id)),
-                }
-            }
-            "gemini-summarize" => {
-                match self.execute_summarize(arguments).await {
-                    Ok(r) => serde_json::json!({"content": [{"type": "text", "text": r}]}),
-                    Err(e) => return JsonRpcResponse::error(-32603, &e.to_string(), Some(id)),
-                }
-            }
-            "gemini-brainstorm" => {
-                match self.execute_brainstorm(arguments).await {
-                    Ok(r) => serde_json::json!({"content": [{"type": "text", "text": r}]}),
-                    Err(e) => return JsonRpcResponse::error(-32603, &e.to_string(), Some(id)),
-                }
-            }
+            "gemini-query" => self.execute_query(arguments).await.map(text_content),
+            "gemini-analyze-code" => self.execute_analyze_code(arguments).await.map(text_content),
+            "gemini-analyze-text" => self.execute_analyze_text(arguments).await.map(text_content),
+            "gemini-summarize" => self.execute_summarize(arguments).await.map(text_content),
+            "gemini-brainstorm" => self.execute_brainstorm(arguments).await.map(text_content),
             // V2 tools (structured JSON responses)
-            "gemini-search-v2" => {
-                match self.execute_search_v2(arguments).await {
-                    Ok(r) => serde_json::json!({"content": [{"type": "text", "text": serde_json::to_string_pretty(&r).unwrap_or_else(|_| "{}".to_string())}]}),
-                    Err(e) => return JsonRpcResponse::error(-32603, &e.to_string(), Some(id)),
-                }
+            "gemini-search-v2" => self.execute_search_v2(arguments).await.map(json_content),
+            "gemini-analyze-v2" => self.execute_analyze_v2(arguments).await.map(json_content),
+            "gemini-summarize-v2" => self.execute_summarize_v2(arguments).await.map(json_content),
+            "gemini-brainstorm-v2" => self.execute_brainstorm_v2(arguments).await.map(json_content),
+            "gemini-agent" => self.execute_agent(arguments).await.map(json_content),
+            // Server tools (no Gemini call on the happy path)
+            "gemini-status" => {
+                return match self.execute_status(arguments).await {
+                    Ok(r) => JsonRpcResponse::success(id, json_content(r)),
+                    Err(e) => JsonRpcResponse::error(-32603, &e.to_string(), Some(id)),
+                };
             }
-            "gemini-analyze-v2" => {
-                match self.execute_analyze_v2(arguments).await {
-                    Ok(r) => serde_json::json!({"content": [{"type": "text", "text": serde_json::to_string_pretty(&r).unwrap_or_else(|_| "{}".to_string())}]}),
-                    Err(e) => return JsonRpcResponse::error(-32603, &e.to_string(), Some(id)),
-                }
+            _ => {
+                return JsonRpcResponse::error(-32601, "Tool not found", Some(id));
             }
-            "gemini-summarize-v2" => {
-                match self.execute_summarize_v2(arguments).await {
-                    Ok(r) => serde_json::json!({"content": [{"type": "text", "text": serde_json::to_string_pretty(&r).unwrap_or_else(|_| "{}".to_string())}]}),
-                    Err(e) => return JsonRpcResponse::error(-32603, &e.to_string(), Some(id)),
-                }
+        };
+
+        self.observe(&result);
+
+        match result {
+            Ok(r) => JsonRpcResponse::success(id, r),
+            Err(e) => JsonRpcResponse::error(-32603, &e.to_string(), Some(id)),
+        }
+    }
+
+    /// Feeds the outcome of a Gemini-backed tool call into the health monitor.
+    /// Input validation errors say nothing about the API and are ignored.
+    fn observe<T>(&self, result: &anyhow::Result<T>) {
+        match result {
+            Ok(_) => self.health.record_success(),
+            // Safety blocks, oversized inputs and exhausted output budgets
+            // still mean the API answered
+            Err(e)
+                if matches!(
+                    e.downcast_ref::<GeminiError>(),
+                    Some(
+                        GeminiError::Blocked { .. }
+                            | GeminiError::InputTooLarge { .. }
+                            | GeminiError::Truncated { .. }
+                    )
+                ) =>
+            {
+                self.health.record_success()
             }
-            "gemini-brainstorm-v2" => {
-                match self.execute_brainstorm_v2(arguments).await {
-                    Ok(r) => serde_json::json!({"content": [{"type": "text", "text": serde_json::to_string_pretty(&r).unwrap_or_else(|_| "{}".to_string())}]}),
-                    Err(e) => return JsonRpcResponse::error(-32603, &e.to_string(), Some(id)),
+            Err(e) if matches!(e.downcast_ref::<GeminiError>(), Some(GeminiError::InvalidModel(_))) => {}
+            Err(e) => {
+                if let Some(gemini_error) = e.downcast_ref::<GeminiError>() {
+                    self.health.record_failure(gemini_error);
                 }
             }
-            _ => {
-                return JsonRpcResponse::error(-32601, "Tool not found", Some(id));
-            }
+        }
+    }
+
+    async fn execute_status(&self, args: serde_json::Value) -> anyhow::Result<serde_json::Value> {
+        let refresh = args.get("refresh").and_then(|v| v.as_bool()).unwrap_or(false);
+
+        let report = if refresh {
+            self.health.check().await
+        } else {
+            self.health.refresh_if_stale();
+            self.health.report()
         };
 
-        JsonRpcResponse::success(id, result)
+        Ok(serde_json::json!({
+            "server_version": env!("CARGO_PKG_VERSION"),
+            "health": report,
+            "api_keys": self.backend.key_usage(),
+        }))
     }
 
     async fn execute_query(&self, args: serde_json::Value) -> anyhow::Result<String> {
         let input: tools::query::QueryInput = serde_json::from_value(args)?;
-        let output = tools::query::execute(input, Arc::clone(&self.client)).await?;
+        let output = tools::query::execute(input, Arc::clone(&self.backend), &self.tools()).await?;
         Ok(output.text)
     }
 
     async fn execute_analyze_code(&self, args: serde_json::Value) -> anyhow::Result<String> {
         let input: tools::analyze::AnalyzeCodeInput = serde_json::from_value(args)?;
-        let output = tools::analyze::execute_code(input, Arc::clone(&self.client)).await?;
+        let output = tools::analyze::execute_code(input, Arc::clone(&self.backend), &self.tools()).await?;
         Ok(output.analysis)
     }
 
     async fn execute_analyze_text(&self, args: serde_json::Value) -> anyhow::Result<String> {
         let input: tools::analyze::AnalyzeTextInput = serde_json::from_value(args)?;
-        let output = tools::analyze::execute_text(input, Arc::clone(&self.client)).await?;
+        let output = tools::analyze::execute_text(input, Arc::clone(&self.backend), &self.tools()).await?;
         Ok(output.analysis)
     }
 
     async fn execute_summarize(&self, args: serde_json::Value) -> anyhow::Result<String> {
         let input: tools::summarize::SummarizeInput = serde_json::from_value(args)?;
-        let output = tools::summarize::execute(input, Arc::clone(&self.client)).await?;
+        let output = tools::summarize::execute(input, Arc::clone(&self.backend), &self.tools()).await?;
         Ok(output.summary)
     }
 
     async fn execute_brainstorm(&self, args: serde_json::Value) -> anyhow::Result<String> {
         let input: tools::brainstorm::BrainstormInput = serde_json::from_value(args)?;
-        let output = tools::brainstorm::execute(input, Arc::clone(&self.client)).await?;
+        let output = tools::brainstorm::execute(input, Arc::clone(&self.backend), &self.tools()).await?;
         Ok(format!(
             "# Synthesis\n\n{}\n\n# Conversation History\n\n{}",
             output.synthesis, output.conversation_history
@@ -410,7 +719,7 @@ impl McpGeminiServer {
     // V2 API execute methods
     async fn execute_search_v2(&self, args: serde_json::Value) -> anyhow::Result<serde_json::Value> {
         let input: tools::query::SearchInput = serde_json::from_value(args)?;
-        let response = tools::query::execute_v2(input, Arc::clone(&self.client)).await?;
+        let response = tools::query::execute_v2(input, Arc::clone(&self.backend), &self.tools()).await?;
 
         // Serialize ToolResponse<SearchResult> to JSON
         Ok(serde_json::to_value(response)?)
@@ -418,7 +727,7 @@ impl McpGeminiServer {
 
     async fn execute_analyze_v2(&self, args: serde_json::Value) -> anyhow::Result<serde_json::Value> {
         let input: tools::analyze::AnalyzeInput = serde_json::from_value(args)?;
-        let response = tools::analyze::execute_v2(input, Arc::clone(&self.client)).await?;
+        let response = tools::analyze::execute_v2(input, Arc::clone(&self.backend), &self.tools()).await?;
 
         // Serialize ToolResponse<AnalyzeResult> to JSON
         Ok(serde_json::to_value(response)?)
@@ -426,7 +735,7 @@ impl McpGeminiServer {
 
     async fn execute_summarize_v2(&self, args: serde_json::Value) -> anyhow::Result<serde_json::Value> {
         let input: tools::summarize::SummarizeInput = serde_json::from_value(args)?;
-        let response = tools::summarize::execute_v2(input, Arc::clone(&self.client)).await?;
+        let response = tools::summarize::execute_v2(input, Arc::clone(&self.backend), &self.tools()).await?;
 
         // Serialize ToolResponse<SummaryResult> to JSON
         Ok(serde_json::to_value(response)?)
@@ -434,11 +743,120 @@ impl McpGeminiServer {
 
     async fn execute_brainstorm_v2(&self, args: serde_json::Value) -> anyhow::Result<serde_json::Value> {
         let input: tools::brainstorm::BrainstormInput = serde_json::from_value(args)?;
-        let response = tools::brainstorm::execute_v2(input, Arc::clone(&self.client)).await?;
+        let response = tools::brainstorm::execute_v2(input, Arc::clone(&self.backend), &self.tools()).await?;
 
         // Serialize ToolResponse<BrainstormResult> to JSON
         Ok(serde_json::to_value(response)?)
     }
+
+    async fn execute_agent(&self, args: serde_json::Value) -> anyhow::Result<serde_json::Value> {
+        let sandbox = self.sandbox.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("gemini-agent is disabled: no valid allowed roots (set GEMINI_AGENT_ROOTS)")
+        })?;
+        let input: tools::agent::AgentInput = serde_json::from_value(args)?;
+        let response = tools::agent::execute_v2(input, Arc::clone(&self.backend), &self.tools(), sandbox).await?;
+
+        // Serialize ToolResponse<AgentResult> to JSON
+        Ok(serde_json::to_value(response)?)
+    }
+}
+
+/// `persona` and `system` properties shared by every generating tool, with
+/// the descriptions from the tool inputs
+fn persona_properties() -> serde_json::Map<String, serde_json::Value> {
+    let schema = serde_json::to_value(schemars::schema_for!(tools::summarize::SummarizeInput))
+        .unwrap_or_default();
+    ["persona", "system"]
+        .into_iter()
+        .map(|name| {
+            let description = schema["properties"][name]["description"].clone();
+            (name.to_string(), serde_json::json!({"type": "string", "description": description}))
+        })
+        .collect()
+}
+
+/// Input schema shared by the `params` argument of every v2 tool
+fn generation_params_schema() -> serde_json::Value {
+    serde_json::json!({
+        "type": "object",
+        "properties": {
+            "temperature": {"type": "number", "minimum": 0.0, "maximum": 2.0},
+            "max_tokens": {"type": "integer", "minimum": 1},
+            "top_p": {"type": "number", "minimum": 0.0, "maximum": 1.0},
+            "top_k": {"type": "integer", "minimum": 1},
+            "stop_sequences": {"type": "array", "items": {"type": "string"}, "maxItems": 5},
+            "presence_penalty": {"type": "number", "minimum": -2.0, "exclusiveMaximum": 2.0},
+            "frequency_penalty": {"type": "number", "minimum": -2.0, "exclusiveMaximum": 2.0},
+            "seed": {"type": "integer"},
+            "candidate_count": {"type": "integer", "minimum": 1, "maximum": 8, "description": "Tools use the first candidate"},
+            "response_logprobs": {"type": "boolean", "description": "Report metadata.avg_logprobs"},
+            "thinking_budget": {"type": "integer", "minimum": -1, "maximum": 32768, "description": "0 disables thinking, -1 lets the model decide"},
+            "include_thoughts": {"type": "boolean"},
+            "max_continuations": {"type": "integer", "minimum": 0, "maximum": 5},
+            "safety": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "category": {"type": "string", "enum": ["harassment", "hate_speech", "sexually_explicit", "dangerous_content", "civic_integrity"]},
+                        "threshold": {"type": "string", "enum": ["block_none", "block_only_high", "block_medium_and_above", "block_low_and_above", "off"]}
+                    },
+                    "required": ["category", "threshold"]
+                }
+            }
+        }
+    })
+}
+
+/// Polls the config files and applies valid changes until aborted
+async fn watch_config(
+    server: Arc<McpGeminiServer>,
+    mut watcher: ConfigWatcher,
+    tx: mpsc::UnboundedSender<Outgoing>,
+) {
+    let mut interval = tokio::time::interval(watcher.interval());
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let Some(loaded) = watcher.poll() else {
+            continue;
+        };
+
+        match loaded.map_err(anyhow::Error::from).and_then(|loaded| server.apply_config(&loaded.config)) {
+            Ok(tools_changed) => {
+                info!("Configuration reloaded");
+                if tools_changed {
+                    let _ = tx.send(Outgoing::Notification(JsonRpcNotification::new(
+                        "notifications/tools/list_changed",
+                    )));
+                }
+            }
+            Err(e) => error!("Keeping the previous configuration: {}", e),
+        }
+    }
+}
+
+async fn write_responses<W>(mut writer: W, mut rx: mpsc::UnboundedReceiver<Outgoing>) -> std::io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    while let Some(response) = rx.recv().await {
+        let response_json = serde_json::to_string(&response)?;
+        writer.write_all(response_json.as_bytes()).await?;
+        writer.write_all(b"\n").await?;
+        writer.flush().await?;
+    }
+
+    Ok(())
+}
+
+fn text_content(text: String) -> serde_json::Value {
+    serde_json::json!({"content": [{"type": "text", "text": text}]})
+}
+
+fn json_content(value: serde_json::Value) -> serde_json::Value {
+    let text = serde_json::to_string_pretty(&value).unwrap_or_else(|_| "{}".to_string());
+    serde_json::json!({"content": [{"type": "text", "text": text}]})
 }
 
 #[derive(Debug, Deserialize)]
@@ -459,6 +877,36 @@ struct JsonRpcResponse {
     error: Option<JsonRpcError>,
 }
 
+/// Server-initiated message without an id
+#[derive(Debug, Serialize)]
+struct JsonRpcNotification {
+    jsonrpc: String,
+    method: String,
+}
+
+impl JsonRpcNotification {
+    fn new(method: &str) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+        }
+    }
+}
+
+/// Anything written to stdout
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Outgoing {
+    Response(JsonRpcResponse),
+    Notification(JsonRpcNotification),
+}
+
+impl From<JsonRpcResponse> for Outgoing {
+    fn from(response: JsonRpcResponse) -> Self {
+        Self::Response(response)
+    }
+}
+
 #[derive(Debug, Serialize)]
 struct JsonRpcError {
     code: i32,
@@ -488,3 +936,84 @@ impl JsonRpcResponse {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, BufReader};
+
+    fn builder() -> ServerBuilder {
+        let mut config = Config::builtin();
+        // Nothing listens here, so the background health probe fails fast
+        config.client.base_url = Some("http://127.0.0.1:9".to_string());
+        McpGeminiServer::builder()
+            .config(config)
+            .keys(KeyPool::single("test-key".to_string()).unwrap())
+            .sandbox(None)
+    }
+
+    #[test]
+    fn test_builder_requires_keys_or_client() {
+        let err = McpGeminiServer::builder().build().err().unwrap();
+        assert!(err.to_string().contains("API keys or a client"));
+        assert!(builder().build().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_serve_over_custom_transport() {
+        let server = builder().build().unwrap();
+        let (mut output, writer) = tokio::io::duplex(64 * 1024);
+        let input = concat!(
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize"}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#,
+            "\n",
+        );
+
+        let outcome = server
+            .serve(BufReader::new(input.as_bytes()), writer, std::future::pending())
+            .await
+            .unwrap();
+        assert_eq!(outcome.reason, ShutdownReason::Eof);
+        assert_eq!(outcome.exit_code(), 0);
+
+        let mut text = String::new();
+        output.read_to_string(&mut text).await.unwrap();
+        let responses: Vec<serde_json::Value> =
+            text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(responses.len(), 2);
+
+        let list = responses.iter().find(|r| r["id"] == 2).unwrap();
+        let names: Vec<&str> = list["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|tool| tool["name"].as_str())
+            .collect();
+        assert!(names.contains(&"gemini-summarize-v2"));
+        assert!(names.contains(&"gemini-status"));
+
+        for tool in list["result"]["tools"].as_array().unwrap() {
+            let persona = &tool["inputSchema"]["properties"]["persona"];
+            if tool["name"] == "gemini-status" {
+                assert!(persona.is_null());
+            } else {
+                assert_eq!(persona["type"], "string");
+                assert!(persona["description"].as_str().unwrap().contains("code-reviewer"));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_stops_on_request() {
+        let server = builder().build().unwrap();
+        let (_client, reader) = tokio::io::duplex(1024);
+
+        let outcome = server
+            .serve(BufReader::new(reader), tokio::io::sink(), async { ShutdownReason::Requested })
+            .await
+            .unwrap();
+        assert_eq!(outcome.reason, ShutdownReason::Requested);
+        assert_eq!(outcome.exit_code(), 0);
+    }
+}
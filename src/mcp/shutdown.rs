@@ -0,0 +1,262 @@
+// Shutdown coordination for the server
+//
+// On end of input or a shutdown request (SIGINT/SIGTERM for stdio) the
+// server stops reading requests, waits up to a deadline for in-flight tool
+// calls, answers any call it had to abandon, runs registered flush hooks and
+// reports an exit code describing the result.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::task::{Id, JoinSet};
+use tracing::{error, info, warn};
+
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Every in-flight call finished and every flush hook succeeded
+pub const EXIT_OK: i32 = 0;
+/// Reading requests or writing a flush target failed (sysexits EX_IOERR)
+pub const EXIT_IO_ERROR: i32 = 74;
+/// The drain deadline expired and in-flight calls were abandoned (EX_TEMPFAIL)
+pub const EXIT_DRAIN_TIMEOUT: i32 = 75;
+
+type FlushFuture = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+type FlushHook = Box<dyn FnOnce() -> FlushFuture + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// The client closed the input
+    Eof,
+    /// Reading the input failed
+    ReadError,
+    Interrupt,
+    Terminate,
+    /// The embedding application asked the server to stop
+    Requested,
+}
+
+#[derive(Debug)]
+pub struct ShutdownOutcome {
+    pub reason: ShutdownReason,
+    pub completed: usize,
+    pub abandoned: usize,
+    pub flush_failures: usize,
+}
+
+impl ShutdownOutcome {
+    pub fn exit_code(&self) -> i32 {
+        if self.abandoned > 0 {
+            EXIT_DRAIN_TIMEOUT
+        } else if self.flush_failures > 0 || self.reason == ShutdownReason::ReadError {
+            EXIT_IO_ERROR
+        } else {
+            match self.reason {
+                // Shell convention: 128 + signal number
+                ShutdownReason::Interrupt => 130,
+                ShutdownReason::Terminate => 143,
+                ShutdownReason::Eof | ShutdownReason::ReadError | ShutdownReason::Requested => EXIT_OK,
+            }
+        }
+    }
+}
+
+pub struct ShutdownCoordinator {
+    timeout: Duration,
+    hooks: Mutex<Vec<(String, FlushHook)>>,
+}
+
+impl ShutdownCoordinator {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            hooks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Registers state that must be persisted once in-flight calls are done.
+    /// Hooks run in registration order.
+    pub fn on_shutdown<F, Fut>(&self, name: &str, hook: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let hook: FlushHook = Box::new(move || Box::pin(hook()));
+        self.hooks.lock().unwrap().push((name.to_string(), hook));
+    }
+
+    /// Waits for in-flight tasks until the deadline, then aborts the rest.
+    /// `pending` maps task ids to their JSON-RPC request ids; entries are
+    /// removed as tasks finish and the request ids of abandoned calls are
+    /// returned so they can still be answered.
+    pub async fn drain<K>(
+        &self,
+        tasks: &mut JoinSet<()>,
+        pending: &mut HashMap<Id, K>,
+    ) -> (usize, Vec<K>) {
+        if !tasks.is_empty() {
+            info!(
+                "Waiting up to {}s for {} in-flight call(s)",
+                self.timeout.as_secs(),
+                tasks.len()
+            );
+        }
+
+        let mut completed = 0;
+        let drained = tokio::time::timeout(self.timeout, async {
+            while let Some(result) = tasks.join_next_with_id().await {
+                match result {
+                    Ok((id, ())) => {
+                        pending.remove(&id);
+                    }
+                    Err(e) => {
+                        error!("In-flight call failed during shutdown: {}", e);
+                        pending.remove(&e.id());
+                    }
+                }
+                completed += 1;
+            }
+        })
+        .await;
+
+        if drained.is_err() {
+            warn!(
+                "Shutdown deadline reached, abandoning {} call(s)",
+                pending.len()
+            );
+            tasks.abort_all();
+            while tasks.join_next().await.is_some() {}
+        }
+
+        (completed, pending.drain().map(|(_, v)| v).collect())
+    }
+
+    /// Runs every registered flush hook and returns the number that failed.
+    pub async fn flush(&self) -> usize {
+        let hooks = std::mem::take(&mut *self.hooks.lock().unwrap());
+        let mut failures = 0;
+
+        for (name, hook) in hooks {
+            match hook().await {
+                Ok(()) => info!("Flushed {}", name),
+                Err(e) => {
+                    error!("Failed to flush {}: {}", name, e);
+                    failures += 1;
+                }
+            }
+        }
+
+        failures
+    }
+}
+
+/// Resolves when SIGINT or (on Unix) SIGTERM is received.
+#[cfg(feature = "stdio")]
+pub async fn wait_for_signal() -> ShutdownReason {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => ShutdownReason::Interrupt,
+                    _ = sigterm.recv() => ShutdownReason::Terminate,
+                }
+            }
+            Err(e) => {
+                warn!("Cannot install SIGTERM handler: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                ShutdownReason::Interrupt
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        ShutdownReason::Interrupt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn outcome(reason: ShutdownReason, abandoned: usize, flush_failures: usize) -> ShutdownOutcome {
+        ShutdownOutcome {
+            reason,
+            completed: 0,
+            abandoned,
+            flush_failures,
+        }
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(outcome(ShutdownReason::Eof, 0, 0).exit_code(), EXIT_OK);
+        assert_eq!(outcome(ShutdownReason::Interrupt, 0, 0).exit_code(), 130);
+        assert_eq!(outcome(ShutdownReason::Terminate, 0, 0).exit_code(), 143);
+        assert_eq!(outcome(ShutdownReason::ReadError, 0, 0).exit_code(), EXIT_IO_ERROR);
+        assert_eq!(outcome(ShutdownReason::Eof, 0, 1).exit_code(), EXIT_IO_ERROR);
+        assert_eq!(outcome(ShutdownReason::Terminate, 2, 1).exit_code(), EXIT_DRAIN_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_fast_tasks() {
+        let coordinator = ShutdownCoordinator::new(Duration::from_secs(5));
+        let mut tasks = JoinSet::new();
+        let mut pending = HashMap::new();
+
+        for i in 0..3 {
+            let handle = tasks.spawn(async move {
+                tokio::time::sleep(Duration::from_millis(10 * i)).await;
+            });
+            pending.insert(handle.id(), i);
+        }
+
+        let (completed, abandoned) = coordinator.drain(&mut tasks, &mut pending).await;
+        assert_eq!(completed, 3);
+        assert!(abandoned.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_drain_abandons_slow_tasks() {
+        let coordinator = ShutdownCoordinator::new(Duration::from_millis(50));
+        let mut tasks = JoinSet::new();
+        let mut pending = HashMap::new();
+
+        let fast = tasks.spawn(async {});
+        pending.insert(fast.id(), "fast");
+        let slow = tasks.spawn(async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+        pending.insert(slow.id(), "slow");
+
+        let (completed, abandoned) = coordinator.drain(&mut tasks, &mut pending).await;
+        assert_eq!(completed, 1);
+        assert_eq!(abandoned, vec!["slow"]);
+        assert!(tasks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_flush_runs_hooks_and_counts_failures() {
+        let coordinator = ShutdownCoordinator::new(DEFAULT_SHUTDOWN_TIMEOUT);
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let c = Arc::clone(&calls);
+        coordinator.on_shutdown("ok", move || async move {
+            c.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+        coordinator.on_shutdown("broken", || async { anyhow::bail!("disk full") });
+
+        assert_eq!(coordinator.flush().await, 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        // Hooks only run once
+        assert_eq!(coordinator.flush().await, 0);
+    }
+}
@@ -0,0 +1,493 @@
+// One-shot tool runs: `gemini-mcp run <tool> ...`
+//
+// Builds the same tool inputs the server deserializes from `tools/call`,
+// calls the same execute functions and prints the `ToolResponse` as JSON or
+// the result as plain text. Input comes from `--file` (`-` for stdin) or,
+// when no file or argument is given, from stdin. Exit codes follow sysexits
+// so scripts can tell bad input, auth and retryable failures apart.
+
+use clap::{Args, Subcommand, ValueEnum};
+use gemini_mcp::gemini::fallback::ErrorClass;
+use gemini_mcp::tools::agent::{self, AgentInput, AgentResult};
+use gemini_mcp::tools::analyze::{self, AnalyzeInput, AnalyzeResult, AnalyzerOptions, AnalyzerType};
+use gemini_mcp::tools::brainstorm::{self, BrainstormInput, BrainstormResult};
+use gemini_mcp::tools::query::{self, SearchInput, SearchResult, Source};
+use gemini_mcp::tools::prompts::RenderError;
+use gemini_mcp::tools::sandbox::Sandbox;
+use gemini_mcp::tools::summarize::{self, SummarizeInput, SummaryFormat, SummaryLength, SummaryResult};
+use gemini_mcp::tools::types::InvalidInput;
+use gemini_mcp::{GenerationBackend, GeminiError, GenerationParams, ToolResponse, ToolSettings};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub const EXIT_OK: i32 = 0;
+/// Any failure not covered below
+pub const EXIT_FAILURE: i32 = 1;
+/// Invalid input: validation, unknown model, oversized input or a safety block
+pub const EXIT_DATA_ERROR: i32 = 65;
+/// An input file could not be read
+pub const EXIT_NO_INPUT: i32 = 66;
+/// The API could not be reached or failed with a server error
+pub const EXIT_UNAVAILABLE: i32 = 69;
+/// Rate limited or overloaded; retrying later may succeed
+pub const EXIT_TEMP_FAIL: i32 = 75;
+/// The API key was rejected
+pub const EXIT_NO_PERMISSION: i32 = 77;
+/// Invalid configuration or no API key
+pub const EXIT_CONFIG: i32 = 78;
+
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    #[command(subcommand)]
+    pub tool: Tool,
+
+    /// Output: the result as plain text, or the full response as JSON
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, global = true)]
+    pub format: OutputFormat,
+
+    /// Model id or alias
+    #[arg(long, global = true)]
+    pub model: Option<String>,
+
+    /// System-instruction preset
+    #[arg(long, global = true)]
+    pub persona: Option<String>,
+
+    /// Literal system instruction; overrides --persona
+    #[arg(long, global = true)]
+    pub system: Option<String>,
+
+    /// Sampling temperature (0.0-2.0)
+    #[arg(long, global = true)]
+    pub temperature: Option<f32>,
+
+    /// Maximum output tokens
+    #[arg(long, global = true)]
+    pub max_tokens: Option<u32>,
+
+    /// Generation parameters as JSON, e.g. '{"seed": 7}'
+    #[arg(long, global = true, value_parser = parse_json::<GenerationParams>)]
+    pub params: Option<GenerationParams>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Tool {
+    /// Answer a query from source files
+    Search {
+        query: String,
+        /// Source file, repeatable; the file name is the source title
+        #[arg(long = "file")]
+        files: Vec<PathBuf>,
+        #[arg(long)]
+        no_citations: bool,
+    },
+    /// Analyze text, code or a document
+    Analyze {
+        #[arg(long)]
+        file: Option<PathBuf>,
+        #[arg(long = "type", value_enum, default_value_t = AnalyzeKind::Text)]
+        kind: AnalyzeKind,
+        /// Language for --type code
+        #[arg(long)]
+        language: Option<String>,
+        /// Second file for --type comparison
+        #[arg(long)]
+        compare_with: Option<PathBuf>,
+        /// Aspect to focus on, repeatable
+        #[arg(long)]
+        focus: Vec<String>,
+    },
+    /// Summarize content
+    Summarize {
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// brief, medium or detailed
+        #[arg(long, default_value = "medium", value_parser = parse_name::<SummaryLength>)]
+        length: SummaryLength,
+        /// paragraph, bullet_points, executive or key_points
+        #[arg(long, default_value = "paragraph", value_parser = parse_name::<SummaryFormat>)]
+        style: SummaryFormat,
+        #[arg(long)]
+        focus: Option<String>,
+    },
+    /// Generate ideas on a topic
+    Brainstorm {
+        /// Topic; read from --file or stdin when omitted
+        topic: Option<String>,
+        #[arg(long)]
+        file: Option<PathBuf>,
+        #[arg(long, default_value_t = 10)]
+        num_ideas: u32,
+        #[arg(long)]
+        constraints: Option<String>,
+    },
+    /// Investigate local files and report with citations
+    Agent {
+        /// Task; read from --file or stdin when omitted
+        task: Option<String>,
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// Restrict to this path inside the allowed roots, repeatable
+        #[arg(long)]
+        root: Vec<String>,
+        #[arg(long)]
+        max_steps: Option<u32>,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnalyzeKind {
+    Text,
+    Code,
+    Document,
+    Sentiment,
+    Comparison,
+}
+
+/// An input file that could not be read
+#[derive(Debug, thiserror::Error)]
+#[error("Cannot read {path}: {source}")]
+pub struct InputError {
+    path: String,
+    source: std::io::Error,
+}
+
+/// Runs one tool and returns the text to print
+pub async fn execute(args: RunArgs, client: Arc<dyn GenerationBackend>, settings: &ToolSettings) -> anyhow::Result<String> {
+    let params = params(&args);
+    let format = args.format;
+
+    match args.tool {
+        Tool::Search { query, files, no_citations } => {
+            let sources = if files.is_empty() {
+                vec![Source::new("stdin", "stdin", read_input(None)?)]
+            } else {
+                files
+                    .iter()
+                    .map(|path| {
+                        let title = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+                        Ok(Source::new(path.display().to_string(), title, read_input(Some(path))?))
+                    })
+                    .collect::<anyhow::Result<_>>()?
+            };
+            let input = SearchInput {
+                include_citations: !no_citations,
+                model: args.model,
+                params,
+                persona: args.persona,
+                system: args.system,
+                ..SearchInput::new(query, sources)
+            };
+            output(query::execute_v2(input, client, settings).await?, format)
+        }
+        Tool::Analyze { file, kind, language, compare_with, focus } => {
+            let analyzer_type = match kind {
+                AnalyzeKind::Text => AnalyzerType::Text,
+                AnalyzeKind::Code => AnalyzerType::Code { language },
+                AnalyzeKind::Document => AnalyzerType::Document,
+                AnalyzeKind::Sentiment => AnalyzerType::Sentiment,
+                AnalyzeKind::Comparison => {
+                    let Some(path) = compare_with else {
+                        anyhow::bail!(InvalidInput("--type comparison needs --compare-with <FILE>".to_string()));
+                    };
+                    AnalyzerType::Comparison { compare_with: read_input(Some(&path))? }
+                }
+            };
+            let options = (!focus.is_empty()).then(|| AnalyzerOptions {
+                focus_areas: Some(focus),
+                detail_level: Default::default(),
+            });
+            let input = AnalyzeInput {
+                options,
+                model: args.model,
+                params,
+                persona: args.persona,
+                system: args.system,
+                ..AnalyzeInput::new(read_input(file.as_deref())?, analyzer_type)
+            };
+            output(analyze::execute_v2(input, client, settings).await?, format)
+        }
+        Tool::Summarize { file, length, style, focus } => {
+            let input = SummarizeInput {
+                length,
+                format: style,
+                focus,
+                model: args.model,
+                params,
+                persona: args.persona,
+                system: args.system,
+                ..SummarizeInput::new(read_input(file.as_deref())?)
+            };
+            output(summarize::execute_v2(input, client, settings).await?, format)
+        }
+        Tool::Brainstorm { topic, file, num_ideas, constraints } => {
+            let topic = match topic {
+                Some(topic) => topic,
+                None => read_input(file.as_deref())?,
+            };
+            let input = BrainstormInput {
+                num_ideas,
+                constraints,
+                model: args.model,
+                params,
+                persona: args.persona,
+                system: args.system,
+                ..BrainstormInput::new(topic)
+            };
+            output(brainstorm::execute_v2(input, client, settings).await?, format)
+        }
+        Tool::Agent { task, file, root, max_steps } => {
+            let task = match task {
+                Some(task) => task,
+                None => read_input(file.as_deref())?,
+            };
+            let sandbox = Sandbox::from_env().map_err(|e| GeminiError::ConfigError(e.to_string()))?;
+            let input = AgentInput {
+                roots: (!root.is_empty()).then_some(root),
+                max_steps,
+                model: args.model,
+                params,
+                persona: args.persona,
+                system: args.system,
+                ..AgentInput::new(task)
+            };
+            output(agent::execute_v2(input, client, settings, &sandbox).await?, format)
+        }
+    }
+}
+
+/// `--params` with `--temperature` and `--max-tokens` layered on top
+fn params(args: &RunArgs) -> Option<GenerationParams> {
+    if args.params.is_none() && args.temperature.is_none() && args.max_tokens.is_none() {
+        return None;
+    }
+    let mut params = args.params.clone().unwrap_or_default();
+    params.temperature = args.temperature.or(params.temperature);
+    params.max_tokens = args.max_tokens.or(params.max_tokens);
+    Some(params)
+}
+
+/// Contents of `path`, or stdin for `None` and `-`
+fn read_input(path: Option<&Path>) -> anyhow::Result<String> {
+    let (name, result) = match path {
+        Some(path) if path != Path::new("-") => (path.display().to_string(), std::fs::read_to_string(path)),
+        _ => {
+            let mut text = String::new();
+            let result = std::io::stdin().read_to_string(&mut text).map(|_| text);
+            ("stdin".to_string(), result)
+        }
+    };
+    Ok(result.map_err(|source| InputError { path: name, source })?)
+}
+
+fn output<T: Serialize + PlainText>(response: ToolResponse<T>, format: OutputFormat) -> anyhow::Result<String> {
+    Ok(match format {
+        OutputFormat::Json => serde_json::to_string_pretty(&response)?,
+        OutputFormat::Text => response.result.plain_text(),
+    })
+}
+
+/// Exit code for a failed run
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    if error.is::<InputError>() {
+        return EXIT_NO_INPUT;
+    }
+    if error.is::<InvalidInput>() {
+        return EXIT_DATA_ERROR;
+    }
+    if error.is::<RenderError>() {
+        return EXIT_CONFIG;
+    }
+    let Some(error) = error.downcast_ref::<GeminiError>() else {
+        return EXIT_FAILURE;
+    };
+
+    match error {
+        GeminiError::AuthError(_) | GeminiError::ApiError { status: 401 | 403, .. } => EXIT_NO_PERMISSION,
+        GeminiError::ConfigError(_) => EXIT_CONFIG,
+        GeminiError::InvalidModel(_)
+        | GeminiError::InputTooLarge { .. }
+        | GeminiError::Blocked { .. }
+        | GeminiError::Truncated { .. }
+        | GeminiError::ApiError { status: 400, .. } => EXIT_DATA_ERROR,
+        GeminiError::HttpClient(_) => EXIT_UNAVAILABLE,
+        GeminiError::ApiError { .. } => match ErrorClass::of(error) {
+            Some(ErrorClass::RateLimited | ErrorClass::Overloaded) => EXIT_TEMP_FAIL,
+            Some(ErrorClass::ServerError) => EXIT_UNAVAILABLE,
+            None => EXIT_FAILURE,
+        },
+        GeminiError::JsonParse(_) | GeminiError::EmptyResponse => EXIT_FAILURE,
+    }
+}
+
+/// Parses a snake_case value name the way the server parses arguments
+fn parse_name<T: DeserializeOwned>(value: &str) -> Result<T, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_string())).map_err(|e| e.to_string())
+}
+
+fn parse_json<T: DeserializeOwned>(value: &str) -> Result<T, String> {
+    serde_json::from_str(value).map_err(|e| e.to_string())
+}
+
+/// Result rendering for `--format text`
+trait PlainText {
+    fn plain_text(&self) -> String;
+}
+
+impl PlainText for SummaryResult {
+    fn plain_text(&self) -> String {
+        self.summary.trim().to_string()
+    }
+}
+
+impl PlainText for SearchResult {
+    fn plain_text(&self) -> String {
+        let mut text = self.answer.trim().to_string();
+        if !self.results.is_empty() {
+            text.push_str("\n\nSources:");
+            for result in &self.results {
+                text.push_str(&format!(
+                    "\n- {} ({:.2}): {}",
+                    result.source_title, result.relevance_score, result.excerpt
+                ));
+            }
+        }
+        text
+    }
+}
+
+impl PlainText for BrainstormResult {
+    fn plain_text(&self) -> String {
+        self.ideas
+            .iter()
+            .map(|idea| format!("{}. {}", idea.id, idea.text))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl PlainText for AgentResult {
+    fn plain_text(&self) -> String {
+        self.report.trim().to_string()
+    }
+}
+
+impl PlainText for AnalyzeResult {
+    fn plain_text(&self) -> String {
+        let value = serde_json::to_value(self).unwrap_or_default();
+        let mut lines = Vec::new();
+        for (key, value) in value.as_object().into_iter().flatten() {
+            match value {
+                serde_json::Value::Array(items) if !items.is_empty() => {
+                    lines.push(format!("{}:", key));
+                    lines.extend(items.iter().map(|item| format!("- {}", scalar(item))));
+                }
+                serde_json::Value::Array(_) => {}
+                _ if key == "type" => {}
+                _ => lines.push(format!("{}: {}", key, scalar(value))),
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+/// Strings without quotes, objects as `key: value` pairs
+fn scalar(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Object(fields) => fields
+            .iter()
+            .map(|(k, v)| format!("{}: {}", k, scalar(v)))
+            .collect::<Vec<_>>()
+            .join(", "),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        run: RunArgs,
+    }
+
+    #[test]
+    fn test_parse_summarize() {
+        let cli = Cli::try_parse_from([
+            "run", "summarize", "--file", "README.md", "--length", "brief", "--style", "bullet_points", "--format",
+            "json", "--temperature", "0.2", "--params", r#"{"seed": 7, "temperature": 0.9}"#,
+        ])
+        .unwrap();
+        assert_eq!(cli.run.format, OutputFormat::Json);
+        let Tool::Summarize { file, length, style, .. } = &cli.run.tool else {
+            panic!("expected summarize");
+        };
+        assert_eq!(file.as_deref(), Some(Path::new("README.md")));
+        assert!(matches!(length, SummaryLength::Brief));
+        assert!(matches!(style, SummaryFormat::BulletPoints));
+
+        let params = params(&cli.run).unwrap();
+        assert_eq!(params.temperature, Some(0.2));
+        assert_eq!(params.seed, Some(7));
+
+        let err = Cli::try_parse_from(["run", "summarize", "--length", "tiny"]).err().unwrap();
+        assert!(err.to_string().contains("brief"));
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        let code = |e: GeminiError| exit_code(&anyhow::Error::from(e));
+        let api = |status| GeminiError::ApiError { status, message: String::new() };
+
+        assert_eq!(code(api(429)), EXIT_TEMP_FAIL);
+        assert_eq!(code(api(503)), EXIT_TEMP_FAIL);
+        assert_eq!(code(api(500)), EXIT_UNAVAILABLE);
+        assert_eq!(code(api(403)), EXIT_NO_PERMISSION);
+        assert_eq!(code(api(400)), EXIT_DATA_ERROR);
+        assert_eq!(code(GeminiError::ConfigError("no key".into())), EXIT_CONFIG);
+        assert_eq!(code(GeminiError::InvalidModel("gemini-9".into())), EXIT_DATA_ERROR);
+        assert_eq!(exit_code(&InvalidInput("Content cannot be empty".into()).into()), EXIT_DATA_ERROR);
+        let render = RenderError { id: "query".into(), message: "missing helper".into() };
+        assert_eq!(exit_code(&render.into()), EXIT_CONFIG);
+        assert_eq!(exit_code(&anyhow::anyhow!("serialization failed")), EXIT_FAILURE);
+
+        let missing = read_input(Some(Path::new("/nonexistent/input.txt"))).unwrap_err();
+        assert_eq!(exit_code(&missing), EXIT_NO_INPUT);
+        assert!(missing.to_string().contains("/nonexistent/input.txt"));
+    }
+
+    #[test]
+    fn test_plain_text() {
+        let ideas: BrainstormResult = BrainstormResult {
+            ideas: vec![
+                brainstorm::Idea { id: 1, text: "Tea".into() },
+                brainstorm::Idea { id: 2, text: "Coffee".into() },
+            ],
+            consensus_themes: None,
+        };
+        assert_eq!(ideas.plain_text(), "1. Tea\n2. Coffee");
+
+        let analysis = AnalyzeResult::Sentiment(analyze::SentimentAnalysis {
+            overall_sentiment: "positive".into(),
+            confidence: 0.5,
+            emotions: vec![analyze::Emotion { name: "joy".into(), intensity: 0.75 }],
+        });
+        assert_eq!(
+            analysis.plain_text(),
+            "confidence: 0.5\nemotions:\n- intensity: 0.75, name: joy\noverall_sentiment: positive"
+        );
+    }
+}
@@ -0,0 +1,32 @@
+// Fixtures shared by the library's unit tests
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// An environment lookup over fixed `(name, value)` pairs
+pub fn env(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let map: HashMap<String, String> = pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    move |key| map.get(key).cloned()
+}
+
+/// A path under the system temp dir that no other test process or thread
+/// uses; `name` keeps its extension
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "gemini-mcp-{}-{:?}-{}",
+        std::process::id(),
+        std::thread::current().id(),
+        name
+    ))
+}
+
+/// A fresh, empty directory at `temp_path(name)`
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = temp_path(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,308 @@
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock};
+use tracing::{debug, info};
+
+use crate::gemini::functions::{FunctionCallRecord, FunctionLoopOptions, LoopStopReason};
+use crate::gemini::types::Content;
+use crate::gemini::backend::GenerationBackend;
+use crate::tools::settings::ToolSettings;
+use crate::tools::sandbox::Sandbox;
+use crate::tools::types::{GenerationParams, InvalidInput, ResponseMetadata, ToolResponse};
+
+const DEFAULT_MAX_STEPS: u32 = 12;
+const MAX_STEPS_LIMIT: u32 = 40;
+const DEFAULT_TOKEN_BUDGET: u32 = 300_000;
+const TOKEN_BUDGET_LIMIT: u32 = 2_000_000;
+
+static CITATION_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(/[\w./@+-]+?)(?::(\d+))?(?:[`\s),;\]]|$|\.(?:\s|$))").unwrap());
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct AgentInput {
+    #[schemars(description = "The investigation task for Gemini")]
+    pub task: String,
+
+    #[schemars(description = "Restrict the investigation to these paths (must be inside the server's allowed roots)")]
+    #[serde(default)]
+    pub roots: Option<Vec<String>>,
+
+    #[schemars(description = "Maximum model turns (1-40)")]
+    #[serde(default)]
+    pub max_steps: Option<u32>,
+
+    #[schemars(description = "Total token budget across all turns")]
+    #[serde(default)]
+    pub token_budget: Option<u32>,
+
+    #[schemars(description = "Model id (e.g. gemini-2.5-flash-lite) or alias (pro, flash)")]
+    #[serde(default)]
+    pub model: Option<String>,
+
+    #[schemars(description = "Generation parameters")]
+    #[serde(default)]
+    pub params: Option<GenerationParams>,
+
+    #[schemars(description = "Named persona preset used as the system instruction (e.g. researcher, analyst, code-reviewer, none)")]
+    #[serde(default)]
+    pub persona: Option<String>,
+
+    #[schemars(description = "Literal system instruction; overrides persona")]
+    #[serde(default)]
+    pub system: Option<String>,
+}
+
+impl AgentInput {
+    pub fn new(task: impl Into<String>) -> Self {
+        Self {
+            task: task.into(),
+            roots: None,
+            max_steps: None,
+            token_budget: None,
+            model: None,
+            params: None,
+            persona: None,
+            system: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct AgentResult {
+    pub report: String,
+    /// `path:line` references in the report that point at files the agent read or searched
+    pub citations: Vec<AgentCitation>,
+    pub steps: u32,
+    pub function_calls: usize,
+    pub stop_reason: LoopStopReason,
+}
+
+#[derive(Debug, Serialize, JsonSchema, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AgentCitation {
+    pub path: String,
+    pub line: Option<u32>,
+}
+
+pub async fn execute_v2(
+    input: AgentInput,
+    client: Arc<dyn GenerationBackend>,
+    settings: &ToolSettings,
+    sandbox: &Sandbox,
+) -> anyhow::Result<ToolResponse<AgentResult>> {
+    info!("Agent: task_len={}, roots={:?}", input.task.len(), input.roots);
+
+    // Validate input
+    if input.task.trim().is_empty() {
+        anyhow::bail!(InvalidInput("Task cannot be empty".to_string()));
+    }
+
+    let max_steps = input.max_steps.unwrap_or(DEFAULT_MAX_STEPS);
+    if max_steps == 0 || max_steps > MAX_STEPS_LIMIT {
+        anyhow::bail!(InvalidInput(format!("max_steps must be between 1 and {}", MAX_STEPS_LIMIT)));
+    }
+
+    let token_budget = input.token_budget.unwrap_or(DEFAULT_TOKEN_BUDGET);
+    if token_budget == 0 || token_budget > TOKEN_BUDGET_LIMIT {
+        anyhow::bail!(InvalidInput(format!("token_budget must be between 1 and {}", TOKEN_BUDGET_LIMIT)));
+    }
+
+    let sandbox = match &input.roots {
+        Some(roots) if !roots.is_empty() => {
+            sandbox.restrict(roots).map_err(InvalidInput)?
+        }
+        _ => sandbox.clone(),
+    };
+
+    let roots: Vec<String> = sandbox.roots().iter().map(|r| r.display().to_string()).collect();
+
+    let system = settings.personas.resolve("agent", input.persona.as_deref(), input.system.as_deref())?;
+
+    let prompt = settings.templates.render(
+        "agent",
+        &json!({"task": input.task, "roots": roots, "example_root": roots[0]}),
+    )?;
+
+    let model = client.resolve_model(input.model.as_deref(), settings.model("agent")).await?;
+
+    let config = GenerationParams::config(
+        input.params.as_ref(),
+        settings.generation("agent"),
+    )?;
+
+    let options = FunctionLoopOptions {
+        max_steps,
+        max_total_tokens: Some(token_budget),
+        ..Default::default()
+    };
+
+    let contents = vec![Content::user(prompt.text)];
+
+    let functions = sandbox.function_registry();
+    debug!("Agent functions: {:?}", functions.names());
+
+    let response = client
+        .generate_with_functions(
+            contents,
+            system.as_deref(),
+            &model.id,
+            Some(config),
+            GenerationParams::safety_overrides(input.params.as_ref()),
+            &functions,
+            &options,
+        )
+        .await?;
+
+    debug!(
+        "Agent finished: steps={}, calls={}, stop={:?}",
+        response.steps,
+        response.calls.len(),
+        response.stop_reason
+    );
+
+    if response.text.trim().is_empty() {
+        anyhow::bail!("Gemini returned an empty report");
+    }
+
+    let citations = extract_citations(&response.text, &sandbox, &response.calls);
+
+    let result = AgentResult {
+        citations,
+        steps: response.steps,
+        function_calls: response.calls.len(),
+        stop_reason: response.stop_reason,
+        report: response.text,
+    };
+
+    let metadata = ResponseMetadata::with_usage(&response.model, &response.usage)
+        .with_fallback(response.fallback_from)
+        .with_trace(response.calls)
+        .with_safety(response.safety_ratings)
+        .with_template(prompt.template);
+
+    Ok(ToolResponse {
+        result,
+        thoughts: response.thoughts,
+        metadata,
+    })
+}
+
+/// Collects `path:line` references to files the agent read, or that lie
+/// under a path it searched
+fn extract_citations(report: &str, sandbox: &Sandbox, calls: &[FunctionCallRecord]) -> Vec<AgentCitation> {
+    let visited = visited_paths(sandbox, calls);
+    let mut citations = BTreeSet::new();
+
+    for cap in CITATION_REGEX.captures_iter(report) {
+        let path = cap[1].trim_end_matches('.');
+        let Ok(resolved) = sandbox.resolve(path) else {
+            continue;
+        };
+        let cited = visited.iter().any(|(visited, searched)| {
+            resolved == *visited || (*searched && resolved.starts_with(visited))
+        });
+        if !cited {
+            continue;
+        }
+        citations.insert(AgentCitation {
+            path: path.to_string(),
+            line: cap.get(2).and_then(|m| m.as_str().parse().ok()),
+        });
+    }
+
+    citations.into_iter().collect()
+}
+
+/// Paths passed to successful `read_file` and `grep` calls, flagged `true`
+/// when everything below them was searched
+fn visited_paths(sandbox: &Sandbox, calls: &[FunctionCallRecord]) -> Vec<(PathBuf, bool)> {
+    calls
+        .iter()
+        .filter(|call| !call.is_error)
+        .filter_map(|call| {
+            let path = call.args.get("path").and_then(|v| v.as_str());
+            match (call.name.as_str(), path) {
+                ("read_file", Some(path)) => Some((sandbox.resolve(path).ok()?, false)),
+                ("grep", Some(path)) => Some((sandbox.resolve(path).ok()?, true)),
+                ("grep", None) => Some((sandbox.roots()[0].clone(), true)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_agent_input_defaults() {
+        let json = r#"{"task": "Find the entry point"}"#;
+        let input: AgentInput = serde_json::from_str(json).unwrap();
+        assert_eq!(input.task, "Find the entry point");
+        assert!(input.roots.is_none());
+        assert!(input.max_steps.is_none());
+    }
+
+    fn call(name: &str, args: serde_json::Value) -> FunctionCallRecord {
+        FunctionCallRecord {
+            step: 1,
+            name: name.to_string(),
+            args,
+            response: json!({}),
+            is_error: false,
+            duration_ms: 0,
+        }
+    }
+
+    #[test]
+    fn test_extract_citations_keeps_only_sandboxed_paths() {
+        let root = std::env::current_dir().unwrap();
+        let sandbox = Sandbox::new(&[root.join("src")]).unwrap();
+        let main = root.join("src/main.rs").canonicalize().unwrap();
+
+        let report = format!(
+            "Entry point is `{}:42`. See also {} and /etc/passwd:1.",
+            main.display(),
+            main.display()
+        );
+        let calls = [call("grep", json!({"pattern": "fn main"}))];
+        let citations = extract_citations(&report, &sandbox, &calls);
+
+        assert_eq!(citations.len(), 2);
+        assert!(citations.iter().all(|c| c.path == main.display().to_string()));
+        assert!(citations.iter().any(|c| c.line == Some(42)));
+        assert!(citations.iter().any(|c| c.line.is_none()));
+    }
+
+    #[test]
+    fn test_extract_citations_keeps_only_visited_paths() {
+        let root = std::env::current_dir().unwrap();
+        let sandbox = Sandbox::new(&[root.join("src")]).unwrap();
+        let main = root.join("src/main.rs").canonicalize().unwrap();
+        let lib = root.join("src/lib.rs").canonicalize().unwrap();
+        let client = root.join("src/gemini/client.rs").canonicalize().unwrap();
+
+        let report = format!(
+            "See {}:1, {}:2 and {}:3.",
+            main.display(),
+            lib.display(),
+            client.display()
+        );
+        let calls = [
+            call("read_file", json!({"path": "main.rs"})),
+            call("grep", json!({"pattern": "impl", "path": "gemini"})),
+            call("list_directory", json!({"path": "."})),
+        ];
+        let citations = extract_citations(&report, &sandbox, &calls);
+
+        let paths: Vec<_> = citations.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths.len(), 2);
+        assert!(paths.contains(&main.display().to_string().as_str()));
+        assert!(paths.contains(&client.display().to_string().as_str()));
+        assert!(extract_citations(&report, &sandbox, &[]).is_empty());
+    }
+}
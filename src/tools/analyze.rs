@@ -1,10 +1,13 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::sync::Arc;
 use tracing::{debug, info};
 
-use crate::gemini::{client::GeminiClient, models::GeminiModel};
-use crate::tools::types::{GenerationParams, ModelPreference, ResponseMetadata, ToolResponse};
+use crate::gemini::backend::GenerationBackend;
+use crate::gemini::types::{GenerationConfig, GenerationResponse};
+use crate::tools::settings::ToolSettings;
+use crate::tools::types::{GenerationParams, InvalidInput, ResponseMetadata, ToolResponse};
 
 // Shared analyze output for backward compatibility
 #[derive(Debug, Serialize)]
@@ -20,6 +23,10 @@ pub struct AnalyzeCodeInput {
     pub language: Option<String>,
     #[serde(default = "default_focus")]
     pub focus: String,
+    #[serde(default)]
+    pub persona: Option<String>,
+    #[serde(default)]
+    pub system: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -27,6 +34,10 @@ pub struct AnalyzeTextInput {
     pub text: String,
     #[serde(default)]
     pub focus: Option<String>,
+    #[serde(default)]
+    pub persona: Option<String>,
+    #[serde(default)]
+    pub system: Option<String>,
 }
 
 fn default_focus() -> String {
@@ -46,13 +57,35 @@ pub struct AnalyzeInput {
     #[serde(default)]
     pub options: Option<AnalyzerOptions>,
 
-    #[schemars(description = "Model preference")]
+    #[schemars(description = "Model id (e.g. gemini-2.5-flash-lite) or alias (pro, flash)")]
     #[serde(default)]
-    pub model: Option<ModelPreference>,
+    pub model: Option<String>,
 
     #[schemars(description = "Generation parameters")]
     #[serde(default)]
     pub params: Option<GenerationParams>,
+
+    #[schemars(description = "Named persona preset used as the system instruction (e.g. researcher, analyst, code-reviewer, none)")]
+    #[serde(default)]
+    pub persona: Option<String>,
+
+    #[schemars(description = "Literal system instruction; overrides persona")]
+    #[serde(default)]
+    pub system: Option<String>,
+}
+
+impl AnalyzeInput {
+    pub fn new(content: impl Into<String>, analyzer_type: AnalyzerType) -> Self {
+        Self {
+            content: content.into(),
+            analyzer_type,
+            options: None,
+            model: None,
+            params: None,
+            persona: None,
+            system: None,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
@@ -85,22 +118,19 @@ pub struct AnalyzerOptions {
     pub focus_areas: Option<Vec<String>>,
 
     #[schemars(description = "Level of detail in analysis")]
-    #[serde(default = "default_detail_level")]
+    #[serde(default)]
     pub detail_level: DetailLevel,
 }
 
-#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum DetailLevel {
     Brief,
+    #[default]
     Standard,
     Comprehensive,
 }
 
-fn default_detail_level() -> DetailLevel {
-    DetailLevel::Standard
-}
-
 #[derive(Debug, Serialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum AnalyzeResult {
@@ -176,7 +206,8 @@ pub struct ComparisonAnalysis {
 // Legacy execute_code for backward compatibility
 pub async fn execute_code(
     input: AnalyzeCodeInput,
-    client: Arc<GeminiClient>,
+    client: Arc<dyn GenerationBackend>,
+    settings: &ToolSettings,
 ) -> anyhow::Result<AnalyzeOutput> {
     info!(
         "Analyze code (legacy): language={:?}, focus={}, code_len={}",
@@ -185,27 +216,16 @@ pub async fn execute_code(
         input.code.len()
     );
 
-    let focus_instruction = match input.focus.as_str() {
-        "quality" => "Focus on code quality, readability, and best practices.",
-        "security" => "Focus on security vulnerabilities and potential exploits.",
-        "performance" => "Focus on performance optimizations and bottlenecks.",
-        "bugs" => "Focus on identifying bugs and logical errors.",
-        _ => "Provide a general comprehensive analysis.",
-    };
-
-    let lang_info = input
-        .language
-        .as_ref()
-        .map(|l| format!("Language: {}\n", l))
-        .unwrap_or_default();
+    let prompt = settings.templates.render(
+        "legacy.analyze-code",
+        &json!({"code": input.code, "language": input.language, "focus": input.focus}),
+    )?;
 
-    let prompt = format!(
-        "Analyze the following code:\n\n{}```\n{}\n```\n\n{}",
-        lang_info, input.code, focus_instruction
-    );
+    let system = settings.personas.resolve("analyze-code", input.persona.as_deref(), input.system.as_deref())?;
 
+    let model = client.resolve_model(None, settings.model("analyze-code")).await?;
     let response = client
-        .generate_content(&prompt, GeminiModel::Pro, None)
+        .generate_content(&prompt.text, system.as_deref(), &model.id, None, &[])
         .await?;
 
     debug!("Analyze code (legacy): analysis_len={}", response.text.len());
@@ -216,7 +236,8 @@ pub async fn execute_code(
 // Legacy execute_text for backward compatibility
 pub async fn execute_text(
     input: AnalyzeTextInput,
-    client: Arc<GeminiClient>,
+    client: Arc<dyn GenerationBackend>,
+    settings: &ToolSettings,
 ) -> anyhow::Result<AnalyzeOutput> {
     info!(
         "Analyze text (legacy): focus={:?}, text_len={}",
@@ -224,19 +245,16 @@ pub async fn execute_text(
         input.text.len()
     );
 
-    let focus_instruction = input
-        .focus
-        .as_ref()
-        .map(|f| format!("\n\nFocus on: {}", f))
-        .unwrap_or_default();
+    let prompt = settings.templates.render(
+        "legacy.analyze-text",
+        &json!({"text": input.text, "focus": input.focus}),
+    )?;
 
-    let prompt = format!(
-        "Analyze the following text:{}\n\n{}",
-        focus_instruction, input.text
-    );
+    let system = settings.personas.resolve("analyze", input.persona.as_deref(), input.system.as_deref())?;
 
+    let model = client.resolve_model(None, settings.model("analyze")).await?;
     let response = client
-        .generate_content(&prompt, GeminiModel::Pro, None)
+        .generate_content(&prompt.text, system.as_deref(), &model.id, None, &[])
         .await?;
 
     debug!("Analyze text (legacy): analysis_len={}", response.text.len());
@@ -247,7 +265,8 @@ pub async fn execute_text(
 // V2 unified analyze implementation
 pub async fn execute_v2(
     input: AnalyzeInput,
-    client: Arc<GeminiClient>,
+    client: Arc<dyn GenerationBackend>,
+    settings: &ToolSettings,
 ) -> anyhow::Result<ToolResponse<AnalyzeResult>> {
     info!(
         "Analyze v2: type={:?}, content_len={}",
@@ -257,229 +276,125 @@ pub async fn execute_v2(
 
     // Validate input
     if input.content.trim().is_empty() {
-        anyhow::bail!("Content cannot be empty");
+        anyhow::bail!(InvalidInput("Content cannot be empty".to_string()));
     }
 
-    let model = match input.model {
-        Some(ModelPreference::Flash) => GeminiModel::Flash,
-        Some(ModelPreference::Pro) | None => GeminiModel::Pro,
+    let tool = match input.analyzer_type {
+        AnalyzerType::Code { .. } => "analyze-code",
+        _ => "analyze",
     };
+    let model = client.resolve_model(input.model.as_deref(), settings.model(tool)).await?;
+    let defaults = settings.generation(tool);
+    let system = settings.personas.resolve(tool, input.persona.as_deref(), input.system.as_deref())?;
+    let system = system.as_deref();
 
-    let (result, usage) = match &input.analyzer_type {
+    let (template, data) = match &input.analyzer_type {
         AnalyzerType::Text => {
-            let (analysis, usage) = analyze_text(&input, &client, model).await?;
-            (AnalyzeResult::Text(analysis), usage)
+            let focus_areas = input
+                .options
+                .as_ref()
+                .and_then(|o| o.focus_areas.as_ref())
+                .map(|f| f.join(", "));
+            ("analyze.text", json!({"content": input.content, "focus_areas": focus_areas}))
         }
         AnalyzerType::Code { language } => {
-            let (analysis, usage) = analyze_code(&input, language.clone(), &client, model).await?;
-            (AnalyzeResult::Code(analysis), usage)
-        }
-        AnalyzerType::Document => {
-            let (analysis, usage) = analyze_document(&input, &client, model).await?;
-            (AnalyzeResult::Document(analysis), usage)
-        }
-        AnalyzerType::Sentiment => {
-            let (analysis, usage) = analyze_sentiment(&input, &client, model).await?;
-            (AnalyzeResult::Sentiment(analysis), usage)
-        }
-        AnalyzerType::Comparison { compare_with } => {
-            let (analysis, usage) = analyze_comparison(&input, compare_with, &client, model).await?;
-            (AnalyzeResult::Comparison(analysis), usage)
+            ("analyze.code", json!({"content": input.content, "language": language}))
         }
+        AnalyzerType::Document => ("analyze.document", json!({"content": input.content})),
+        AnalyzerType::Sentiment => ("analyze.sentiment", json!({"content": input.content})),
+        AnalyzerType::Comparison { compare_with } => (
+            "analyze.comparison",
+            json!({"content": input.content, "compare_with": compare_with}),
+        ),
     };
+    debug!("Running {} analyzer", template);
+    let prompt = settings.templates.render(template, &data)?;
 
-    let metadata = ResponseMetadata::with_usage(model.as_str(), &usage);
+    let response = generate(&input, client.as_ref(), system, &model.id, &defaults, &prompt.text).await?;
 
-    Ok(ToolResponse { result, metadata })
-}
+    let result = match &input.analyzer_type {
+        AnalyzerType::Text => AnalyzeResult::Text(analyze_text(&response.text)),
+        AnalyzerType::Code { .. } => AnalyzeResult::Code(analyze_code(&response.text)),
+        AnalyzerType::Document => AnalyzeResult::Document(analyze_document(&response.text)),
+        AnalyzerType::Sentiment => AnalyzeResult::Sentiment(analyze_sentiment(&response.text)),
+        AnalyzerType::Comparison { .. } => AnalyzeResult::Comparison(analyze_comparison(&response.text)),
+    };
 
-async fn analyze_text(
-    input: &AnalyzeInput,
-    client: &GeminiClient,
-    model: GeminiModel,
-) -> anyhow::Result<(TextAnalysis, crate::gemini::types::UsageMetadata)> {
-    debug!("Running text analyzer");
-
-    let focus = input
-        .options
-        .as_ref()
-        .and_then(|o| o.focus_areas.as_ref())
-        .map(|f| format!("\nFocus on: {}", f.join(", ")))
-        .unwrap_or_default();
-
-    let prompt = format!(
-        "Analyze the following text and provide:\n\
-         1. Overall sentiment (positive, negative, neutral, mixed)\n\
-         2. Main themes (3-5 themes)\n\
-         3. Tone (formal, informal, technical, conversational, etc.)\n\
-         4. Key points (3-5 bullet points){}\n\n\
-         Text:\n{}\n\n\
-         Provide analysis in a structured format.",
-        focus, input.content
-    );
+    let metadata = ResponseMetadata::for_response(&response).with_template(prompt.template);
 
-    let response = client.generate_content(&prompt, model, None).await?;
+    Ok(ToolResponse {
+        result,
+        thoughts: response.thoughts,
+        metadata,
+    })
+}
 
+fn analyze_text(text: &str) -> TextAnalysis {
     // Parse the response (simplified - in production, use JSON mode)
-    let sentiment = extract_field(&response.text, "sentiment").unwrap_or_else(|| "neutral".to_string());
-    let themes = extract_list(&response.text, "theme");
-    let tone = extract_field(&response.text, "tone").unwrap_or_else(|| "neutral".to_string());
-    let key_points = extract_list(&response.text, "key point");
-
-    let analysis = TextAnalysis {
-        sentiment,
-        themes,
-        tone,
-        key_points,
-    };
-
-    Ok((analysis, response.usage))
+    TextAnalysis {
+        sentiment: extract_field(text, "sentiment").unwrap_or_else(|| "neutral".to_string()),
+        themes: extract_list(text, "theme"),
+        tone: extract_field(text, "tone").unwrap_or_else(|| "neutral".to_string()),
+        key_points: extract_list(text, "key point"),
+    }
 }
 
-async fn analyze_code(
-    input: &AnalyzeInput,
-    language: Option<String>,
-    client: &GeminiClient,
-    model: GeminiModel,
-) -> anyhow::Result<(CodeAnalysis, crate::gemini::types::UsageMetadata)> {
-    debug!("Running code analyzer for language: {:?}", language);
-
-    let lang_info = language
-        .as_ref()
-        .map(|l| format!("Language: {}\n", l))
-        .unwrap_or_default();
-
-    let prompt = format!(
-        "Analyze this code and provide:\n\
-         1. Quality score (0-10)\n\
-         2. List of issues with severity (critical/high/medium/low) and category\n\
-         3. Design patterns used\n\
-         4. Complexity assessment\n\
-         5. Improvement suggestions\n\n\
-         {}```\n{}\n```\n\n\
-         Be specific and actionable.",
-        lang_info, input.content
-    );
-
-    let response = client.generate_content(&prompt, model, None).await?;
-
-    // Parse response (simplified)
-    let quality_score = extract_score(&response.text).unwrap_or(5.0);
-    let issues = extract_issues(&response.text);
-    let patterns = extract_list(&response.text, "pattern");
-    let complexity = extract_field(&response.text, "complexity").unwrap_or_else(|| "moderate".to_string());
-    let suggestions = extract_list(&response.text, "suggestion");
-
-    let analysis = CodeAnalysis {
-        quality_score,
-        issues,
-        patterns,
-        complexity,
-        suggestions,
-    };
-
-    Ok((analysis, response.usage))
+fn analyze_code(text: &str) -> CodeAnalysis {
+    CodeAnalysis {
+        quality_score: extract_score(text).unwrap_or(5.0),
+        issues: extract_issues(text),
+        patterns: extract_list(text, "pattern"),
+        complexity: extract_field(text, "complexity").unwrap_or_else(|| "moderate".to_string()),
+        suggestions: extract_list(text, "suggestion"),
+    }
 }
 
-async fn analyze_document(
-    input: &AnalyzeInput,
-    client: &GeminiClient,
-    model: GeminiModel,
-) -> anyhow::Result<(DocumentAnalysis, crate::gemini::types::UsageMetadata)> {
-    debug!("Running document analyzer");
-
-    let prompt = format!(
-        "Analyze this document's structure and readability:\n\
-         1. Overall structure (how it's organized)\n\
-         2. Readability score (0-10, where 10 is most readable)\n\
-         3. Main sections\n\
-         4. Key points\n\n\
-         Document:\n{}\n\n\
-         Provide structured analysis.",
-        input.content
-    );
-
-    let response = client.generate_content(&prompt, model, None).await?;
-
-    let structure = extract_field(&response.text, "structure").unwrap_or_else(|| "linear".to_string());
-    let readability_score = extract_score(&response.text).unwrap_or(7.0);
-    let sections = extract_list(&response.text, "section");
-    let key_points = extract_list(&response.text, "key point");
-
-    let analysis = DocumentAnalysis {
-        structure,
-        readability_score,
-        sections,
-        key_points,
-    };
-
-    Ok((analysis, response.usage))
+fn analyze_document(text: &str) -> DocumentAnalysis {
+    DocumentAnalysis {
+        structure: extract_field(text, "structure").unwrap_or_else(|| "linear".to_string()),
+        readability_score: extract_score(text).unwrap_or(7.0),
+        sections: extract_list(text, "section"),
+        key_points: extract_list(text, "key point"),
+    }
 }
 
-async fn analyze_sentiment(
-    input: &AnalyzeInput,
-    client: &GeminiClient,
-    model: GeminiModel,
-) -> anyhow::Result<(SentimentAnalysis, crate::gemini::types::UsageMetadata)> {
-    debug!("Running sentiment analyzer");
-
-    let prompt = format!(
-        "Perform detailed sentiment analysis:\n\
-         1. Overall sentiment (very negative, negative, neutral, positive, very positive)\n\
-         2. Confidence level (0-1)\n\
-         3. Detected emotions with intensity (0-1): joy, sadness, anger, fear, surprise, etc.\n\n\
-         Text:\n{}\n\n\
-         Be precise and nuanced.",
-        input.content
-    );
-
-    let response = client.generate_content(&prompt, model, None).await?;
-
-    let overall_sentiment = extract_field(&response.text, "sentiment")
-        .unwrap_or_else(|| "neutral".to_string());
-    let confidence = extract_score(&response.text).unwrap_or(0.5);
-    let emotions = extract_emotions(&response.text);
-
-    let analysis = SentimentAnalysis {
-        overall_sentiment,
-        confidence,
-        emotions,
-    };
+fn analyze_sentiment(text: &str) -> SentimentAnalysis {
+    SentimentAnalysis {
+        overall_sentiment: extract_field(text, "sentiment").unwrap_or_else(|| "neutral".to_string()),
+        confidence: extract_score(text).unwrap_or(0.5),
+        emotions: extract_emotions(text),
+    }
+}
 
-    Ok((analysis, response.usage))
+fn analyze_comparison(text: &str) -> ComparisonAnalysis {
+    ComparisonAnalysis {
+        similarities: extract_list(text, "similar"),
+        differences: extract_list(text, "differ"),
+        verdict: extract_field(text, "verdict").unwrap_or_else(|| "moderately similar".to_string()),
+    }
 }
 
-async fn analyze_comparison(
+async fn generate(
     input: &AnalyzeInput,
-    compare_with: &str,
-    client: &GeminiClient,
-    model: GeminiModel,
-) -> anyhow::Result<(ComparisonAnalysis, crate::gemini::types::UsageMetadata)> {
-    debug!("Running comparison analyzer");
-
-    let prompt = format!(
-        "Compare these two texts:\n\n\
-         Text A:\n{}\n\n\
-         Text B:\n{}\n\n\
-         Provide:\n\
-         1. Key similarities\n\
-         2. Key differences\n\
-         3. Overall verdict on how similar they are",
-        input.content, compare_with
-    );
-
-    let response = client.generate_content(&prompt, model, None).await?;
-
-    let similarities = extract_list(&response.text, "similar");
-    let differences = extract_list(&response.text, "differ");
-    let verdict = extract_field(&response.text, "verdict")
-        .unwrap_or_else(|| "moderately similar".to_string());
-
-    Ok(ComparisonAnalysis {
-        similarities,
-        differences,
-        verdict,
-    })
+    client: &dyn GenerationBackend,
+    system: Option<&str>,
+    model: &str,
+    defaults: &GenerationConfig,
+    prompt: &str,
+) -> anyhow::Result<GenerationResponse> {
+    let params = input.params.as_ref();
+    let config = GenerationParams::config(params, defaults.clone())?;
+    let response = client
+        .generate_content_with_continuation(
+            prompt,
+            system,
+            model,
+            Some(config),
+            GenerationParams::safety_overrides(params),
+            GenerationParams::max_continuations(params),
+        )
+        .await?;
+    Ok(response)
 }
 
 // Helper parsing functions (simplified - in production, use structured JSON output)
@@ -505,31 +420,72 @@ fn extract_score(text: &str) -> Option<f32> {
     })
 }
 
+const SEVERITIES: &[&str] = &["critical", "high", "medium", "low"];
+
 fn extract_list(text: &str, keyword: &str) -> Vec<String> {
     text.lines()
-        .filter(|line| line.to_lowercase().contains(keyword) && (line.starts_with('-') || line.starts_with('*') || line.contains('•')))
-        .map(|line| {
-            line.trim_start_matches('-')
-                .trim_start_matches('*')
-                .trim_start_matches('•')
-                .trim()
-                .to_string()
-        })
+        .filter_map(list_item)
+        .filter(|item| item.to_lowercase().contains(keyword))
+        .map(|item| split_label(item, &[keyword]).1.to_string())
+        .filter(|item| !item.is_empty())
         .collect()
 }
 
+/// Text of a `-`, `*` or `•` bullet line
+fn list_item(line: &str) -> Option<&str> {
+    let line = line.trim();
+    (line.starts_with('-') || line.starts_with('*') || line.starts_with('•'))
+        .then(|| line.trim_start_matches(['-', '*', '•']).trim())
+}
+
+/// Splits a leading label such as `Suggestion:` or `**Issue (high):**` off
+/// an item when it names one of `keywords`
+fn split_label<'a>(item: &'a str, keywords: &[&str]) -> (Option<&'a str>, &'a str) {
+    let Some((label, rest)) = item.split_once(':') else {
+        return (None, item);
+    };
+    let lower = label.to_lowercase();
+    if label.split_whitespace().count() > 4 || !keywords.iter().any(|k| lower.contains(k)) {
+        return (None, item);
+    }
+    (Some(label.trim_matches(['*', ' '])), rest.trim_start_matches(['*', ' ']).trim())
+}
+
 fn extract_issues(text: &str) -> Vec<CodeIssue> {
-    // Simplified extraction
+    const KEYWORDS: &[&str] = &["issue", "problem"];
+
     let mut issues = Vec::new();
     for line in text.lines() {
-        if line.to_lowercase().contains("issue") || line.to_lowercase().contains("problem") {
-            issues.push(CodeIssue {
-                severity: "medium".to_string(),
-                category: "general".to_string(),
-                description: line.trim().to_string(),
-                location: None,
-            });
+        let bullet = list_item(line);
+        let (label, description) = split_label(bullet.unwrap_or(line.trim()), KEYWORDS);
+        // Only labeled lines or bullets naming an issue count; headers such
+        // as "Issues:" have nothing after the label
+        let mentions_issue = KEYWORDS.iter().any(|k| description.to_lowercase().contains(k));
+        if description.is_empty() || (label.is_none() && !(bullet.is_some() && mentions_issue)) {
+            continue;
         }
+
+        // Severity and category may follow the label, as in "Issue (high, security)"
+        let details: Vec<String> = label
+            .and_then(|label| label.split_once(['(', '[']))
+            .map(|(_, details)| {
+                details
+                    .trim_end_matches([')', ']'])
+                    .split(',')
+                    .map(|d| d.trim().to_lowercase())
+                    .filter(|d| !d.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let severity = details.iter().find(|d| SEVERITIES.contains(&d.as_str()));
+        let category = details.iter().find(|d| !SEVERITIES.contains(&d.as_str()));
+
+        issues.push(CodeIssue {
+            severity: severity.cloned().unwrap_or_else(|| "medium".to_string()),
+            category: category.cloned().unwrap_or_else(|| "general".to_string()),
+            description: description.to_string(),
+            location: None,
+        });
     }
     issues
 }
@@ -583,6 +539,22 @@ mod tests {
         let text = "Themes:\n- Theme 1\n- Theme 2\n* Theme 3";
         let themes = extract_list(text, "theme");
         assert!(themes.len() >= 2);
+
+        let text = "Suggestions:\n- Suggestion: add tests\n* **Suggestion:** split the module";
+        assert_eq!(extract_list(text, "suggestion"), ["add tests", "split the module"]);
+    }
+
+    #[test]
+    fn test_extract_issues() {
+        let text = "Issues:\n- Issue (high, security): SQL injection in find_user\n- Problem: no tests\nNo other issues found.";
+        let issues = extract_issues(text);
+        // Prose mentioning issues is not an issue itself
+        assert_eq!(issues.len(), 2);
+        assert_eq!(
+            (issues[0].severity.as_str(), issues[0].category.as_str(), issues[0].description.as_str()),
+            ("high", "security", "SQL injection in find_user")
+        );
+        assert_eq!((issues[1].severity.as_str(), issues[1].description.as_str()), ("medium", "no tests"));
     }
 
     #[test]
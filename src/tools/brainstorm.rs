@@ -1,12 +1,14 @@
 use regex::Regex;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tracing::{debug, info};
 
-use crate::gemini::{client::GeminiClient, models::GeminiModel, types::GenerationConfig};
-use crate::tools::types::{GenerationParams, ModelPreference, ResponseMetadata, ToolResponse};
+use crate::gemini::backend::GenerationBackend;
+use crate::tools::settings::ToolSettings;
+use crate::tools::types::{GenerationParams, InvalidInput, ResponseMetadata, ToolResponse};
 
 #[derive(Debug, Deserialize, JsonSchema)]
 pub struct BrainstormInput {
@@ -25,14 +27,22 @@ pub struct BrainstormInput {
     #[serde(default = "default_extract_consensus")]
     pub extract_consensus: bool,
 
-    #[schemars(description = "Model preference")]
+    #[schemars(description = "Model id (e.g. gemini-2.5-flash-lite) or alias (pro, flash)")]
     #[serde(default)]
-    pub model: Option<ModelPreference>,
+    pub model: Option<String>,
 
     #[schemars(description = "Generation parameters")]
     #[serde(default)]
     pub params: Option<GenerationParams>,
 
+    #[schemars(description = "Named persona preset used as the system instruction (e.g. researcher, analyst, code-reviewer, none)")]
+    #[serde(default)]
+    pub persona: Option<String>,
+
+    #[schemars(description = "Literal system instruction; overrides persona")]
+    #[serde(default)]
+    pub system: Option<String>,
+
     // Legacy field for backward compatibility
     #[serde(default)]
     pub claude_thoughts: Option<String>,
@@ -41,6 +51,23 @@ pub struct BrainstormInput {
     pub max_rounds: Option<u32>,
 }
 
+impl BrainstormInput {
+    pub fn new(prompt: impl Into<String>) -> Self {
+        Self {
+            prompt: prompt.into(),
+            num_ideas: default_num_ideas(),
+            constraints: None,
+            extract_consensus: default_extract_consensus(),
+            model: None,
+            params: None,
+            persona: None,
+            system: None,
+            claude_thoughts: None,
+            max_rounds: default_max_rounds(),
+        }
+    }
+}
+
 fn default_num_ideas() -> u32 {
     10
 }
@@ -81,7 +108,8 @@ pub struct BrainstormOutput {
 
 pub async fn execute(
     input: BrainstormInput,
-    client: Arc<GeminiClient>,
+    client: Arc<dyn GenerationBackend>,
+    settings: &ToolSettings,
 ) -> anyhow::Result<BrainstormOutput> {
     info!(
         "Brainstorm tool: topic_len={}, num_ideas={}",
@@ -91,10 +119,10 @@ pub async fn execute(
 
     // Check if this is a legacy request (has claude_thoughts)
     if input.claude_thoughts.is_some() {
-        return execute_legacy(input, client).await;
+        return execute_legacy(input, client, settings).await;
     }
 
-    let response = execute_v2(input, client).await?;
+    let response = execute_v2(input, client, settings).await?;
 
     // Convert to legacy format
     let synthesis = serde_json::to_string_pretty(&response)?;
@@ -106,7 +134,8 @@ pub async fn execute(
 
 pub async fn execute_v2(
     input: BrainstormInput,
-    client: Arc<GeminiClient>,
+    client: Arc<dyn GenerationBackend>,
+    settings: &ToolSettings,
 ) -> anyhow::Result<ToolResponse<BrainstormResult>> {
     debug!(
         "Brainstorm v2: topic={}, num_ideas={}, extract_consensus={}",
@@ -117,39 +146,40 @@ pub async fn execute_v2(
 
     // Validate input
     if input.num_ideas == 0 || input.num_ideas > 50 {
-        anyhow::bail!("num_ideas must be between 1 and 50");
+        anyhow::bail!(InvalidInput("num_ideas must be between 1 and 50".to_string()));
     }
 
     if input.prompt.trim().is_empty() {
-        anyhow::bail!("Topic cannot be empty");
+        anyhow::bail!(InvalidInput("Topic cannot be empty".to_string()));
     }
 
-    let mut prompt = format!(
-        "Generate {} creative, diverse ideas for the following topic:\n\n{}\n\n",
-        input.num_ideas, input.prompt
-    );
-
-    if let Some(constraints) = &input.constraints {
-        prompt.push_str(&format!("Constraints: {}\n\n", constraints));
-    }
+    let system = settings.personas.resolve("brainstorm", input.persona.as_deref(), input.system.as_deref())?;
 
-    prompt.push_str("List each idea on a new line, numbered (1., 2., 3., etc.).\n");
-    prompt.push_str("Make ideas specific, actionable, and varied in approach.");
+    let prompt = settings.templates.render(
+        "brainstorm",
+        &json!({
+            "topic": input.prompt,
+            "num_ideas": input.num_ideas,
+            "constraints": input.constraints,
+        }),
+    )?;
 
-    let model = match input.model {
-        Some(ModelPreference::Flash) => GeminiModel::Flash,
-        Some(ModelPreference::Pro) | None => GeminiModel::Pro,
-    };
+    let model = client.resolve_model(input.model.as_deref(), settings.model("brainstorm")).await?;
 
-    let config = GenerationConfig {
-        temperature: input.params.as_ref().and_then(|p| p.temperature).or(Some(0.9)),
-        max_output_tokens: input.params.as_ref().and_then(|p| p.max_tokens).or(Some(2048)),
-        top_p: input.params.as_ref().and_then(|p| p.top_p),
-        top_k: input.params.as_ref().and_then(|p| p.top_k),
-    };
+    let config = GenerationParams::config(
+        input.params.as_ref(),
+        settings.generation("brainstorm"),
+    )?;
 
     let response = client
-        .generate_content(&prompt, model, Some(config))
+        .generate_content_with_continuation(
+            &prompt.text,
+            system.as_deref(),
+            &model.id,
+            Some(config),
+            GenerationParams::safety_overrides(input.params.as_ref()),
+            GenerationParams::max_continuations(input.params.as_ref()),
+        )
         .await?;
 
     debug!("Ideas generated: {} chars", response.text.len());
@@ -171,9 +201,13 @@ pub async fn execute_v2(
         consensus_themes,
     };
 
-    let metadata = ResponseMetadata::with_usage(model.as_str(), &response.usage);
+    let metadata = ResponseMetadata::for_response(&response).with_template(prompt.template);
 
-    Ok(ToolResponse { result, metadata })
+    Ok(ToolResponse {
+        result,
+        thoughts: response.thoughts,
+        metadata,
+    })
 }
 
 fn parse_ideas(text: &str) -> Vec<Idea> {
@@ -217,7 +251,7 @@ fn extract_consensus_themes(ideas: &[Idea]) -> Vec<ConsensusTheme> {
                 if seen_in_idea.insert(word.clone()) {
                     keyword_to_ideas
                         .entry(word)
-                        .or_insert_with(Vec::new)
+                        .or_default()
                         .push(idea.id);
                 }
             }
@@ -249,7 +283,7 @@ fn extract_consensus_themes(ideas: &[Idea]) -> Vec<ConsensusTheme> {
         .collect();
 
     // Sort by frequency (descending)
-    themes.sort_by(|a, b| b.frequency.cmp(&a.frequency));
+    themes.sort_by_key(|t| std::cmp::Reverse(t.frequency));
 
     // Return top 10 themes
     themes.into_iter().take(10).collect()
@@ -258,7 +292,8 @@ fn extract_consensus_themes(ideas: &[Idea]) -> Vec<ConsensusTheme> {
 // Legacy implementation for backward compatibility
 async fn execute_legacy(
     input: BrainstormInput,
-    client: Arc<GeminiClient>,
+    client: Arc<dyn GenerationBackend>,
+    settings: &ToolSettings,
 ) -> anyhow::Result<BrainstormOutput> {
     info!("Using legacy brainstorm implementation");
 
@@ -266,13 +301,16 @@ async fn execute_legacy(
     let _max_rounds = input.max_rounds.unwrap_or(3);
 
     // Simple legacy implementation: just get Gemini's response
-    let prompt = format!(
-        "Collaborative brainstorm on: {}\n\nClaude's thoughts: {}\n\nRespond with your insights.",
-        input.prompt, claude_thoughts
-    );
+    let prompt = settings.templates.render(
+        "legacy.brainstorm",
+        &json!({"topic": input.prompt, "claude_thoughts": claude_thoughts}),
+    )?;
+
+    let system = settings.personas.resolve("brainstorm", input.persona.as_deref(), input.system.as_deref())?;
 
+    let model = client.resolve_model(None, settings.model("brainstorm")).await?;
     let response = client
-        .generate_content(&prompt, GeminiModel::Pro, None)
+        .generate_content(&prompt.text, system.as_deref(), &model.id, None, &[])
         .await?;
 
     let synthesis = response.text;
@@ -1,5 +1,10 @@
+pub mod agent;
 pub mod analyze;
 pub mod brainstorm;
+pub mod personas;
+pub mod prompts;
 pub mod query;
+pub mod sandbox;
+pub mod settings;
 pub mod summarize;
 pub mod types;
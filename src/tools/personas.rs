@@ -0,0 +1,200 @@
+// Persona and system-instruction presets for tools
+//
+// Each tool has a default persona, sent as the request's system instruction
+// instead of being prepended to the user prompt. Precedence, highest first:
+//   1. `system` argument on the call (literal instruction)
+//   2. `persona` argument on the call (preset name)
+//   3. `[tools.<tool>]` system / persona from the configuration (including
+//      GEMINI_SYSTEM_<TOOL> / GEMINI_PERSONA_<TOOL>)
+//   4. Built-in tool default
+
+use std::collections::HashMap;
+
+use crate::tools::types::InvalidInput;
+
+/// Persona name that sends no system instruction
+pub const NO_PERSONA: &str = "none";
+
+const BUILTIN_PERSONAS: &[(&str, &str)] = &[
+    (
+        "researcher",
+        "You are a research assistant performing semantic search across sources supplied by the user. \
+         Answer only from those sources, say so when they do not contain the answer, and quote them \
+         verbatim when citing.",
+    ),
+    (
+        "analyst",
+        "You are a careful analyst. Be objective and precise. Structure your answer with labelled \
+         fields (e.g. \"Sentiment: positive\") and bullet lists so it can be parsed.",
+    ),
+    (
+        "code-reviewer",
+        "You are a senior software engineer reviewing code. Be specific and actionable, reference \
+         identifiers or lines when pointing out issues, and rate severity honestly.",
+    ),
+    (
+        "summarizer",
+        "You are an expert editor who writes faithful summaries. Never add information that is not \
+         in the source, and preserve key figures, names and conclusions.",
+    ),
+    (
+        "brainstormer",
+        "You are a creative facilitator. Generate diverse, specific and actionable ideas that differ \
+         in approach, not just wording.",
+    ),
+    (
+        "investigator",
+        "You are investigating a local codebase with read-only tools. Gather evidence with the tools \
+         before answering and never guess file contents; read them. Cite evidence as `path:line` \
+         using the absolute paths returned by the tools.",
+    ),
+];
+
+/// Built-in default persona per tool id
+pub const TOOL_DEFAULTS: &[(&str, &str)] = &[
+    ("query", NO_PERSONA),
+    ("search", "researcher"),
+    ("analyze", "analyst"),
+    ("analyze-code", "code-reviewer"),
+    ("summarize", "summarizer"),
+    ("brainstorm", "brainstormer"),
+    ("agent", "investigator"),
+];
+
+#[derive(Debug, Clone)]
+pub struct Personas {
+    presets: HashMap<String, String>,
+    /// Tool id -> system instruction; `None` sends no instruction
+    tool_defaults: HashMap<String, Option<String>>,
+}
+
+impl Default for Personas {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl Personas {
+    pub fn builtin() -> Self {
+        let presets: HashMap<String, String> = BUILTIN_PERSONAS
+            .iter()
+            .map(|(name, text)| (name.to_string(), text.to_string()))
+            .collect();
+
+        let tool_defaults = TOOL_DEFAULTS
+            .iter()
+            .map(|(tool, persona)| (tool.to_string(), presets.get(*persona).cloned()))
+            .collect();
+
+        Self {
+            presets,
+            tool_defaults,
+        }
+    }
+
+    /// Defines or replaces a named preset
+    pub fn define(&mut self, name: &str, system: &str) {
+        self.presets.insert(name.to_string(), system.to_string());
+    }
+
+    pub fn set_tool_persona(&mut self, tool: &str, persona: &str) -> anyhow::Result<()> {
+        let system = self.preset(persona)?;
+        self.set_tool_system(tool, system);
+        Ok(())
+    }
+
+    pub fn set_tool_system(&mut self, tool: &str, system: Option<String>) {
+        self.tool_defaults.insert(tool.to_string(), system);
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.presets.keys().map(String::as_str).collect();
+        names.push(NO_PERSONA);
+        names.sort_unstable();
+        names
+    }
+
+    /// The system instruction for one call of `tool`
+    pub fn resolve(
+        &self,
+        tool: &str,
+        persona: Option<&str>,
+        system: Option<&str>,
+    ) -> anyhow::Result<Option<String>> {
+        if let Some(system) = system {
+            return Ok(Some(system.to_string()).filter(|s| !s.trim().is_empty()));
+        }
+
+        if let Some(persona) = persona {
+            return self.preset(persona);
+        }
+
+        Ok(self.tool_defaults.get(tool).cloned().flatten())
+    }
+
+    fn preset(&self, name: &str) -> anyhow::Result<Option<String>> {
+        if name == NO_PERSONA {
+            return Ok(None);
+        }
+
+        self.presets.get(name).cloned().map(Some).ok_or_else(|| {
+            InvalidInput(format!(
+                "Unknown persona '{}'. Available: {}",
+                name,
+                self.names().join(", ")
+            ))
+            .into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_tool_defaults() {
+        let personas = Personas::builtin();
+        assert_eq!(personas.resolve("query", None, None).unwrap(), None);
+
+        let search = personas.resolve("search", None, None).unwrap().unwrap();
+        assert!(search.contains("semantic search"));
+    }
+
+    #[test]
+    fn test_call_overrides_take_precedence() {
+        let mut personas = Personas::builtin();
+        personas.set_tool_persona("summarize", "analyst").unwrap();
+
+        let deployed = personas.resolve("summarize", None, None).unwrap().unwrap();
+        assert!(deployed.contains("careful analyst"));
+
+        let persona = personas.resolve("summarize", Some("brainstormer"), None).unwrap().unwrap();
+        assert!(persona.contains("creative facilitator"));
+
+        let system = personas
+            .resolve("summarize", Some("brainstormer"), Some("Be terse."))
+            .unwrap();
+        assert_eq!(system, Some("Be terse.".to_string()));
+
+        assert_eq!(personas.resolve("summarize", Some("none"), None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_unknown_persona_lists_available() {
+        let personas = Personas::builtin();
+        let err = personas.resolve("search", Some("pirate"), None).unwrap_err().to_string();
+        assert!(err.contains("pirate"));
+        assert!(err.contains("researcher"));
+    }
+
+    #[test]
+    fn test_define_custom_persona() {
+        let mut personas = Personas::builtin();
+        personas.define("pirate", "Talk like a pirate.");
+        assert_eq!(
+            personas.resolve("query", Some("pirate"), None).unwrap(),
+            Some("Talk like a pirate.".to_string())
+        );
+    }
+}
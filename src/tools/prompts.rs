@@ -0,0 +1,323 @@
+// Prompt templates
+//
+// Tool prompts are Handlebars templates. The defaults in `templates/` are
+// embedded in the binary; a templates directory can replace any of them
+// with a file named `<id>.hbs`. Templates are validated when loaded: each
+// must reference its required variables and render against sample values
+// in strict mode, so a typo fails at startup instead of on a call.
+
+use handlebars::Handlebars;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Directory with `<id>.hbs` files overriding the embedded templates
+pub const DIR_ENV: &str = "GEMINI_TEMPLATES_DIR";
+
+/// Version of overrides without a `{{!-- version: ... --}}` header
+const CUSTOM_VERSION: &str = "custom";
+
+struct Spec {
+    id: &'static str,
+    source: &'static str,
+    /// Variables a template must use, or the prompt would lose the input
+    required: &'static [&'static str],
+    /// Values for every variable the tool passes
+    sample: fn() -> Value,
+}
+
+const SPECS: &[Spec] = &[
+    Spec {
+        id: "search",
+        source: include_str!("../../templates/search.hbs"),
+        required: &["query", "sources"],
+        sample: || json!({"query": "q", "sources": [{"id": "1", "title": "t", "content": "c"}]}),
+    },
+    Spec {
+        id: "analyze.text",
+        source: include_str!("../../templates/analyze.text.hbs"),
+        required: &["content"],
+        sample: || json!({"content": "c", "focus_areas": "f"}),
+    },
+    Spec {
+        id: "analyze.code",
+        source: include_str!("../../templates/analyze.code.hbs"),
+        required: &["content"],
+        sample: || json!({"content": "c", "language": "rust"}),
+    },
+    Spec {
+        id: "analyze.document",
+        source: include_str!("../../templates/analyze.document.hbs"),
+        required: &["content"],
+        sample: || json!({"content": "c"}),
+    },
+    Spec {
+        id: "analyze.sentiment",
+        source: include_str!("../../templates/analyze.sentiment.hbs"),
+        required: &["content"],
+        sample: || json!({"content": "c"}),
+    },
+    Spec {
+        id: "analyze.comparison",
+        source: include_str!("../../templates/analyze.comparison.hbs"),
+        required: &["content", "compare_with"],
+        sample: || json!({"content": "a", "compare_with": "b"}),
+    },
+    Spec {
+        id: "summarize",
+        source: include_str!("../../templates/summarize.hbs"),
+        required: &["content"],
+        sample: || json!({"content": "c", "length": "medium", "format": "paragraph", "focus": "f"}),
+    },
+    Spec {
+        id: "brainstorm",
+        source: include_str!("../../templates/brainstorm.hbs"),
+        required: &["topic", "num_ideas"],
+        sample: || json!({"topic": "t", "num_ideas": 5, "constraints": "c"}),
+    },
+    Spec {
+        id: "agent",
+        source: include_str!("../../templates/agent.hbs"),
+        required: &["task", "roots"],
+        sample: || json!({"task": "t", "roots": ["/r"], "example_root": "/r"}),
+    },
+    Spec {
+        id: "legacy.analyze-code",
+        source: include_str!("../../templates/legacy.analyze-code.hbs"),
+        required: &["code"],
+        sample: || json!({"code": "c", "language": "rust", "focus": "quality"}),
+    },
+    Spec {
+        id: "legacy.analyze-text",
+        source: include_str!("../../templates/legacy.analyze-text.hbs"),
+        required: &["text"],
+        sample: || json!({"text": "t", "focus": "f"}),
+    },
+    Spec {
+        id: "legacy.brainstorm",
+        source: include_str!("../../templates/legacy.brainstorm.hbs"),
+        required: &["topic"],
+        sample: || json!({"topic": "t", "claude_thoughts": "c"}),
+    },
+];
+
+/// Template that produced a prompt, reported in response metadata
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct TemplateRef {
+    pub id: String,
+    pub version: String,
+}
+
+/// A template that fails to render, usually a bad `[templates]` override
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to render template '{id}': {message}")]
+pub struct RenderError {
+    pub id: String,
+    pub message: String,
+}
+
+/// A rendered prompt and the template it came from
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    pub text: String,
+    pub template: TemplateRef,
+}
+
+#[derive(Debug, Clone)]
+pub struct Templates {
+    registry: Handlebars<'static>,
+    versions: HashMap<String, String>,
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl Templates {
+    pub fn builtin() -> Self {
+        let mut registry = Handlebars::new();
+        registry.set_strict_mode(true);
+        // Prompts are plain text, not HTML
+        registry.register_escape_fn(handlebars::no_escape);
+
+        let mut templates = Self {
+            registry,
+            versions: HashMap::new(),
+        };
+        for spec in SPECS {
+            templates
+                .register(spec, spec.source)
+                .expect("embedded templates are valid");
+        }
+        templates
+    }
+
+    /// Embedded templates with the `<id>.hbs` files in `dir` replacing them
+    pub fn load(dir: Option<&Path>) -> anyhow::Result<Self> {
+        let mut templates = Self::builtin();
+        let Some(dir) = dir else {
+            return Ok(templates);
+        };
+
+        let entries = std::fs::read_dir(dir)
+            .map_err(|e| anyhow::anyhow!("Cannot read templates directory {}: {}", dir.display(), e))?;
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "hbs"))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            let id = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+            let spec = SPECS.iter().find(|spec| spec.id == id).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Unknown template '{}' in {}. Available: {}",
+                    id,
+                    dir.display(),
+                    ids().join(", ")
+                )
+            })?;
+            let source = std::fs::read_to_string(&path)
+                .map_err(|e| anyhow::anyhow!("Cannot read template {}: {}", path.display(), e))?;
+            templates
+                .register(spec, &source)
+                .map_err(|e| anyhow::anyhow!("Invalid template {}: {}", path.display(), e))?;
+        }
+
+        Ok(templates)
+    }
+
+    fn register(&mut self, spec: &Spec, source: &str) -> anyhow::Result<()> {
+        for name in spec.required {
+            let used = Regex::new(&format!(r"\{{\{{[^}}]*\b{}\b", regex::escape(name)))
+                .is_ok_and(|pattern| pattern.is_match(source));
+            if !used {
+                anyhow::bail!("missing required variable '{}'", name);
+            }
+        }
+
+        // Register under a scratch name first so a bad override never
+        // replaces a working template
+        let scratch = format!("{}.check", spec.id);
+        self.registry.register_template_string(&scratch, source)?;
+        let rendered = self.registry.render(&scratch, &(spec.sample)());
+        self.registry.unregister_template(&scratch);
+        rendered?;
+
+        self.registry.register_template_string(spec.id, source)?;
+        self.versions.insert(spec.id.to_string(), version_of(source));
+        Ok(())
+    }
+
+    pub fn render(&self, id: &str, data: &Value) -> anyhow::Result<Prompt> {
+        let text = self
+            .registry
+            .render(id, data)
+            .map_err(|e| RenderError { id: id.to_string(), message: e.to_string() })?;
+
+        Ok(Prompt {
+            text: text.trim().to_string(),
+            template: TemplateRef {
+                id: id.to_string(),
+                version: self.versions.get(id).cloned().unwrap_or_default(),
+            },
+        })
+    }
+}
+
+/// Ids of all templates, e.g. for error messages
+pub fn ids() -> Vec<&'static str> {
+    SPECS.iter().map(|spec| spec.id).collect()
+}
+
+/// Version from a leading `{{!-- version: 2 --}}` comment
+fn version_of(source: &str) -> String {
+    static VERSION: OnceLock<Regex> = OnceLock::new();
+    VERSION
+        .get_or_init(|| Regex::new(r"^\s*\{\{!--\s*version:\s*(\S+)\s*--\}\}").expect("valid regex"))
+        .captures(source)
+        .map(|c| c[1].to_string())
+        .unwrap_or_else(|| CUSTOM_VERSION.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn test_builtin_templates_render() {
+        let templates = Templates::builtin();
+
+        let prompt = templates
+            .render(
+                "summarize",
+                &json!({"content": "Rust is fast.", "length": "brief", "format": "bullet_points", "focus": null}),
+            )
+            .unwrap();
+        assert_eq!(prompt.template, TemplateRef { id: "summarize".to_string(), version: "1".to_string() });
+        assert!(prompt.text.starts_with("Summarize the following content:\n\nRust is fast."));
+        assert!(prompt.text.contains("2-3 sentences max"));
+        assert!(prompt.text.contains("bullet points"));
+        assert!(!prompt.text.contains("Focus"));
+
+        let prompt = templates
+            .render(
+                "search",
+                &json!({"query": "q", "sources": [{"id": "a", "title": "A & B", "content": "<x>"}]}),
+            )
+            .unwrap();
+        assert!(prompt.text.contains("--- Source: A & B (ID: a) ---\n<x>\n"));
+    }
+
+    #[test]
+    fn test_override_replaces_embedded_template() {
+        let dir = temp_dir("override");
+        std::fs::write(
+            dir.join("brainstorm.hbs"),
+            "{{!-- version: 2024-06 --}}\n{{num_ideas}} ideas about {{topic}}, please.",
+        )
+        .unwrap();
+
+        let templates = Templates::load(Some(&dir)).unwrap();
+        let prompt = templates
+            .render("brainstorm", &json!({"topic": "tea", "num_ideas": 3, "constraints": null}))
+            .unwrap();
+        assert_eq!(prompt.text, "3 ideas about tea, please.");
+        assert_eq!(prompt.template.version, "2024-06");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_overrides_are_rejected() {
+        let cases = [
+            ("summarize.hbs", "Summarize this.", "content"),
+            ("summarize.hbs", "{{content}} {{contnet}}", "contnet"),
+            ("summarize.hbs", "{{#if content}}{{content}}", "summarize"),
+            ("poem.hbs", "{{content}}", "poem"),
+        ];
+        for (i, (file, source, expected)) in cases.iter().enumerate() {
+            let dir = temp_dir(&format!("invalid-{}", i));
+            std::fs::write(dir.join(file), source).unwrap();
+
+            let err = Templates::load(Some(&dir)).unwrap_err().to_string();
+            assert!(err.contains(expected), "{}: {}", source, err);
+
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        assert!(Templates::load(Some(Path::new("/nonexistent/templates"))).is_err());
+    }
+
+    #[test]
+    fn test_custom_version_without_header() {
+        assert_eq!(version_of("{{content}}"), CUSTOM_VERSION);
+        assert_eq!(version_of("{{!-- version: 3 --}}\n{{content}}"), "3");
+    }
+}
@@ -1,26 +1,33 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::sync::Arc;
 use tracing::{debug, info};
 
-use crate::gemini::{client::GeminiClient, models::GeminiModel, types::GenerationConfig};
-use crate::tools::types::{GenerationParams, ModelPreference, ResponseMetadata, ToolResponse};
+use crate::gemini::{backend::GenerationBackend, types::GenerationConfig};
+use crate::tools::settings::ToolSettings;
+use crate::tools::types::{GenerationParams, InvalidInput, ResponseMetadata, ToolResponse};
 
 // Legacy input/output for backward compatibility
 #[derive(Debug, Deserialize)]
 pub struct QueryInput {
     pub prompt: String,
-    #[serde(default = "default_model")]
-    pub model: String,
+    #[serde(default)]
+    pub model: Option<String>,
     #[serde(default)]
     pub temperature: Option<f32>,
     #[serde(default)]
     pub max_output_tokens: Option<u32>,
+    /// Number of alternative answers to generate (1-8)
+    #[serde(default)]
+    pub candidate_count: Option<u32>,
+    #[serde(default)]
+    pub persona: Option<String>,
+    #[serde(default)]
+    pub system: Option<String>,
 }
 
-fn default_model() -> String {
-    "pro".to_string()
-}
+const MAX_CANDIDATES: u32 = 8;
 
 #[derive(Debug, Serialize)]
 pub struct QueryOutput {
@@ -48,13 +55,37 @@ pub struct SearchInput {
     #[serde(default = "default_include_citations")]
     pub include_citations: bool,
 
-    #[schemars(description = "Model preference")]
+    #[schemars(description = "Model id (e.g. gemini-2.5-flash-lite) or alias (pro, flash)")]
     #[serde(default)]
-    pub model: Option<ModelPreference>,
+    pub model: Option<String>,
 
     #[schemars(description = "Generation parameters")]
     #[serde(default)]
     pub params: Option<GenerationParams>,
+
+    #[schemars(description = "Named persona preset used as the system instruction (e.g. researcher, analyst, code-reviewer, none)")]
+    #[serde(default)]
+    pub persona: Option<String>,
+
+    #[schemars(description = "Literal system instruction; overrides persona")]
+    #[serde(default)]
+    pub system: Option<String>,
+}
+
+impl SearchInput {
+    pub fn new(query: impl Into<String>, sources: Vec<Source>) -> Self {
+        Self {
+            query: query.into(),
+            sources,
+            filters: None,
+            ranking: default_ranking(),
+            include_citations: default_include_citations(),
+            model: None,
+            params: None,
+            persona: None,
+            system: None,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
@@ -69,6 +100,16 @@ pub struct Source {
     pub content: String,
 }
 
+impl Source {
+    pub fn new(id: impl Into<String>, title: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            title: title.into(),
+            content: content.into(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, JsonSchema)]
 pub struct SearchFilters {
     #[schemars(description = "Limit search to specific source IDs")]
@@ -122,38 +163,73 @@ pub struct Citation {
 // Legacy execute function
 pub async fn execute(
     input: QueryInput,
-    client: Arc<GeminiClient>,
+    client: Arc<dyn GenerationBackend>,
+    settings: &ToolSettings,
 ) -> anyhow::Result<QueryOutput> {
-    debug!("Query tool (legacy): model={}, prompt_len={}", input.model, input.prompt.len());
+    debug!("Query tool (legacy): model={:?}, prompt_len={}", input.model, input.prompt.len());
 
-    let model = GeminiModel::from_str(&input.model);
+    let model = client.resolve_model(input.model.as_deref(), settings.model("query")).await?;
 
-    let config = if input.temperature.is_some() || input.max_output_tokens.is_some() {
+    if let Some(count) = input.candidate_count {
+        if !(1..=MAX_CANDIDATES).contains(&count) {
+            anyhow::bail!(InvalidInput(format!("candidate_count must be between 1 and {}", MAX_CANDIDATES)));
+        }
+    }
+
+    let config = if input.temperature.is_some()
+        || input.max_output_tokens.is_some()
+        || input.candidate_count.is_some()
+    {
         Some(GenerationConfig {
             temperature: input.temperature,
             max_output_tokens: input.max_output_tokens,
-            top_p: None,
-            top_k: None,
+            candidate_count: input.candidate_count,
+            ..Default::default()
         })
     } else {
         None
     };
 
-    let response = client.generate_content(&input.prompt, model, config).await?;
+    let system = settings.personas.resolve("query", input.persona.as_deref(), input.system.as_deref())?;
+
+    let response = client
+        .generate_content(&input.prompt, system.as_deref(), &model.id, config, &[])
+        .await?;
 
     if response.text.trim().is_empty() {
         anyhow::bail!("Empty response from Gemini API");
     }
 
-    debug!("Query tool (legacy): response_len={}", response.text.len());
+    debug!(
+        "Query tool (legacy): response_len={}, candidates={}",
+        response.text.len(),
+        response.candidates.len()
+    );
+
+    let text = if response.candidates.len() > 1 {
+        format_variants(&response.candidates)
+    } else {
+        response.text
+    };
 
-    Ok(QueryOutput { text: response.text })
+    Ok(QueryOutput { text })
+}
+
+/// Numbered sections, one per alternative answer
+fn format_variants(candidates: &[String]) -> String {
+    candidates
+        .iter()
+        .enumerate()
+        .map(|(i, text)| format!("## Variant {}\n\n{}", i + 1, text.trim()))
+        .collect::<Vec<_>>()
+        .join("\n\n")
 }
 
 // V2 multi-source search implementation
 pub async fn execute_v2(
     input: SearchInput,
-    client: Arc<GeminiClient>,
+    client: Arc<dyn GenerationBackend>,
+    settings: &ToolSettings,
 ) -> anyhow::Result<ToolResponse<SearchResult>> {
     info!(
         "Search v2: query='{}', sources={}, include_citations={}",
@@ -164,11 +240,11 @@ pub async fn execute_v2(
 
     // Validate input
     if input.query.trim().is_empty() {
-        anyhow::bail!("Query cannot be empty");
+        anyhow::bail!(InvalidInput("Query cannot be empty".to_string()));
     }
 
     if input.sources.is_empty() {
-        anyhow::bail!("At least one source is required");
+        anyhow::bail!(InvalidInput("At least one source is required".to_string()));
     }
 
     // Filter sources if source_ids filter is provided
@@ -181,51 +257,38 @@ pub async fn execute_v2(
     };
 
     if filtered_sources.is_empty() {
-        anyhow::bail!("No sources match the filter criteria");
+        anyhow::bail!(InvalidInput("No sources match the filter criteria".to_string()));
     }
 
     debug!("Filtered to {} sources", filtered_sources.len());
 
     // Build search prompt with all sources
-    let mut prompt = format!(
-        "You are performing a semantic search across multiple sources.\n\n\
-         Query: {}\n\n\
-         Sources:\n\n",
-        input.query
-    );
+    let system = settings.personas.resolve("search", input.persona.as_deref(), input.system.as_deref())?;
 
-    for source in &filtered_sources {
-        prompt.push_str(&format!(
-            "--- Source: {} (ID: {}) ---\n{}\n\n",
-            source.title, source.id, source.content
-        ));
-    }
+    let sources: Vec<_> = filtered_sources
+        .iter()
+        .map(|s| json!({"id": s.id, "title": s.title, "content": s.content}))
+        .collect();
+    let prompt = settings
+        .templates
+        .render("search", &json!({"query": input.query, "sources": sources}))?;
 
-    prompt.push_str(
-        "Based on the query, provide:\n\
-         1. A direct answer to the query\n\
-         2. For each relevant source, provide:\n\
-            - Source ID and title\n\
-            - A brief excerpt showing relevance\n\
-            - Relevance score (0.0-1.0)\n\
-         3. If applicable, include direct quotes as citations\n\n\
-         Format your response clearly with sections for Answer, Results, and Citations."
-    );
+    let model = client.resolve_model(input.model.as_deref(), settings.model("search")).await?;
 
-    let model = match input.model {
-        Some(ModelPreference::Flash) => GeminiModel::Flash,
-        Some(ModelPreference::Pro) | None => GeminiModel::Pro,
-    };
-
-    let config = GenerationConfig {
-        temperature: input.params.as_ref().and_then(|p| p.temperature).or(Some(0.3)),
-        max_output_tokens: input.params.as_ref().and_then(|p| p.max_tokens).or(Some(2048)),
-        top_p: input.params.as_ref().and_then(|p| p.top_p),
-        top_k: input.params.as_ref().and_then(|p| p.top_k),
-    };
+    let config = GenerationParams::config(
+        input.params.as_ref(),
+        settings.generation("search"),
+    )?;
 
     let response = client
-        .generate_content(&prompt, model, Some(config))
+        .generate_content_with_continuation(
+            &prompt.text,
+            system.as_deref(),
+            &model.id,
+            Some(config),
+            GenerationParams::safety_overrides(input.params.as_ref()),
+            GenerationParams::max_continuations(input.params.as_ref()),
+        )
         .await?;
 
     debug!("Search response: {} chars", response.text.len());
@@ -268,9 +331,13 @@ pub async fn execute_v2(
         citations,
     };
 
-    let metadata = ResponseMetadata::with_usage(model.as_str(), &response.usage);
+    let metadata = ResponseMetadata::for_response(&response).with_template(prompt.template);
 
-    Ok(ToolResponse { result, metadata })
+    Ok(ToolResponse {
+        result,
+        thoughts: response.thoughts,
+        metadata,
+    })
 }
 
 fn extract_answer(text: &str) -> String {
@@ -359,11 +426,23 @@ mod tests {
     fn test_query_input_defaults() {
         let json = r#"{"prompt": "test"}"#;
         let input: QueryInput = serde_json::from_str(json).unwrap();
-        assert_eq!(input.model, "pro");
+        assert_eq!(input.model, None);
         assert_eq!(input.temperature, None);
         assert_eq!(input.max_output_tokens, None);
     }
 
+    #[tokio::test]
+    async fn test_query_uses_configured_model() {
+        let mut config = crate::config::Config::builtin();
+        config.tools.defaults.get_mut("query").unwrap().model = Some("gemini-2.5-flash-lite".to_string());
+        let settings = ToolSettings::from_config(&config).unwrap();
+        let mock = Arc::new(crate::gemini::MockBackend::new().with_reply("Hi"));
+
+        let input: QueryInput = serde_json::from_str(r#"{"prompt": "Hello"}"#).unwrap();
+        execute(input, mock.clone(), &settings).await.unwrap();
+        assert_eq!(mock.requests()[0].model, "gemini-2.5-flash-lite");
+    }
+
     #[test]
     fn test_search_input_deserialize() {
         let json = r#"{
@@ -379,6 +458,12 @@ mod tests {
         assert!(input.include_citations);
     }
 
+    #[test]
+    fn test_format_variants() {
+        let text = format_variants(&["First.\n".to_string(), "Second.".to_string()]);
+        assert_eq!(text, "## Variant 1\n\nFirst.\n\n## Variant 2\n\nSecond.");
+    }
+
     #[test]
     fn test_extract_answer() {
         let text = "Answer: This is the answer\nMore text here";
@@ -388,7 +473,7 @@ mod tests {
 
     #[test]
     fn test_extract_results() {
-        let sources = vec![
+        let sources = [
             Source {
                 id: "1".to_string(),
                 title: "Document A".to_string(),
@@ -0,0 +1,496 @@
+// Read-only filesystem capabilities for the agent tool, confined to allowed roots
+//
+// Every path is canonicalized before use, so `..` segments and symlinks that
+// point outside the allowed roots are rejected.
+
+use regex::RegexBuilder;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::gemini::functions::FunctionRegistry;
+use crate::gemini::types::FunctionDeclaration;
+
+/// Environment variable listing allowed roots, separated like `PATH`
+pub const ROOTS_ENV: &str = "GEMINI_AGENT_ROOTS";
+
+const MAX_DIR_ENTRIES: usize = 500;
+const MAX_READ_BYTES: u64 = 256 * 1024;
+const DEFAULT_READ_LINES: usize = 400;
+const MAX_GREP_MATCHES: usize = 100;
+const MAX_GREP_FILE_BYTES: u64 = 1024 * 1024;
+const MAX_GIT_LOG_ENTRIES: u32 = 50;
+const GIT_TIMEOUT: Duration = Duration::from_secs(10);
+const SKIPPED_DIRS: &[&str] = &[".git", "node_modules", "target"];
+
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    roots: Vec<PathBuf>,
+}
+
+impl Sandbox {
+    pub fn new(roots: &[PathBuf]) -> anyhow::Result<Self> {
+        if roots.is_empty() {
+            anyhow::bail!("At least one allowed root is required");
+        }
+
+        let roots = roots
+            .iter()
+            .map(|r| {
+                fs::canonicalize(r)
+                    .map_err(|e| anyhow::anyhow!("Invalid allowed root {}: {}", r.display(), e))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self { roots })
+    }
+
+    /// Roots from `GEMINI_AGENT_ROOTS`, or the working directory when unset
+    pub fn from_env() -> anyhow::Result<Self> {
+        match std::env::var_os(ROOTS_ENV) {
+            Some(value) if !value.is_empty() => {
+                Self::new(&std::env::split_paths(&value).collect::<Vec<_>>())
+            }
+            _ => Self::new(&[std::env::current_dir()?]),
+        }
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Narrows the sandbox to `roots`, each of which must already be allowed
+    pub fn restrict(&self, roots: &[String]) -> Result<Self, String> {
+        let roots = roots
+            .iter()
+            .map(|r| self.resolve(r))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { roots })
+    }
+
+    /// Resolves `path` (absolute, or relative to the first root) to a
+    /// canonical path inside an allowed root.
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, String> {
+        let candidate = Path::new(path);
+        let joined = if candidate.is_absolute() {
+            candidate.to_path_buf()
+        } else {
+            self.roots[0].join(candidate)
+        };
+
+        let canonical =
+            fs::canonicalize(&joined).map_err(|e| format!("Cannot access {}: {}", path, e))?;
+
+        if self.roots.iter().any(|root| canonical.starts_with(root)) {
+            Ok(canonical)
+        } else {
+            Err(format!("Path is outside the allowed roots: {}", path))
+        }
+    }
+
+    pub fn list_dir(&self, path: &str) -> Result<serde_json::Value, String> {
+        let dir = self.resolve(path)?;
+        let mut entries = Vec::new();
+        let mut truncated = false;
+
+        let read = fs::read_dir(&dir).map_err(|e| format!("Cannot list {}: {}", path, e))?;
+        for entry in read.flatten() {
+            if entries.len() >= MAX_DIR_ENTRIES {
+                truncated = true;
+                break;
+            }
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let kind = if file_type.is_dir() {
+                "dir"
+            } else if file_type.is_symlink() {
+                "symlink"
+            } else {
+                "file"
+            };
+            entries.push(DirEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                kind,
+                size: entry.metadata().ok().filter(|m| m.is_file()).map(|m| m.len()),
+            });
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(serde_json::json!({
+            "path": dir.display().to_string(),
+            "entries": entries,
+            "truncated": truncated,
+        }))
+    }
+
+    /// Reads a line range of a text file; lines are numbered for citation
+    pub fn read_file(
+        &self,
+        path: &str,
+        start_line: Option<usize>,
+        max_lines: Option<usize>,
+    ) -> Result<serde_json::Value, String> {
+        let file = self.resolve(path)?;
+        let metadata = fs::metadata(&file).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+        if !metadata.is_file() {
+            return Err(format!("Not a file: {}", path));
+        }
+        if metadata.len() > MAX_READ_BYTES * 16 {
+            return Err(format!("File too large to read: {} ({} bytes)", path, metadata.len()));
+        }
+
+        let bytes = fs::read(&file).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+        let text = String::from_utf8(bytes).map_err(|_| format!("Not a UTF-8 text file: {}", path))?;
+
+        let lines: Vec<&str> = text.lines().collect();
+        let start = start_line.unwrap_or(1).max(1);
+        let limit = max_lines.unwrap_or(DEFAULT_READ_LINES).clamp(1, DEFAULT_READ_LINES * 5);
+
+        let mut content = String::new();
+        let mut end = start.saturating_sub(1);
+        for (i, line) in lines.iter().enumerate().skip(start - 1).take(limit) {
+            if content.len() + line.len() > MAX_READ_BYTES as usize {
+                break;
+            }
+            content.push_str(&format!("{:>5}| {}\n", i + 1, line));
+            end = i + 1;
+        }
+
+        Ok(serde_json::json!({
+            "path": file.display().to_string(),
+            "start_line": start,
+            "end_line": end,
+            "total_lines": lines.len(),
+            "content": content,
+        }))
+    }
+
+    /// Searches files under `path` for a regex, skipping VCS and build dirs
+    pub fn grep(
+        &self,
+        pattern: &str,
+        path: Option<&str>,
+        case_insensitive: bool,
+    ) -> Result<serde_json::Value, String> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(case_insensitive)
+            .size_limit(1 << 20)
+            .build()
+            .map_err(|e| format!("Invalid pattern: {}", e))?;
+
+        let start = match path {
+            Some(p) => self.resolve(p)?,
+            None => self.roots[0].clone(),
+        };
+
+        let mut matches = Vec::new();
+        let mut stack = vec![start];
+        let mut truncated = false;
+
+        'walk: while let Some(current) = stack.pop() {
+            let Ok(metadata) = fs::symlink_metadata(&current) else {
+                continue;
+            };
+
+            if metadata.is_dir() {
+                let Ok(read) = fs::read_dir(&current) else {
+                    continue;
+                };
+                let mut children: Vec<PathBuf> = read
+                    .flatten()
+                    .filter(|e| {
+                        let name = e.file_name();
+                        !SKIPPED_DIRS.iter().any(|s| name == *s)
+                    })
+                    .map(|e| e.path())
+                    .collect();
+                // Reverse so the stack yields entries in name order
+                children.sort_by(|a, b| b.cmp(a));
+                stack.extend(children);
+                continue;
+            }
+
+            if !metadata.is_file() || metadata.len() > MAX_GREP_FILE_BYTES {
+                continue;
+            }
+            let Ok(text) = fs::read_to_string(&current) else {
+                continue;
+            };
+
+            for (i, line) in text.lines().enumerate() {
+                if regex.is_match(line) {
+                    if matches.len() >= MAX_GREP_MATCHES {
+                        truncated = true;
+                        break 'walk;
+                    }
+                    matches.push(GrepMatch {
+                        path: current.display().to_string(),
+                        line: i + 1,
+                        text: line.chars().take(300).collect(),
+                    });
+                }
+            }
+        }
+
+        Ok(serde_json::json!({
+            "pattern": pattern,
+            "matches": matches,
+            "truncated": truncated,
+        }))
+    }
+
+    /// Recent commits touching `path` (or the whole repository)
+    pub async fn git_log(
+        &self,
+        path: Option<&str>,
+        max_count: Option<u32>,
+    ) -> Result<serde_json::Value, String> {
+        let target = match path {
+            Some(p) => self.resolve(p)?,
+            None => self.roots[0].clone(),
+        };
+        let dir = if target.is_dir() {
+            target.clone()
+        } else {
+            target.parent().map(Path::to_path_buf).unwrap_or_else(|| target.clone())
+        };
+        let count = max_count.unwrap_or(20).clamp(1, MAX_GIT_LOG_ENTRIES);
+
+        let mut command = tokio::process::Command::new("git");
+        command
+            .arg("-C")
+            .arg(&dir)
+            .args(["-c", "log.showSignature=false", "log", "--no-color"])
+            .arg(format!("--max-count={}", count))
+            .args(["--date=short", "--format=%h%x09%ad%x09%an%x09%s", "--"])
+            .arg(&target)
+            .kill_on_drop(true);
+
+        let output = tokio::time::timeout(GIT_TIMEOUT, command.output())
+            .await
+            .map_err(|_| "git log timed out".to_string())?
+            .map_err(|e| format!("Cannot run git: {}", e))?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "git log failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let commits: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(4, '\t');
+                Some(serde_json::json!({
+                    "hash": fields.next()?,
+                    "date": fields.next()?,
+                    "author": fields.next()?,
+                    "subject": fields.next().unwrap_or(""),
+                }))
+            })
+            .collect();
+
+        Ok(serde_json::json!({
+            "path": target.display().to_string(),
+            "commits": commits,
+        }))
+    }
+
+    /// Registers the sandboxed capabilities as Gemini functions
+    pub fn function_registry(&self) -> FunctionRegistry {
+        let mut registry = FunctionRegistry::new();
+
+        let sandbox = self.clone();
+        registry.register(
+            declaration(
+                "list_directory",
+                "List the entries of a directory.",
+                serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {"type": "string", "description": "Directory path, absolute or relative to the first allowed root"}
+                    },
+                    "required": ["path"]
+                }),
+            ),
+            move |args| {
+                let sandbox = sandbox.clone();
+                async move {
+                    let path = string_arg(&args, "path")?;
+                    blocking(move || sandbox.list_dir(&path)).await
+                }
+            },
+        );
+
+        let sandbox = self.clone();
+        registry.register(
+            declaration(
+                "read_file",
+                "Read a range of lines from a UTF-8 text file. Lines are prefixed with their line number.",
+                serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {"type": "string"},
+                        "start_line": {"type": "integer", "description": "First line to read (1-based)"},
+                        "max_lines": {"type": "integer", "description": "Number of lines to read (default 400)"}
+                    },
+                    "required": ["path"]
+                }),
+            ),
+            move |args| {
+                let sandbox = sandbox.clone();
+                async move {
+                    let path = string_arg(&args, "path")?;
+                    let start = args.get("start_line").and_then(|v| v.as_u64()).map(|v| v as usize);
+                    let max = args.get("max_lines").and_then(|v| v.as_u64()).map(|v| v as usize);
+                    blocking(move || sandbox.read_file(&path, start, max)).await
+                }
+            },
+        );
+
+        let sandbox = self.clone();
+        registry.register(
+            declaration(
+                "grep",
+                "Search text files recursively for lines matching a regular expression. Skips .git, node_modules and target directories.",
+                serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "pattern": {"type": "string", "description": "Rust regex syntax"},
+                        "path": {"type": "string", "description": "File or directory to search (default: first allowed root)"},
+                        "case_insensitive": {"type": "boolean"}
+                    },
+                    "required": ["pattern"]
+                }),
+            ),
+            move |args| {
+                let sandbox = sandbox.clone();
+                async move {
+                    let pattern = string_arg(&args, "pattern")?;
+                    let path = args.get("path").and_then(|v| v.as_str()).map(str::to_string);
+                    let ci = args.get("case_insensitive").and_then(|v| v.as_bool()).unwrap_or(false);
+                    blocking(move || sandbox.grep(&pattern, path.as_deref(), ci)).await
+                }
+            },
+        );
+
+        let sandbox = self.clone();
+        registry.register(
+            declaration(
+                "git_log",
+                "Show recent git commits (hash, date, author, subject) touching a file or directory.",
+                serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {"type": "string"},
+                        "max_count": {"type": "integer", "description": "Number of commits (default 20, max 50)"}
+                    }
+                }),
+            ),
+            move |args| {
+                let sandbox = sandbox.clone();
+                async move {
+                    let path = args.get("path").and_then(|v| v.as_str());
+                    let count = args.get("max_count").and_then(|v| v.as_u64()).map(|v| v as u32);
+                    sandbox.git_log(path, count).await
+                }
+            },
+        );
+
+        registry
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DirEntry {
+    name: String,
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct GrepMatch {
+    path: String,
+    line: usize,
+    text: String,
+}
+
+fn declaration(name: &str, description: &str, parameters: serde_json::Value) -> FunctionDeclaration {
+    FunctionDeclaration {
+        name: name.to_string(),
+        description: description.to_string(),
+        parameters: Some(parameters),
+    }
+}
+
+fn string_arg(args: &serde_json::Value, name: &str) -> Result<String, String> {
+    args.get(name)
+        .and_then(|v| v.as_str())
+        .map(str::to_string)
+        .ok_or_else(|| format!("Missing required argument: {}", name))
+}
+
+async fn blocking<F>(f: F) -> Result<serde_json::Value, String>
+where
+    F: FnOnce() -> Result<serde_json::Value, String> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    fn fixture() -> (PathBuf, Sandbox) {
+        let dir = temp_dir("sandbox");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/lib.rs"), "fn main() {}\n// TODO: handle errors\n").unwrap();
+        fs::write(dir.join("README.md"), "# Project\n").unwrap();
+        let sandbox = Sandbox::new(&[dir.join("src")]).unwrap();
+        (dir, sandbox)
+    }
+
+    #[test]
+    fn test_resolve_rejects_escapes() {
+        let (dir, sandbox) = fixture();
+        assert!(sandbox.resolve("lib.rs").is_ok());
+        assert!(sandbox.resolve("../README.md").is_err());
+        assert!(sandbox.resolve(dir.join("README.md").to_str().unwrap()).is_err());
+        assert!(sandbox.resolve("/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_read_file_numbers_lines() {
+        let (_dir, sandbox) = fixture();
+        let result = sandbox.read_file("lib.rs", Some(2), None).unwrap();
+        assert_eq!(result["start_line"], 2);
+        assert_eq!(result["end_line"], 2);
+        assert_eq!(result["total_lines"], 2);
+        assert!(result["content"].as_str().unwrap().contains("2| // TODO"));
+    }
+
+    #[test]
+    fn test_grep_and_list_dir() {
+        let (_dir, sandbox) = fixture();
+        let result = sandbox.grep("todo", None, true).unwrap();
+        assert_eq!(result["matches"].as_array().unwrap().len(), 1);
+        assert_eq!(result["matches"][0]["line"], 2);
+
+        let listing = sandbox.list_dir(".").unwrap();
+        assert_eq!(listing["entries"][0]["name"], "lib.rs");
+    }
+
+    #[test]
+    fn test_restrict_requires_allowed_roots() {
+        let (dir, sandbox) = fixture();
+        assert!(sandbox.restrict(&[dir.display().to_string()]).is_err());
+        assert!(sandbox.restrict(&[".".to_string()]).is_ok());
+    }
+}
@@ -0,0 +1,137 @@
+// Per-tool settings
+//
+// Everything a tool call takes from the configuration: persona presets,
+// prompt templates, each tool's default model and generation settings, and
+// which MCP tools are exposed.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::config::{Config, ToolConfig};
+use crate::gemini::models::PRO_ALIAS;
+use crate::gemini::types::GenerationConfig;
+use crate::tools::personas::Personas;
+use crate::tools::prompts::Templates;
+
+/// MCP tools that can be enabled or disabled; gemini-status is always on
+pub const TOOL_NAMES: &[&str] = &[
+    "gemini-query",
+    "gemini-analyze-code",
+    "gemini-analyze-text",
+    "gemini-summarize",
+    "gemini-brainstorm",
+    "gemini-search-v2",
+    "gemini-analyze-v2",
+    "gemini-summarize-v2",
+    "gemini-brainstorm-v2",
+    "gemini-agent",
+];
+
+#[derive(Debug, Clone)]
+pub struct ToolSettings {
+    /// Default system instruction per tool
+    pub personas: Personas,
+    /// Prompt templates, embedded or overridden
+    pub templates: Templates,
+    defaults: BTreeMap<String, ToolConfig>,
+    enabled: Option<Vec<String>>,
+}
+
+impl Default for ToolSettings {
+    fn default() -> Self {
+        Self::from_config(&Config::builtin()).expect("built-in config is valid")
+    }
+}
+
+impl ToolSettings {
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let mut personas = Personas::builtin();
+        for (tool, defaults) in &config.tools.defaults {
+            if let Some(persona) = &defaults.persona {
+                personas
+                    .set_tool_persona(tool, persona)
+                    .map_err(|e| anyhow::anyhow!("Invalid tools.{}.persona: {}", tool, e))?;
+            }
+            if let Some(system) = &defaults.system {
+                personas.set_tool_system(tool, Some(system.clone()));
+            }
+        }
+
+        let templates = Templates::load(config.templates.dir.as_deref().map(Path::new))?;
+
+        Ok(Self {
+            personas,
+            templates,
+            defaults: config.tools.defaults.clone(),
+            enabled: config.tools.enabled.clone(),
+        })
+    }
+
+    /// Model id or alias used when a call of `tool` names none
+    pub fn model(&self, tool: &str) -> &str {
+        self.defaults
+            .get(tool)
+            .and_then(|d| d.model.as_deref())
+            .unwrap_or(PRO_ALIAS)
+    }
+
+    /// Configured temperature and max tokens for `tool`
+    pub fn generation(&self, tool: &str) -> GenerationConfig {
+        let defaults = self.defaults.get(tool);
+        GenerationConfig {
+            temperature: defaults.and_then(|d| d.temperature).map(|t| t as f32),
+            max_output_tokens: defaults.and_then(|d| d.max_tokens),
+            ..Default::default()
+        }
+    }
+
+    /// MCP tools currently exposed, in listing order
+    pub fn enabled_names(&self) -> Vec<&'static str> {
+        TOOL_NAMES.iter().copied().filter(|name| self.is_enabled(name)).collect()
+    }
+
+    /// Whether the MCP tool `name` is exposed
+    pub fn is_enabled(&self, name: &str) -> bool {
+        name == "gemini-status"
+            || self
+                .enabled
+                .as_ref()
+                .is_none_or(|enabled| enabled.iter().any(|n| n == name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_defaults() {
+        let settings = ToolSettings::default();
+        assert_eq!(settings.model("summarize"), "flash");
+        assert_eq!(settings.model("brainstorm"), "pro");
+        assert_eq!(settings.generation("brainstorm").temperature, Some(0.9));
+        assert_eq!(settings.generation("search").max_output_tokens, Some(2048));
+        assert_eq!(settings.generation("summarize").max_output_tokens, None);
+        assert!(settings.is_enabled("gemini-agent"));
+    }
+
+    #[test]
+    fn test_configured_tools() {
+        let mut config = Config::builtin();
+        config.tools.enabled = Some(vec!["gemini-search-v2".to_string()]);
+        let summarize = config.tools.defaults.get_mut("summarize").unwrap();
+        summarize.persona = Some("analyst".to_string());
+        summarize.model = Some("gemini-2.5-flash-lite".to_string());
+
+        let settings = ToolSettings::from_config(&config).unwrap();
+        assert_eq!(settings.model("summarize"), "gemini-2.5-flash-lite");
+        let system = settings.personas.resolve("summarize", None, None).unwrap().unwrap();
+        assert!(system.contains("careful analyst"));
+        assert!(settings.is_enabled("gemini-search-v2"));
+        assert!(settings.is_enabled("gemini-status"));
+        assert!(!settings.is_enabled("gemini-agent"));
+
+        config.tools.defaults.get_mut("summarize").unwrap().persona = Some("pirate".to_string());
+        assert!(ToolSettings::from_config(&config).is_err());
+    }
+}
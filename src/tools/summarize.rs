@@ -1,10 +1,12 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::sync::Arc;
 use tracing::{debug, info};
 
-use crate::gemini::{client::GeminiClient, models::GeminiModel, types::GenerationConfig};
-use crate::tools::types::{GenerationParams, ModelPreference, ResponseMetadata, ToolResponse};
+use crate::gemini::backend::GenerationBackend;
+use crate::tools::settings::ToolSettings;
+use crate::tools::types::{GenerationParams, InvalidInput, ResponseMetadata, ToolResponse};
 
 #[derive(Debug, Deserialize, JsonSchema)]
 pub struct SummarizeInput {
@@ -23,16 +25,39 @@ pub struct SummarizeInput {
     #[serde(default)]
     pub focus: Option<String>,
 
-    #[schemars(description = "Model preference")]
+    #[schemars(description = "Model id (e.g. gemini-2.5-flash-lite) or alias (pro, flash)")]
     #[serde(default)]
-    pub model: Option<ModelPreference>,
+    pub model: Option<String>,
 
     #[schemars(description = "Generation parameters")]
     #[serde(default)]
     pub params: Option<GenerationParams>,
+
+    #[schemars(description = "Named persona preset used as the system instruction (e.g. researcher, analyst, code-reviewer, none)")]
+    #[serde(default)]
+    pub persona: Option<String>,
+
+    #[schemars(description = "Literal system instruction; overrides persona")]
+    #[serde(default)]
+    pub system: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize, JsonSchema)]
+impl SummarizeInput {
+    pub fn new(content: impl Into<String>) -> Self {
+        Self {
+            content: content.into(),
+            length: default_length(),
+            format: default_format(),
+            focus: None,
+            model: None,
+            params: None,
+            persona: None,
+            system: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum SummaryLength {
     Brief,
@@ -44,7 +69,7 @@ fn default_length() -> SummaryLength {
     SummaryLength::Medium
 }
 
-#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum SummaryFormat {
     Paragraph,
@@ -72,7 +97,8 @@ pub struct SummarizeOutput {
 
 pub async fn execute(
     input: SummarizeInput,
-    client: Arc<GeminiClient>,
+    client: Arc<dyn GenerationBackend>,
+    settings: &ToolSettings,
 ) -> anyhow::Result<SummarizeOutput> {
     info!(
         "Summarize tool: length={:?}, format={:?}, content_len={}",
@@ -83,14 +109,10 @@ pub async fn execute(
 
     // Validate input
     if input.content.trim().is_empty() {
-        anyhow::bail!("Content cannot be empty");
-    }
-
-    if input.content.len() > 1_000_000 {
-        anyhow::bail!("Content too large (max 1M characters)");
+        anyhow::bail!(InvalidInput("Content cannot be empty".to_string()));
     }
 
-    let response = execute_v2(input, client).await?;
+    let response = execute_v2(input, client, settings).await?;
 
     // Convert to legacy format
     Ok(SummarizeOutput {
@@ -100,7 +122,8 @@ pub async fn execute(
 
 pub async fn execute_v2(
     input: SummarizeInput,
-    client: Arc<GeminiClient>,
+    client: Arc<dyn GenerationBackend>,
+    settings: &ToolSettings,
 ) -> anyhow::Result<ToolResponse<SummaryResult>> {
     debug!(
         "Summarize v2: length={:?}, format={:?}, content_len={}",
@@ -109,53 +132,40 @@ pub async fn execute_v2(
         input.content.len()
     );
 
-    let (detail_instruction, max_tokens) = match input.length {
-        SummaryLength::Brief => (
-            "Provide a very brief, concise summary (2-3 sentences max).",
-            256,
-        ),
-        SummaryLength::Detailed => (
-            "Provide a comprehensive, detailed summary covering all key points and nuances.",
-            2048,
-        ),
-        SummaryLength::Medium => (
-            "Provide a balanced summary with key points and main themes.",
-            1024,
-        ),
+    let max_tokens = match input.length {
+        SummaryLength::Brief => 256,
+        SummaryLength::Medium => 1024,
+        SummaryLength::Detailed => 2048,
     };
 
-    let format_instruction = match input.format {
-        SummaryFormat::BulletPoints => "\n\nFormat the summary as bullet points.",
-        SummaryFormat::Executive => "\n\nFormat as an executive summary with clear sections.",
-        SummaryFormat::KeyPoints => "\n\nExtract and list only the key takeaways.",
-        SummaryFormat::Paragraph => "\n\nFormat the summary as coherent paragraphs.",
-    };
+    let system = settings.personas.resolve("summarize", input.persona.as_deref(), input.system.as_deref())?;
 
-    let focus_instruction = input
-        .focus
-        .as_ref()
-        .map(|f| format!("\n\nFocus specifically on: {}", f))
-        .unwrap_or_default();
+    let prompt = settings.templates.render(
+        "summarize",
+        &json!({
+            "content": input.content,
+            "length": input.length,
+            "format": input.format,
+            "focus": input.focus,
+        }),
+    )?;
 
-    let prompt = format!(
-        "Summarize the following content:\n\n{}\n\n{}{}{}",
-        input.content, detail_instruction, format_instruction, focus_instruction
-    );
+    let model = client.resolve_model(input.model.as_deref(), settings.model("summarize")).await?;
 
-    let model = match input.model {
-        Some(ModelPreference::Pro) => GeminiModel::Pro,
-        Some(ModelPreference::Flash) | None => GeminiModel::Flash,
-    };
-
-    let config = GenerationConfig {
-        temperature: input.params.as_ref().and_then(|p| p.temperature).or(Some(0.4)),
-        max_output_tokens: input.params.as_ref().and_then(|p| p.max_tokens).or(Some(max_tokens)),
-        top_p: input.params.as_ref().and_then(|p| p.top_p),
-        top_k: input.params.as_ref().and_then(|p| p.top_k),
-    };
+    // A configured max_tokens replaces the per-length default
+    let mut defaults = settings.generation("summarize");
+    defaults.max_output_tokens.get_or_insert(max_tokens);
+    let config = GenerationParams::config(input.params.as_ref(), defaults)?;
 
     let response = client
-        .generate_content(&prompt, model, Some(config))
+        .generate_content_with_continuation(
+            &prompt.text,
+            system.as_deref(),
+            &model.id,
+            Some(config),
+            GenerationParams::safety_overrides(input.params.as_ref()),
+            GenerationParams::max_continuations(input.params.as_ref()),
+        )
         .await?;
 
     debug!("Summary generated: {} chars", response.text.len());
@@ -166,15 +176,19 @@ pub async fn execute_v2(
     // Count words
     let word_count = response.text.split_whitespace().count();
 
+    let metadata = ResponseMetadata::for_response(&response).with_template(prompt.template);
+
     let result = SummaryResult {
         summary: response.text,
         word_count,
         key_topics,
     };
 
-    let metadata = ResponseMetadata::with_usage(model.as_str(), &response.usage);
-
-    Ok(ToolResponse { result, metadata })
+    Ok(ToolResponse {
+        result,
+        thoughts: response.thoughts,
+        metadata,
+    })
 }
 
 fn extract_key_topics(text: &str) -> Vec<String> {
@@ -205,7 +219,7 @@ fn extract_key_topics(text: &str) -> Vec<String> {
         .filter(|(word, count)| *count >= 2 && !stop_words.contains(&word.as_str()))
         .collect();
 
-    topics.sort_by(|a, b| b.1.cmp(&a.1));
+    topics.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
 
     topics.into_iter().take(5).map(|(word, _)| word).collect()
 }
@@ -1,58 +1,149 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::ops::RangeInclusive;
+
+use crate::gemini::functions::FunctionCallRecord;
+use crate::gemini::client::MAX_CONTINUATIONS;
+use crate::tools::prompts::TemplateRef;
+use crate::gemini::types::{
+    GenerationConfig, GenerationResponse, SafetyRating, SafetySetting, ThinkingConfig, UsageMetadata,
+};
+
+/// Tool arguments that fail validation, as opposed to failures of the
+/// model, the configuration or the server
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct InvalidInput(pub String);
 
 /// Shared JSON response wrapper with metadata
 #[derive(Debug, Serialize, JsonSchema)]
 pub struct ToolResponse<T> {
     pub result: T,
+    /// Summary of the model's reasoning, when `params.include_thoughts` was set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thoughts: Option<String>,
     pub metadata: ResponseMetadata,
 }
 
 /// Response metadata including model info and token usage
 #[derive(Debug, Serialize, JsonSchema)]
 pub struct ResponseMetadata {
+    /// Model that produced the response
     pub model_used: String,
+    /// True when the requested model failed and a fallback model answered
+    pub fallback: bool,
+    /// Model the call asked for, when a fallback happened
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requested_model: Option<String>,
     pub prompt_tokens: u32,
     pub response_tokens: u32,
     pub total_tokens: u32,
+    /// Tokens spent thinking, included in `total_tokens`
+    #[serde(skip_serializing_if = "is_zero")]
+    pub thoughts_tokens: u32,
+    /// Prompt tokens served from the context cache
+    #[serde(skip_serializing_if = "is_zero")]
+    pub cached_tokens: u32,
+    /// Function calls made by the model, for agentic tools
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace: Option<Vec<FunctionCallRecord>>,
+    /// Prompt and response safety ratings, when the API returned any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub safety_ratings: Option<Vec<SafetyRating>>,
+    /// Why generation stopped, e.g. STOP or MAX_TOKENS (truncated)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    /// Continuation requests issued after MAX_TOKENS stops
+    #[serde(skip_serializing_if = "is_zero")]
+    pub continuations: u32,
+    /// Average token log probability, when `response_logprobs` was set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_logprobs: Option<f64>,
+    /// Prompt template id and version
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<TemplateRef>,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
 }
 
 impl ResponseMetadata {
     pub fn new(model: &str) -> Self {
         Self {
             model_used: model.to_string(),
+            fallback: false,
+            requested_model: None,
             prompt_tokens: 0,
             response_tokens: 0,
             total_tokens: 0,
+            thoughts_tokens: 0,
+            cached_tokens: 0,
+            trace: None,
+            safety_ratings: None,
+            finish_reason: None,
+            continuations: 0,
+            avg_logprobs: None,
+            template: None,
         }
     }
 
-    pub fn with_usage(model: &str, usage: &crate::gemini::types::UsageMetadata) -> Self {
+    pub fn with_usage(model: &str, usage: &UsageMetadata) -> Self {
         Self {
             model_used: model.to_string(),
+            fallback: false,
+            requested_model: None,
             prompt_tokens: usage.prompt_token_count,
             response_tokens: usage.candidates_token_count,
             total_tokens: usage.total_token_count,
+            thoughts_tokens: usage.thoughts_token_count,
+            cached_tokens: usage.cached_content_token_count,
+            trace: None,
+            safety_ratings: None,
+            finish_reason: None,
+            continuations: 0,
+            avg_logprobs: None,
+            template: None,
         }
     }
-}
 
-/// Model preference for tool requests
-#[derive(Debug, Clone, Deserialize, JsonSchema)]
-#[serde(rename_all = "lowercase")]
-pub enum ModelPreference {
-    Pro,
-    Flash,
-}
+    /// Model, usage, safety ratings and finish state of a generation
+    pub fn for_response(response: &GenerationResponse) -> Self {
+        let mut metadata = Self::with_usage(&response.model, &response.usage)
+            .with_fallback(response.fallback_from.clone())
+            .with_safety(response.safety_ratings.clone());
+        metadata.finish_reason = response.finish_reason.clone();
+        metadata.continuations = response.continuations;
+        metadata.avg_logprobs = response.avg_logprobs;
+        metadata
+    }
 
-impl Default for ModelPreference {
-    fn default() -> Self {
-        ModelPreference::Pro
+    pub fn with_trace(mut self, trace: Vec<FunctionCallRecord>) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// Records the model originally requested when a fallback answered
+    pub fn with_fallback(mut self, requested_model: Option<String>) -> Self {
+        self.fallback = requested_model.is_some();
+        self.requested_model = requested_model;
+        self
+    }
+
+    pub fn with_template(mut self, template: TemplateRef) -> Self {
+        self.template = Some(template);
+        self
+    }
+
+    pub fn with_safety(mut self, ratings: Vec<SafetyRating>) -> Self {
+        self.safety_ratings = Some(ratings).filter(|r| !r.is_empty());
+        self
     }
 }
 
 /// Generation parameters for customizing model behavior
-#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
 pub struct GenerationParams {
     #[schemars(description = "Temperature for generation (0.0-2.0)")]
     pub temperature: Option<f32>,
@@ -60,11 +151,149 @@ pub struct GenerationParams {
     #[schemars(description = "Maximum tokens in response")]
     pub max_tokens: Option<u32>,
 
-    #[schemars(description = "Top-p sampling parameter")]
+    #[schemars(description = "Top-p sampling parameter (0.0-1.0)")]
     pub top_p: Option<f32>,
 
     #[schemars(description = "Top-k sampling parameter")]
     pub top_k: Option<u32>,
+
+    #[schemars(description = "Up to 5 strings that stop generation")]
+    #[serde(default)]
+    pub stop_sequences: Option<Vec<String>>,
+
+    #[schemars(description = "Penalty for tokens already present in the output (-2.0 to 2.0)")]
+    #[serde(default)]
+    pub presence_penalty: Option<f32>,
+
+    #[schemars(description = "Penalty scaled by how often a token has appeared (-2.0 to 2.0)")]
+    #[serde(default)]
+    pub frequency_penalty: Option<f32>,
+
+    #[schemars(description = "Seed for best-effort deterministic sampling")]
+    #[serde(default)]
+    pub seed: Option<i32>,
+
+    #[schemars(description = "Number of candidates to generate (1-8); tools use the first")]
+    #[serde(default)]
+    pub candidate_count: Option<u32>,
+
+    #[schemars(description = "Report the average token log probability in metadata")]
+    #[serde(default)]
+    pub response_logprobs: Option<bool>,
+
+    #[schemars(description = "Thinking token budget: 0 disables thinking, -1 lets the model decide (max 32768)")]
+    #[serde(default)]
+    pub thinking_budget: Option<i32>,
+
+    #[schemars(description = "Ask the model to return summaries of its thoughts")]
+    #[serde(default)]
+    pub include_thoughts: Option<bool>,
+
+    #[schemars(description = "Continuation requests allowed when the response hits max_tokens (0-5)")]
+    #[serde(default)]
+    pub max_continuations: Option<u32>,
+
+    #[schemars(description = "Safety thresholds overriding the server defaults per category")]
+    #[serde(default)]
+    pub safety: Option<Vec<SafetySetting>>,
+}
+
+const MAX_STOP_SEQUENCES: usize = 5;
+const MAX_CANDIDATE_COUNT: u32 = 8;
+const MAX_THINKING_BUDGET: i32 = 32768;
+
+impl GenerationParams {
+    /// Tool defaults with the caller's params applied on top, after range checks
+    pub fn config(
+        params: Option<&GenerationParams>,
+        defaults: GenerationConfig,
+    ) -> anyhow::Result<GenerationConfig> {
+        let Some(params) = params else {
+            return Ok(defaults);
+        };
+        params.validate()?;
+
+        let thinking_config = if params.thinking_budget.is_some() || params.include_thoughts.is_some() {
+            let defaults = defaults.thinking_config.unwrap_or_default();
+            Some(ThinkingConfig {
+                thinking_budget: params.thinking_budget.or(defaults.thinking_budget),
+                include_thoughts: params.include_thoughts.or(defaults.include_thoughts),
+            })
+        } else {
+            defaults.thinking_config
+        };
+
+        Ok(GenerationConfig {
+            temperature: params.temperature.or(defaults.temperature),
+            max_output_tokens: params.max_tokens.or(defaults.max_output_tokens),
+            top_p: params.top_p.or(defaults.top_p),
+            top_k: params.top_k.or(defaults.top_k),
+            candidate_count: params.candidate_count.or(defaults.candidate_count),
+            stop_sequences: params.stop_sequences.clone().or(defaults.stop_sequences),
+            presence_penalty: params.presence_penalty.or(defaults.presence_penalty),
+            frequency_penalty: params.frequency_penalty.or(defaults.frequency_penalty),
+            seed: params.seed.or(defaults.seed),
+            response_logprobs: params.response_logprobs.or(defaults.response_logprobs),
+            thinking_config,
+        })
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        check_range("temperature", self.temperature, 0.0..=2.0)?;
+        check_range("top_p", self.top_p, 0.0..=1.0)?;
+        check_range("top_k", self.top_k, 1..=u32::MAX)?;
+        check_range("max_tokens", self.max_tokens, 1..=u32::MAX)?;
+        check_range("candidate_count", self.candidate_count, 1..=MAX_CANDIDATE_COUNT)?;
+        check_range("max_continuations", self.max_continuations, 0..=MAX_CONTINUATIONS)?;
+        check_range("thinking_budget", self.thinking_budget, -1..=MAX_THINKING_BUDGET)?;
+
+        // The API accepts penalties in [-2.0, 2.0)
+        for (name, value) in [
+            ("presence_penalty", self.presence_penalty),
+            ("frequency_penalty", self.frequency_penalty),
+        ] {
+            if value.is_some_and(|v| !(-2.0..2.0).contains(&v)) {
+                anyhow::bail!(InvalidInput(format!("params.{} must be at least -2.0 and below 2.0", name)));
+            }
+        }
+
+        if let Some(stops) = &self.stop_sequences {
+            if stops.len() > MAX_STOP_SEQUENCES {
+                anyhow::bail!(InvalidInput(format!("params.stop_sequences allows at most {} entries", MAX_STOP_SEQUENCES)));
+            }
+            if stops.iter().any(|s| s.is_empty()) {
+                anyhow::bail!(InvalidInput("params.stop_sequences cannot contain empty strings".to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn max_continuations(params: Option<&GenerationParams>) -> Option<u32> {
+        params.and_then(|p| p.max_continuations)
+    }
+
+    /// Per-call safety overrides from optional params
+    pub fn safety_overrides(params: Option<&GenerationParams>) -> &[SafetySetting] {
+        params.and_then(|p| p.safety.as_deref()).unwrap_or_default()
+    }
+}
+
+fn check_range<T: PartialOrd + Display>(
+    name: &str,
+    value: Option<T>,
+    range: RangeInclusive<T>,
+) -> anyhow::Result<()> {
+    match value {
+        Some(v) if !range.contains(&v) => anyhow::bail!(InvalidInput(format!(
+            "params.{} must be between {} and {} (got {})",
+            name,
+            range.start(),
+            range.end(),
+            v
+        ))),
+        _ => Ok(()),
+    }
 }
 
 #[cfg(test)]
@@ -78,22 +307,6 @@ mod tests {
         assert_eq!(meta.total_tokens, 0);
     }
 
-    #[test]
-    fn test_model_preference_default() {
-        let pref = ModelPreference::default();
-        matches!(pref, ModelPreference::Pro);
-    }
-
-    #[test]
-    fn test_model_preference_deserialize() {
-        let json = r#""pro""#;
-        let pref: ModelPreference = serde_json::from_str(json).unwrap();
-        matches!(pref, ModelPreference::Pro);
-
-        let json2 = r#""flash""#;
-        let pref2: ModelPreference = serde_json::from_str(json2).unwrap();
-        matches!(pref2, ModelPreference::Flash);
-    }
 
     #[test]
     fn test_generation_params_deserialize() {
@@ -103,12 +316,111 @@ mod tests {
         assert_eq!(params.max_tokens, Some(1024));
         assert_eq!(params.top_p, None);
         assert_eq!(params.top_k, None);
+        assert!(GenerationParams::safety_overrides(Some(&params)).is_empty());
+    }
+
+    #[test]
+    fn test_generation_params_config_overrides_defaults() {
+        let json = r#"{
+            "temperature": 0.1,
+            "stop_sequences": ["END"],
+            "presence_penalty": 0.5,
+            "seed": 42,
+            "thinking_budget": 0
+        }"#;
+        let params: GenerationParams = serde_json::from_str(json).unwrap();
+        let defaults = GenerationConfig {
+            temperature: Some(0.9),
+            max_output_tokens: Some(2048),
+            ..Default::default()
+        };
+
+        let config = GenerationParams::config(Some(&params), defaults).unwrap();
+        assert_eq!(config.temperature, Some(0.1));
+        assert_eq!(config.max_output_tokens, Some(2048));
+        assert_eq!(config.seed, Some(42));
+
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["stop_sequences"], serde_json::json!(["END"]));
+        assert_eq!(json["thinking_config"], serde_json::json!({"thinking_budget": 0}));
+        assert!(json.get("frequency_penalty").is_none());
+    }
+
+    #[test]
+    fn test_generation_params_validation() {
+        let invalid = [
+            r#"{"temperature": 2.5}"#,
+            r#"{"top_p": 1.5}"#,
+            r#"{"top_k": 0}"#,
+            r#"{"candidate_count": 9}"#,
+            r#"{"presence_penalty": 2.0}"#,
+            r#"{"frequency_penalty": -3.0}"#,
+            r#"{"thinking_budget": -2}"#,
+            r#"{"max_continuations": 6}"#,
+            r#"{"stop_sequences": ["a", "b", "c", "d", "e", "f"]}"#,
+            r#"{"stop_sequences": [""]}"#,
+        ];
+        for json in invalid {
+            let params: GenerationParams = serde_json::from_str(json).unwrap();
+            assert!(params.validate().is_err(), "{} should be rejected", json);
+        }
+
+        let err = serde_json::from_str::<GenerationParams>(r#"{"temperature": 3.0}"#)
+            .unwrap()
+            .validate()
+            .unwrap_err();
+        assert!(err.to_string().contains("params.temperature must be between 0 and 2"));
+
+        let valid: GenerationParams =
+            serde_json::from_str(r#"{"thinking_budget": -1, "frequency_penalty": -2.0, "candidate_count": 8}"#)
+                .unwrap();
+        assert!(valid.validate().is_ok());
+    }
+
+    #[test]
+    fn test_generation_params_safety() {
+        let json = r#"{"safety": [{"category": "dangerous_content", "threshold": "block_only_high"}]}"#;
+        let params: GenerationParams = serde_json::from_str(json).unwrap();
+        let safety = GenerationParams::safety_overrides(Some(&params));
+        assert_eq!(safety.len(), 1);
+        assert_eq!(
+            serde_json::to_value(&safety[0]).unwrap()["category"],
+            "HARM_CATEGORY_DANGEROUS_CONTENT"
+        );
+    }
+
+    #[test]
+    fn test_metadata_for_truncated_response() {
+        let response = GenerationResponse {
+            text: "partial".to_string(),
+            candidates: vec!["partial".to_string()],
+            usage: UsageMetadata::default(),
+            safety_ratings: vec![],
+            finish_reason: Some("MAX_TOKENS".to_string()),
+            continuations: 2,
+            avg_logprobs: None,
+            thoughts: None,
+            model: "gemini-flash".to_string(),
+            fallback_from: None,
+        };
+
+        let json = serde_json::to_value(ResponseMetadata::for_response(&response)).unwrap();
+        assert_eq!(json["model_used"], "gemini-flash");
+        assert_eq!(json["fallback"], false);
+        assert!(json.get("requested_model").is_none());
+        assert_eq!(json["finish_reason"], "MAX_TOKENS");
+        assert_eq!(json["continuations"], 2);
+        assert!(json.get("safety_ratings").is_none());
+
+        let json = serde_json::to_value(ResponseMetadata::new("gemini-flash")).unwrap();
+        assert!(json.get("continuations").is_none());
     }
 
     #[test]
     fn test_tool_response_serialize() {
         let response = ToolResponse {
             result: "test result".to_string(),
+            thoughts: None,
             metadata: ResponseMetadata::new("gemini-flash"),
         };
 
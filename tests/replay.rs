@@ -0,0 +1,88 @@
+// Parser regression tests against cassettes of model output
+//
+// The cassettes in tests/cassettes are synthetic: the requests are the
+// tools' real requests, captured with `--record` against a stub, and the
+// responses are hand-written in the formats Gemini answers with (their
+// token counts are made up). The tools are run with the same input, the
+// client answers from the cassette, and the parsed result is pinned. A
+// prompt or template change alters the request hash, so the cassette has
+// to be recorded again. Cassettes recorded against the API can be dropped
+// in next to them.
+
+use gemini_mcp::gemini::cassette::Cassette;
+use gemini_mcp::tools::analyze::{self, AnalyzeInput, AnalyzerType};
+use gemini_mcp::tools::brainstorm::{self, BrainstormInput};
+use gemini_mcp::{Config, GeminiClient, KeyPool, ToolSettings};
+use serde_json::{json, Value};
+use std::path::Path;
+use std::sync::Arc;
+
+fn replaying(cassette: &str) -> (Arc<GeminiClient>, ToolSettings) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/cassettes").join(cassette);
+    let config = Config::builtin();
+    let client = GeminiClient::from_config(KeyPool::single("replay".to_string()).unwrap(), &config)
+        .unwrap()
+        .with_cassette(Cassette::replay(path).unwrap());
+    (Arc::new(client), ToolSettings::from_config(&config).unwrap())
+}
+
+fn result_json<T: serde::Serialize>(result: &T) -> Value {
+    serde_json::to_value(result).unwrap()
+}
+
+#[tokio::test]
+async fn test_brainstorm_parses_markdown_ideas() {
+    let (client, settings) = replaying("brainstorm.json");
+    let input = BrainstormInput {
+        num_ideas: 4,
+        ..BrainstormInput::new("Names for a Rust HTTP client library")
+    };
+
+    let response = brainstorm::execute_v2(input, client, &settings).await.unwrap();
+
+    let ideas: Vec<String> = response.result.ideas.iter().map(|idea| idea.text.clone()).collect();
+    assert_eq!(
+        ideas,
+        [
+            // Wrapped lines are joined onto their idea
+            "**Ferrous Fetch** - a nod to iron oxide and to fetching resources over the network.",
+            "**Crabwire** - combines Ferris the crab with wire-level networking.",
+            "**Hyperlink** - plays on hyper and on the web's link concept.",
+            "**Rustle** - short, memorable and evokes quick network requests.",
+        ]
+    );
+    assert_eq!(result_json(&response.result)["ideas"][3]["id"], 4);
+}
+
+#[tokio::test]
+async fn test_analyze_code_extracts_review_fields() {
+    let (client, settings) = replaying("analyze_code.json");
+    let input = AnalyzeInput::new(
+        "fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n",
+        AnalyzerType::Code {
+            language: Some("rust".to_string()),
+        },
+    );
+
+    let response = analyze::execute_v2(input, client, &settings).await.unwrap();
+
+    assert_eq!(
+        result_json(&response.result),
+        json!({
+            "type": "code",
+            "quality_score": 9.0,
+            "complexity": "low",
+            "issues": [{
+                "severity": "medium",
+                "category": "general",
+                "description": "the public function has no documentation comment",
+                "location": null,
+            }],
+            "patterns": ["pure function with no side effects"],
+            "suggestions": [
+                "document the overflow behaviour",
+                "consider checked_add for untrusted input",
+            ],
+        })
+    );
+}
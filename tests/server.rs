@@ -0,0 +1,155 @@
+// Offline end-to-end tests: JSON-RPC request -> tool -> parse -> response,
+// with Gemini replaced by a scripted MockBackend
+#![cfg(feature = "server")]
+
+use gemini_mcp::error::GeminiError;
+use gemini_mcp::gemini::mock::MockBackend;
+use gemini_mcp::mcp::ShutdownReason;
+use gemini_mcp::tools::sandbox::Sandbox;
+use gemini_mcp::McpGeminiServer;
+use serde_json::{json, Value};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, BufReader};
+
+/// Sends `initialize` and one `tools/call`, returning the call's response
+async fn call_tool(backend: &Arc<MockBackend>, sandbox: Option<Sandbox>, tool: &str, arguments: Value) -> Value {
+    let server = McpGeminiServer::builder()
+        .backend(backend.clone())
+        .sandbox(sandbox)
+        .build()
+        .unwrap();
+    let input = format!(
+        "{}\n{}\n",
+        json!({"jsonrpc": "2.0", "id": 1, "method": "initialize"}),
+        json!({"jsonrpc": "2.0", "id": 2, "method": "tools/call", "params": {"name": tool, "arguments": arguments}}),
+    );
+
+    let (mut output, writer) = tokio::io::duplex(1 << 20);
+    let outcome = server
+        .serve(BufReader::new(input.as_bytes()), writer, std::future::pending())
+        .await
+        .unwrap();
+    assert_eq!(outcome.reason, ShutdownReason::Eof);
+
+    let mut text = String::new();
+    output.read_to_string(&mut text).await.unwrap();
+    text.lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .find(|response| response["id"] == 2)
+        .expect("no response to tools/call")
+}
+
+/// The JSON document a V2 tool returns as text content
+fn structured(response: &Value) -> Value {
+    let text = response["result"]["content"][0]["text"].as_str().expect("no text content");
+    serde_json::from_str(text).unwrap()
+}
+
+#[tokio::test]
+async fn test_summarize_v2() {
+    let backend = Arc::new(MockBackend::new().with_reply("Rust offers memory safety without a garbage collector."));
+
+    let response = call_tool(
+        &backend,
+        None,
+        "gemini-summarize-v2",
+        json!({"content": "Rust is a systems programming language focused on safety.", "length": "brief"}),
+    )
+    .await;
+    let body = structured(&response);
+
+    assert_eq!(body["result"]["summary"], "Rust offers memory safety without a garbage collector.");
+    assert_eq!(body["result"]["word_count"], 8);
+    assert_eq!(body["metadata"]["template"]["id"], "summarize");
+
+    let requests = backend.requests();
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].prompt().contains("Rust is a systems programming language"));
+    assert_eq!(body["metadata"]["model_used"], requests[0].model);
+}
+
+#[tokio::test]
+async fn test_analyze_v2_parses_code_review() {
+    let backend = Arc::new(MockBackend::new().with_reply(
+        "Quality score: 8/10\nComplexity: low\n- Suggestion: add documentation comments",
+    ));
+
+    let response = call_tool(
+        &backend,
+        None,
+        "gemini-analyze-v2",
+        json!({"content": "fn main() {}", "analyzer_type": {"type": "code", "params": {"language": "rust"}}}),
+    )
+    .await;
+    let body = structured(&response);
+
+    assert_eq!(body["result"]["type"], "code");
+    assert_eq!(body["result"]["quality_score"], 8.0);
+    assert_eq!(body["result"]["complexity"], "low");
+    assert!(backend.requests()[0].prompt().contains("fn main() {}"));
+}
+
+#[tokio::test]
+async fn test_v1_query_returns_text() {
+    let backend = Arc::new(MockBackend::new().with_reply("Forty-two."));
+
+    let response = call_tool(&backend, None, "gemini-query", json!({"prompt": "What is the answer?"})).await;
+
+    assert_eq!(response["result"]["content"][0]["text"], "Forty-two.");
+    assert_eq!(backend.requests()[0].prompt(), "What is the answer?");
+}
+
+#[tokio::test]
+async fn test_agent_runs_sandboxed_functions() {
+    let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+    let lib = src.join("lib.rs").canonicalize().unwrap();
+    let backend = Arc::new(
+        MockBackend::new()
+            .with_function_call("read_file", json!({"path": lib.display().to_string(), "max_lines": 3}))
+            .with_reply(format!("The library root is `{}:1`.", lib.display())),
+    );
+
+    let response = call_tool(
+        &backend,
+        Some(Sandbox::new(&[src]).unwrap()),
+        "gemini-agent",
+        json!({"task": "Where is the library root?"}),
+    )
+    .await;
+    let body = structured(&response);
+
+    assert_eq!(body["result"]["steps"], 2);
+    assert_eq!(body["result"]["function_calls"], 1);
+    assert_eq!(body["result"]["stop_reason"], "completed");
+    assert_eq!(body["result"]["citations"][0]["line"], 1);
+    assert_eq!(body["metadata"]["trace"][0]["name"], "read_file");
+    assert!(body["metadata"]["trace"][0]["response"]["content"]
+        .as_str()
+        .unwrap()
+        .contains("    1| "));
+}
+
+#[tokio::test]
+async fn test_backend_errors_become_rpc_errors() {
+    let backend = Arc::new(MockBackend::new().with_error(GeminiError::ApiError {
+        status: 429,
+        message: "Resource exhausted".to_string(),
+    }));
+
+    let response = call_tool(&backend, None, "gemini-brainstorm-v2", json!({"prompt": "Names for a cat"})).await;
+
+    assert_eq!(response["error"]["code"], -32603);
+    assert!(response["error"]["message"].as_str().unwrap().contains("429"));
+    assert_eq!(backend.remaining(), 0);
+}
+
+#[tokio::test]
+async fn test_invalid_input_never_reaches_the_backend() {
+    let backend = Arc::new(MockBackend::new());
+
+    let response = call_tool(&backend, None, "gemini-brainstorm-v2", json!({"prompt": "  "})).await;
+
+    assert!(response["error"].is_object());
+    assert!(backend.requests().is_empty());
+}